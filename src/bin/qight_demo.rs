@@ -7,6 +7,14 @@ use tokio::sync::oneshot;
 use mdns_sd::ScopedIp;
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Library diagnostics go to stderr; results are printed below.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
     let (tx, rx) = oneshot::channel::<SocketAddr>();
 let mdns = ServiceDaemon::new().expect("Failed to create daemon");
 let service_type = "_qight._udp.local.";
@@ -14,20 +22,17 @@ let receiver = mdns.browse(service_type).expect("Failed to browse");
 
 tokio::spawn(async move {
     while let Ok(event) = receiver.recv_async().await {
-        match event {
-            ServiceEvent::ServiceResolved(resolved) => {
-                if let Some(scoped) = resolved.get_addresses().iter().next() {
-                    let ip: IpAddr = match scoped {
-                        ScopedIp::V4(v4) => IpAddr::V4(*v4.addr()),
-                        ScopedIp::V6(v6) => IpAddr::V6(*v6.addr()),
-                        _ => continue,
-                    };
-                    let addr = SocketAddr::new(ip, resolved.get_port());
-                    let _ = tx.send(addr);
-                    break;
-                }
+        if let ServiceEvent::ServiceResolved(resolved) = event {
+            if let Some(scoped) = resolved.get_addresses().iter().next() {
+                let ip: IpAddr = match scoped {
+                    ScopedIp::V4(v4) => IpAddr::V4(*v4.addr()),
+                    ScopedIp::V6(v6) => IpAddr::V6(*v6.addr()),
+                    _ => continue,
+                };
+                let addr = SocketAddr::new(ip, resolved.get_port());
+                let _ = tx.send(addr);
+                break;
             }
-            _ => {}
         }
     }
 });
@@ -41,7 +46,7 @@ let addr = tokio::time::timeout(
     let client = qight::RelayClient::connect(addr).await?;
     client.hello("test-client-123").await?;
 
-    let (recipient_key, _) = gen_keypair();
+    let (recipient_key, recipient_priv) = gen_keypair();
     let (sender_pub, sender_priv) = gen_keypair();
 
     let mut envelope = qight::MessageEnvelope::new(
@@ -54,7 +59,7 @@ let addr = tokio::time::timeout(
     envelope.sign(&sender_priv);
     client.send(&envelope).await?;
 
-    let messages = client.fetch(&hex::encode(recipient_key)).await?;
+    let messages = client.fetch_verified(&recipient_priv).await?;
 
     println!("Fetched {} message(s):", messages.len());
     for msg in messages {
@@ -0,0 +1,303 @@
+use quinn::{ClientConfig as QuinnClientConfig, Endpoint};
+use quinn_proto::crypto::rustls::QuicClientConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::{ClientConfig as RustlsClientConfig, RootCertStore};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use std::time::Duration;
+
+use crate::client::{
+    ClientLimits, HelloParams, Outbox, ReconnectPolicy, RelayClient, DEFAULT_OUTBOX_POOL_SIZE,
+    KEEP_ALIVE_INTERVAL,
+};
+use crate::errors::{QightError, Result};
+use crate::protocol::Features;
+
+/// Default for [`RelayClientBuilder::send_retries`].
+pub const DEFAULT_SEND_RETRIES: u32 = 2;
+
+/// Certificates a [`RelayClient`] trusts when verifying the relay.
+#[derive(Clone, Debug)]
+pub enum TrustRoots {
+    /// A certificate file on disk, DER or PEM encoded.
+    CertFile(PathBuf),
+    /// One or more PEM encoded certificates.
+    Pem(Vec<u8>),
+    /// A DER encoded certificate, e.g. [`crate::relay::RelayServer::certificate`].
+    Der(Vec<u8>),
+    /// The Mozilla root store bundled through `webpki-roots`.
+    System,
+}
+
+/// Where a [`RelayClient`] queues messages that could not be delivered.
+#[derive(Clone, Debug)]
+pub enum OutboxLocation {
+    /// A SQLite database file.
+    File(PathBuf),
+    /// A private in-memory database, lost when the client is dropped.
+    Memory,
+    /// An outbox opened by the caller, e.g. to share it or inspect dead letters.
+    Custom(Outbox),
+    /// No queuing; failed sends are only reported to the caller.
+    Disabled,
+}
+
+/// Builder for [`RelayClient`].
+///
+/// ```no_run
+/// # async fn demo() -> anyhow::Result<()> {
+/// let client = qight::RelayClient::builder("127.0.0.1:4433".parse()?)
+///     .trust(qight::TrustRoots::CertFile("server_cert".into()))
+///     .server_name("localhost")
+///     .outbox_path("alice_outbox.db")
+///     .connect()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RelayClientBuilder {
+    server_addr: SocketAddr,
+    roots: Vec<TrustRoots>,
+    server_name: String,
+    outbox: OutboxLocation,
+    outbox_pool_size: u32,
+    limits: ClientLimits,
+    reconnect: Option<ReconnectPolicy>,
+    hello: HelloParams,
+}
+
+impl RelayClientBuilder {
+    pub fn new(server_addr: SocketAddr) -> Self {
+        Self {
+            server_addr,
+            roots: Vec::new(),
+            server_name: "localhost".to_string(),
+            outbox: OutboxLocation::File(PathBuf::from("qight_outbox.db")),
+            outbox_pool_size: DEFAULT_OUTBOX_POOL_SIZE,
+            limits: ClientLimits::default(),
+            reconnect: Some(ReconnectPolicy::default()),
+            hello: HelloParams::default(),
+        }
+    }
+
+    /// Adds trusted roots. Defaults to `TrustRoots::CertFile("server_cert")`
+    /// when none are given.
+    pub fn trust(mut self, roots: TrustRoots) -> Self {
+        self.roots.push(roots);
+        self
+    }
+
+    /// TLS server name (SNI) the relay certificate must be valid for.
+    /// Defaults to `localhost`.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = name.into();
+        self
+    }
+
+    /// Queues undelivered messages in the SQLite file at `path`. Defaults to
+    /// `qight_outbox.db`; clients in one process should use distinct paths.
+    pub fn outbox_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.outbox = OutboxLocation::File(path.into());
+        self
+    }
+
+    /// Queues undelivered messages in memory only.
+    pub fn in_memory_outbox(mut self) -> Self {
+        self.outbox = OutboxLocation::Memory;
+        self
+    }
+
+    /// Queues undelivered messages in an outbox opened by the caller.
+    pub fn outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = OutboxLocation::Custom(outbox);
+        self
+    }
+
+    /// Disables the outbox.
+    pub fn no_outbox(mut self) -> Self {
+        self.outbox = OutboxLocation::Disabled;
+        self
+    }
+
+    /// Pooled SQLite connections for a file outbox.
+    pub fn outbox_pool_size(mut self, size: u32) -> Self {
+        self.outbox_pool_size = size;
+        self
+    }
+
+    /// Largest single message accepted from the relay, in bytes.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.limits.max_message_size = bytes;
+        self
+    }
+
+    /// How often [`RelayClient::send`] resends an envelope whose reply was
+    /// lost, if the relay supports [`Features::IDEMPOTENT_SEND`]. Defaults
+    /// to [`DEFAULT_SEND_RETRIES`]; 0 disables resending.
+    pub fn send_retries(mut self, retries: u32) -> Self {
+        self.limits.send_retries = retries;
+        self
+    }
+
+    /// Whether to reconnect in the background after the connection is lost or
+    /// the relay was unreachable at startup. Enabled by default.
+    pub fn reconnect(mut self, enabled: bool) -> Self {
+        self.reconnect = enabled.then(|| self.reconnect.unwrap_or_default());
+        self
+    }
+
+    /// Delay between reconnection attempts, doubling from `initial` up to
+    /// `max`. Defaults to 500 ms and 30 s. Enables reconnection.
+    pub fn reconnect_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect = Some(ReconnectPolicy { initial, max });
+        self
+    }
+
+    /// Client id sent in HELLO. Defaults to `qight-client`.
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.hello.client_id = client_id.into();
+        self
+    }
+
+    /// Refuses relays that do not support all of `features`. Optional
+    /// features the relay lacks are worked around instead; see
+    /// [`RelayClient::capabilities`].
+    pub fn require_features(mut self, features: Features) -> Self {
+        self.hello.required = self.hello.required | features;
+        self
+    }
+
+    /// Pins the relay identity key, e.g. [`crate::relay::RelayServer::identity_key`].
+    /// Relays that cannot prove possession of it are refused.
+    pub fn relay_key(mut self, key: [u8; PUBLIC_KEY_LENGTH]) -> Self {
+        self.hello.relay_key = Some(key);
+        self
+    }
+
+    /// Opens the outbox and connects. An unreachable relay is not an error;
+    /// the client starts in offline mode and keeps trying to reconnect. A
+    /// relay that fails the HELLO negotiation is.
+    pub async fn connect(self) -> Result<RelayClient> {
+        let outbox = match self.outbox {
+            OutboxLocation::File(path) => {
+                Some(Outbox::open_with_pool_size(path, self.outbox_pool_size)?)
+            }
+            OutboxLocation::Memory => Some(Outbox::in_memory()?),
+            OutboxLocation::Custom(outbox) => Some(outbox),
+            OutboxLocation::Disabled => None,
+        };
+
+        let roots = if self.roots.is_empty() {
+            vec![TrustRoots::CertFile(PathBuf::from("server_cert"))]
+        } else {
+            self.roots
+        };
+        let root_store = build_root_store(&roots)?;
+
+        let mut rustls_config = RustlsClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        rustls_config.alpn_protocols = vec![b"qight".to_vec()];
+
+        let quic_crypto = QuicClientConfig::try_from(rustls_config)
+            .map_err(|e| QightError::Tls(e.to_string()))?;
+
+        let bind = if self.server_addr.is_ipv6() {
+            SocketAddr::from(([0u16; 8], 0))
+        } else {
+            SocketAddr::from(([0u8; 4], 0))
+        };
+        let mut transport = quinn::TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        let mut client_config = QuinnClientConfig::new(Arc::new(quic_crypto));
+        client_config.transport_config(Arc::new(transport));
+
+        let mut endpoint =
+            Endpoint::client(bind).map_err(|e| QightError::ConnectFailed(e.to_string()))?;
+        endpoint.set_default_client_config(client_config);
+
+        RelayClient::open(
+            endpoint,
+            self.server_addr,
+            &self.server_name,
+            outbox,
+            self.limits,
+            self.reconnect,
+            self.hello,
+        )
+        .await
+    }
+}
+
+fn build_root_store(roots: &[TrustRoots]) -> Result<RootCertStore> {
+    let mut store = RootCertStore::empty();
+    for root in roots {
+        match root {
+            TrustRoots::CertFile(path) => {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    QightError::Tls(format!("cannot read certificate {}: {}", path.display(), e))
+                })?;
+                if bytes.starts_with(b"-----BEGIN") {
+                    add_pem(&mut store, &bytes)?;
+                } else {
+                    store.add(CertificateDer::from(bytes))?;
+                }
+            }
+            TrustRoots::Pem(pem) => add_pem(&mut store, pem)?,
+            TrustRoots::Der(der) => store.add(CertificateDer::from(der.clone()))?,
+            TrustRoots::System => store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+    }
+    Ok(store)
+}
+
+fn add_pem(store: &mut RootCertStore, pem: &[u8]) -> Result<()> {
+    let mut added = 0;
+    for cert in CertificateDer::pem_slice_iter(pem) {
+        let cert = cert.map_err(|e| QightError::Tls(format!("invalid PEM certificate: {}", e)))?;
+        store.add(cert)?;
+        added += 1;
+    }
+    if added == 0 {
+        return Err(QightError::Tls("no certificates found in PEM input".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn self_signed() -> rcgen::CertifiedKey<rcgen::KeyPair> {
+        rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap()
+    }
+
+    #[test]
+    fn test_root_store_accepts_der_and_pem() {
+        let first = self_signed();
+        let second = self_signed();
+        let pem = format!("{}{}", first.cert.pem(), second.cert.pem());
+
+        let store = build_root_store(&[
+            TrustRoots::Der(first.cert.der().to_vec()),
+            TrustRoots::Pem(pem.into_bytes()),
+        ])
+        .unwrap();
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn test_root_store_system_roots() {
+        let store = build_root_store(&[TrustRoots::System]).unwrap();
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn test_root_store_rejects_empty_pem() {
+        assert!(build_root_store(&[TrustRoots::Pem(b"not a certificate".to_vec())]).is_err());
+    }
+}
@@ -1,260 +1,1109 @@
-use anyhow::{Context, Result};
-use quinn::{ClientConfig as QuinnClientConfig, Endpoint};
-use quinn_proto::crypto::rustls::QuicClientConfig;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rustls::pki_types::CertificateDer;
-use rustls::{ClientConfig as RustlsClientConfig, RootCertStore};
-use std::fs::read;
+use quinn::Endpoint;
 use std::net::SocketAddr;
-use std::result::Result::Ok;
-use std::sync::Arc;
-use std::usize;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, warn, Instrument};
 
-use crate::MessageEnvelope;
+use crate::client::{
+    connect_once, ClientLimits, ConnectionState, HelloParams, Outbox, ReceiptStream,
+    ReceiptWatcher, ReconnectPolicy, RelayClientBuilder, Subscriber, Subscription,
+    OUTBOX_RETRY_INTERVAL, SUBSCRIPTION_BUFFER,
+};
+use crate::errors::{QightError, Result};
+use crate::protocol::{
+    error_from_code, read_frame, write_frame, Capabilities, CloseCode, ErrorCode, Features, Frame,
+    Receipt, ReceiptStatus, RelayStats, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::{
+    challenge_message, key_fingerprint, public_key_from_secret, relay_identity_message,
+    sign_message, verify_message, MessageEnvelope, MessageId, CHALLENGE_EXPORTER_LABEL,
+    RELAY_IDENTITY_EXPORTER_LABEL,
+};
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
 
+/// Largest response payload expected for requests other than FETCH.
+const MAX_CONTROL_PAYLOAD: usize = 64 * 1024;
+
+/// Visibility timeout [`RelayClient::fetch`] requests before acknowledging.
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(30);
+
+/// How long a resent SEND waits for a lost connection to be replaced.
+const SEND_RETRY_WAIT: Duration = Duration::from_secs(5);
+
+/// Outcome of [`RelayClient::drain_queue`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Messages the relay accepted.
+    pub delivered: usize,
+    /// Messages the relay rejected; moved to the dead-letter state.
+    pub dead_lettered: usize,
+    /// Messages that failed in transit and were rescheduled with backoff.
+    pub deferred: usize,
+    /// Messages dropped because their TTL passed while queued.
+    pub expired: usize,
+}
+
+/// How [`RelayClient::send`] disposed of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendStatus {
+    /// The relay accepted the message.
+    Delivered,
+    /// The relay could not be reached; the message waits in the outbox.
+    Queued,
+}
+
+/// Relay verdict on a single SEND.
+enum SendOutcome {
+    Accepted,
+    Rejected { code: u16, message: String },
+}
+
+/// Client for a qight relay.
+///
+/// Cloning is cheap; clones share one connection, outbox and state. Unless
+/// reconnection is disabled on the builder, a background task re-establishes
+/// lost connections with backoff and drains the outbox once back online.
+///
+/// Every connection starts with a HELLO exchange that settles the protocol
+/// version, the optional features both sides support and the relay's limits,
+/// and verifies the relay identity key; see [`RelayClient::capabilities`].
 #[derive(Clone)]
 pub struct RelayClient {
-    connection: Option<quinn::Connection>,
-    outbox: Pool<SqliteConnectionManager>,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    endpoint: Endpoint,
+    server_addr: SocketAddr,
+    server_name: String,
+    connection: Mutex<Option<quinn::Connection>>,
+    state: watch::Sender<ConnectionState>,
+    outbox: Option<Outbox>,
+    limits: ClientLimits,
+    reconnects: bool,
+    /// Serializes outbox replays so an entry is never sent twice at once.
+    drain_lock: tokio::sync::Mutex<()>,
+    subscribers: Mutex<Vec<Subscriber>>,
+    receipt_watchers: Mutex<Vec<ReceiptWatcher>>,
+    shutdown: watch::Sender<bool>,
+    hello: HelloParams,
+    /// Outcome of the last successful HELLO; kept while offline.
+    capabilities: Mutex<Option<Capabilities>>,
+}
+
+impl Inner {
+    fn set_connection(&self, connection: Option<quinn::Connection>) {
+        let state = if connection.is_some() {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Disconnected
+        };
+        *self.connection.lock().unwrap() = connection;
+        self.set_state(state);
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.state.send_if_modified(|current| {
+            // Closed is final.
+            if *current == state || *current == ConnectionState::Closed {
+                return false;
+            }
+            *current = state;
+            true
+        });
+    }
 }
+
 impl RelayClient {
+    /// Connects with the default settings of [`RelayClientBuilder`]: trust
+    /// `server_cert` from the working directory, SNI `localhost` and an
+    /// outbox at `qight_outbox.db`.
     pub async fn connect(server_addr: SocketAddr) -> Result<Self> {
-        let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
-
-        let manager = SqliteConnectionManager::file("qight_outbox.db");
-        let outbox = Pool::builder().max_size(5).build(manager)?;
-        let conn = outbox.get()?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS outbox (
-        msg_id      BLOB PRIMARY KEY,  
-        sender      TEXT NOT NULL,
-        sender_key  BLOB NOT NULL,    
-        recipient   BLOB NOT NULL,    
-        timestamp   INTEGER NOT NULL,
-        ttl         INTEGER NOT NULL,
-        payload     BLOB NOT NULL
-    )",
-    (),
-        )?;
-
-        let mut roots = RootCertStore::empty();
-        let read_cert = read("server_cert")?;
-        roots.add(CertificateDer::from(read_cert))?;
-
-        let mut rustls_config = RustlsClientConfig::builder()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        rustls_config.alpn_protocols = vec![b"qight".to_vec()];
-
-        let quic_crypto =
-            QuicClientConfig::try_from(rustls_config).context("invalid rustls config")?;
-
-        let client_config = QuinnClientConfig::new(Arc::new(quic_crypto));
-
-        endpoint.set_default_client_config(client_config);
-
-        let connection = match endpoint.connect(server_addr, "localhost") {
-            Ok(connecting) => match connecting.await {
-                Ok(conn) => {
-                    println!(
-                        "Connected via QUIC to {} (peer: {})",
-                        server_addr,
-                        conn.remote_address()
-                    );
-                    Some(conn)
-                }
-                Err(e) => {
-                    eprintln!("Relay unreachable: {}. Running in offline mode.", e);
-                    None
+        RelayClientBuilder::new(server_addr).connect().await
+    }
+
+    pub fn builder(server_addr: SocketAddr) -> RelayClientBuilder {
+        RelayClientBuilder::new(server_addr)
+    }
+
+    pub(crate) async fn open(
+        endpoint: Endpoint,
+        server_addr: SocketAddr,
+        server_name: &str,
+        outbox: Option<Outbox>,
+        limits: ClientLimits,
+        reconnect: Option<ReconnectPolicy>,
+        hello: HelloParams,
+    ) -> Result<Self> {
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let client = Self {
+            inner: Arc::new(Inner {
+                endpoint,
+                server_addr,
+                server_name: server_name.to_string(),
+                connection: Mutex::new(None),
+                state: watch::channel(ConnectionState::Connecting).0,
+                outbox,
+                limits,
+                reconnects: reconnect.is_some(),
+                drain_lock: tokio::sync::Mutex::new(()),
+                subscribers: Mutex::new(Vec::new()),
+                receipt_watchers: Mutex::new(Vec::new()),
+                shutdown,
+                hello,
+                capabilities: Mutex::new(None),
+            }),
+        };
+
+        match client.establish().await {
+            Ok((conn, capabilities)) => {
+                client.attach(conn, capabilities);
+                if let Err(e) = client.drain_queue().await {
+                    warn!(error = %e, "failed to replay outbox");
                 }
-            },
+            }
+            Err(e) if is_incompatible(&e) => {
+                client.inner.endpoint.close(CloseCode::Normal.as_u32().into(), b"incompatible relay");
+                return Err(e);
+            }
             Err(e) => {
-                eprintln!("Failed to connect: {}. Running in offline mode.", e);
-                None
+                warn!(relay = %server_addr, error = %e, "relay unreachable, running offline");
+                client.inner.set_connection(None);
             }
-        };
+        }
 
-        let client = Self {
-            connection: connection,
-            outbox,
-        };
-        if client.connection.is_some() {
-          
-            let _ = client.drain_queue().await;
+        if let Some(policy) = reconnect {
+            let supervisor = supervise(Arc::downgrade(&client.inner), shutdown_rx, policy);
+            tokio::spawn(supervisor.instrument(client.span()));
         }
         Ok(client)
     }
-    pub async fn hello(&self, client_id: &str) -> Result<()> {
-        let conn = self.connection.as_ref().context("Not connected to relay")?;
+
+    /// Connects and negotiates with the relay, closing the connection again
+    /// if the relay turns out to be incompatible.
+    async fn establish(&self) -> Result<(quinn::Connection, Capabilities)> {
+        let inner = &self.inner;
+        let conn = connect_once(&inner.endpoint, inner.server_addr, &inner.server_name).await?;
+        match negotiate(&conn, &inner.hello).await {
+            Ok(capabilities) => Ok((conn, capabilities)),
+            Err(e) => {
+                // Stream errors hide why the relay closed, e.g. a rate limit.
+                if let Some(reason) = conn.close_reason() {
+                    return Err(reason.into());
+                }
+                conn.close(CloseCode::Normal.as_u32().into(), b"handshake failed");
+                Err(e)
+            }
+        }
+    }
+
+    /// Makes `conn` the current connection and listens on it for pushed
+    /// messages.
+    fn attach(&self, conn: quinn::Connection, capabilities: Capabilities) {
+        *self.inner.capabilities.lock().unwrap() = Some(capabilities);
+        self.inner.set_connection(Some(conn.clone()));
+        let listener = listen_for_pushes(
+            Arc::downgrade(&self.inner),
+            self.inner.shutdown.subscribe(),
+            conn,
+        );
+        tokio::spawn(listener.instrument(self.span()));
+    }
+
+    /// Span for background work on behalf of this client.
+    fn span(&self) -> tracing::Span {
+        tracing::info_span!("client", relay = %self.inner.server_addr)
+    }
+
+    /// The current connection, if any.
+    fn connection(&self) -> Result<quinn::Connection> {
+        self.inner
+            .connection
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(QightError::NotConnected)
+    }
+
+    /// Current connection state.
+    pub fn state(&self) -> ConnectionState {
+        *self.inner.state.borrow()
+    }
+
+    /// Observes connection state changes.
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.inner.state.subscribe()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state() == ConnectionState::Connected
+    }
+
+    /// What the relay agreed to in the last HELLO exchange, or `None` if the
+    /// client has never been connected.
+    ///
+    /// Optional features the relay lacks are worked around: without
+    /// [`Features::ACK`] [`RelayClient::fetch`] removes messages as it reads
+    /// them, and without [`Features::PUSH`] [`RelayClient::subscribe`] fails.
+    /// Sends that exceed the relay's payload or TTL limits are refused
+    /// locally instead of being queued.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        *self.inner.capabilities.lock().unwrap()
+    }
+
+    /// Repeats the HELLO exchange on the current connection under
+    /// `client_id` and returns the renegotiated capabilities. Clients
+    /// already say HELLO on every connect, so this is rarely needed.
+    pub async fn hello(&self, client_id: &str) -> Result<Capabilities> {
+        let conn = self.connection()?;
+        let params = HelloParams {
+            client_id: client_id.to_string(),
+            ..self.inner.hello.clone()
+        };
+        let capabilities = negotiate(&conn, &params).await?;
+        *self.inner.capabilities.lock().unwrap() = Some(capabilities);
+        Ok(capabilities)
+    }
+
+    /// The relay's storage figures, quota settings and quota counters.
+    pub async fn stats(&self) -> Result<RelayStats> {
+        let conn = self.connection()?;
         let (mut send, mut recv) = conn.open_bi().await?;
-        let payload = format!("HELLO {}\n", client_id);
-        send.write_all(payload.as_bytes()).await?;
+        write_frame(&mut send, &Frame::Stats).await?;
         send.finish()?;
 
-        let mut buf = vec![0; 1024];
-        let n = recv.read(&mut buf).await.context("read hello response")?;
-
-        match n {
-            Some(_) => println!("Hello Response Recieved from Server"),
-            None => println!("No response from server"),
+        match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+            Frame::StatsReply { stats } => Ok(stats),
+            other => Err(unexpected("STATS", &other)),
         }
+    }
+
+    /// Whether the relay supports `feature`, assumed while the capabilities
+    /// are unknown.
+    fn relay_supports(&self, feature: Features) -> bool {
+        self.capabilities()
+            .is_none_or(|capabilities| capabilities.supports(feature))
+    }
 
+    fn require(&self, feature: Features) -> Result<()> {
+        if !self.relay_supports(feature) {
+            return Err(QightError::UnsupportedFeature);
+        }
         Ok(())
     }
-    pub async fn send(&self, envelope: &MessageEnvelope) -> Result<()> {
-        let conn = self.connection.as_ref().context("Not connected to relay")?;
-        let (mut send, mut recv) = conn.open_bi().await?;
+
+    /// Sends a signed envelope.
+    ///
+    /// With an outbox the envelope is queued before it is transmitted and
+    /// removed once the relay accepts it. While offline, or when the transfer
+    /// fails, it stays queued and [`SendStatus::Queued`] is returned; it is
+    /// replayed once the client is back online. A rejection by the relay
+    /// moves it to the dead-letter state and is returned as an error.
+    ///
+    /// If the reply is lost, a relay supporting [`Features::IDEMPOTENT_SEND`]
+    /// gets the same envelope again, up to
+    /// [`RelayClientBuilder::send_retries`] times, on the same connection or
+    /// the one replacing it; the relay answers a resend of an accepted
+    /// envelope with OK. Reusing a `msg_id` for a different envelope fails
+    /// with [`QightError::MessageConflict`].
+    /// Without an outbox every failure is an error. Expired envelopes and
+    /// envelopes over the relay's payload or TTL limits are rejected without
+    /// being queued.
+    pub async fn send(&self, envelope: &MessageEnvelope) -> Result<SendStatus> {
         let bytes = envelope.to_bytes()?;
-        let pool = self.outbox.clone();
-        let envelope_clone = envelope.clone();
-        tokio::task::spawn_blocking(move || {
-            let conn = pool.get()?;
-            conn.execute(
-    "INSERT OR IGNORE INTO outbox (msg_id, sender, sender_key, recipient, timestamp, ttl, payload)
-     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", 
-    (
-        &envelope_clone.msg_id,
-        &envelope_clone.sender,
-        &envelope_clone.sender_key, 
-        &envelope_clone.recipient,
-        &envelope_clone.timestamp,
-        &envelope_clone.ttl,
-        &envelope_clone.payload,
-    ),
-)?;
-            Ok::<_, anyhow::Error>(())
-        })
-        .await??;
-        send.write_all(b"SEND").await?;
-        send.write_all(&(bytes.len() as u32).to_be_bytes()).await?;
-        send.write_all(&bytes).await?;
+        let now = unix_now();
+        if envelope.is_expired(now) {
+            return Err(QightError::Expired);
+        }
+        if let Some(capabilities) = self.capabilities() {
+            if bytes.len() > capabilities.max_payload as usize {
+                return Err(QightError::PayloadTooLarge);
+            }
+            if envelope.ttl > capabilities.max_ttl {
+                return Err(QightError::TtlOutOfRange);
+            }
+        }
+        let msg_id = envelope.msg_id;
 
-        send.finish()?;
+        let queued = envelope.clone();
+        let has_outbox = self
+            .with_outbox(move |outbox| outbox.enqueue(&queued, now))
+            .await?
+            .is_some();
+
+        let conn = match self.connection() {
+            Ok(conn) => conn,
+            Err(_) if has_outbox => return Ok(SendStatus::Queued),
+            Err(e) => return Err(e),
+        };
+
+        match self.transmit_with_retries(conn, &bytes).await {
+            Ok(SendOutcome::Accepted) => {
+                self.with_outbox(move |outbox| outbox.remove(&msg_id))
+                    .await?;
+                Ok(SendStatus::Delivered)
+            }
+            Ok(SendOutcome::Rejected { code, message }) => {
+                self.with_outbox(move |outbox| outbox.dead_letter(&msg_id, &message))
+                    .await?;
+                Err(error_from_code(code))
+            }
+            Err(e) => {
+                let error = e.to_string();
+                self.with_outbox(move |outbox| outbox.record_failure(&msg_id, &error, now))
+                    .await?;
+                if has_outbox {
+                    info!(%msg_id, error = %e, "send failed, message queued");
+                    Ok(SendStatus::Queued)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Transmits `bytes`, resending them after a lost reply if the relay
+    /// deduplicates SENDs.
+    async fn transmit_with_retries(
+        &self,
+        mut conn: quinn::Connection,
+        bytes: &[u8],
+    ) -> Result<SendOutcome> {
+        let mut retries = 0;
+        loop {
+            match transmit(&conn, bytes).await {
+                Err(QightError::ConnectionLost(reason))
+                    if retries < self.inner.limits.send_retries
+                        && self.relay_supports(Features::IDEMPOTENT_SEND) =>
+                {
+                    retries += 1;
+                    debug!(retry = retries, %reason, "SEND reply lost, resending");
+                    conn = match self.replacement(&conn).await {
+                        Some(next) => next,
+                        None => return Err(QightError::ConnectionLost(reason)),
+                    };
+                }
+                result => return result,
+            }
+        }
+    }
 
-        let mut resp = [0u8; 2];
-        recv.read_exact(&mut resp)
+    /// `conn` while it is open, otherwise the connection that replaces it
+    /// within [`SEND_RETRY_WAIT`].
+    async fn replacement(&self, conn: &quinn::Connection) -> Option<quinn::Connection> {
+        if conn.close_reason().is_none() {
+            return Some(conn.clone());
+        }
+        if !self.inner.reconnects {
+            return None;
+        }
+        let mut changes = self.state_changes();
+        let reconnected = async {
+            loop {
+                if let Ok(next) = self.connection() {
+                    if next.stable_id() != conn.stable_id() {
+                        return Some(next);
+                    }
+                }
+                if changes.changed().await.is_err()
+                    || *changes.borrow() == ConnectionState::Closed
+                {
+                    return None;
+                }
+            }
+        };
+        tokio::time::timeout(SEND_RETRY_WAIT, reconnected)
             .await
-            .context("Failed to read OK response")?;
-
-        if &resp == b"OK" {
-            let pool = self.outbox.clone();
-            let msg_id = envelope.msg_id.clone();
-            tokio::task::spawn_blocking(move || {
-                let conn = pool.get()?;
-                conn.execute("DELETE FROM outbox WHERE msg_id = ?1", [&msg_id])?;
-                Ok::<_, anyhow::Error>(())
-            })
-            .await??;
-        } else {
-            anyhow::bail!("Relay sent unexpected response: {:?}", resp);
+            .ok()
+            .flatten()
+    }
+
+    /// Runs `op` against the outbox on the blocking pool. Does nothing when
+    /// the outbox is disabled.
+    async fn with_outbox<T, F>(&self, op: F) -> Result<Option<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Outbox) -> Result<T> + Send + 'static,
+    {
+        let Some(outbox) = self.inner.outbox.clone() else {
+            return Ok(None);
+        };
+        let result = tokio::task::spawn_blocking(move || op(&outbox))
+            .await
+            .map_err(|e| QightError::Storage(format!("outbox task failed: {}", e)))??;
+        Ok(Some(result))
+    }
+
+    /// The outbox undelivered messages are queued in, if enabled.
+    pub fn outbox(&self) -> Option<&Outbox> {
+        self.inner.outbox.as_ref()
+    }
+
+    /// Fetches and removes the inbox of the recipient owning `signing_key`.
+    ///
+    /// The relay challenges the request; the client answers by signing the
+    /// nonce bound to this QUIC connection, proving possession of the key.
+    /// Messages are leased first and only acknowledged once they have been
+    /// read in full, so a dropped connection does not lose them. Relays
+    /// without [`Features::ACK`] remove messages as soon as they are sent.
+    pub async fn fetch(&self, signing_key: &[u8; SECRET_KEY_LENGTH]) -> Result<Vec<MessageEnvelope>> {
+        if !self.relay_supports(Features::ACK) {
+            return self.fetch_with_lease(signing_key, 0).await;
         }
+        let messages = self.fetch_leased(signing_key, DEFAULT_LEASE).await?;
+        if !messages.is_empty() {
+            let msg_ids: Vec<_> = messages.iter().map(|msg| msg.msg_id).collect();
+            self.ack(signing_key, &msg_ids).await?;
+        }
+        Ok(messages)
+    }
 
-        Ok(())
+    /// Fetches the inbox without removing it. The returned messages stay
+    /// invisible to other fetches for `lease` and reappear afterwards unless
+    /// they are acknowledged with [`RelayClient::ack`].
+    pub async fn fetch_leased(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+        lease: Duration,
+    ) -> Result<Vec<MessageEnvelope>> {
+        self.require(Features::ACK)?;
+        let lease_secs = lease.as_secs().clamp(1, u32::MAX as u64) as u32;
+        self.fetch_with_lease(signing_key, lease_secs).await
     }
 
-    pub async fn fetch(&self, recipient: &str) -> Result<Vec<MessageEnvelope>> {
-        let conn = self.connection.as_ref().context("Not connected to relay")?;
-        let (mut send, mut recv) = conn.open_bi().await?;
-        let req = format!("FETCH {}\n", recipient.trim());
-        send.write_all(req.as_bytes()).await?;
-        send.finish()?;
+    /// Sends FETCH; a `lease_secs` of 0 asks the relay to remove the
+    /// messages right away. Envelopes that do not decode are dropped so the
+    /// rest of the batch is still returned.
+    async fn fetch_with_lease(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+        lease_secs: u32,
+    ) -> Result<Vec<MessageEnvelope>> {
+        let request = Frame::Fetch {
+            recipient: public_key_from_secret(signing_key),
+            lease_secs,
+        };
+        let (_send, mut recv) = self.open_authenticated(&request, signing_key).await?;
 
         let mut messages = Vec::new();
+        let mut malformed = 0usize;
+        loop {
+            match read_response(&mut recv, self.inner.limits.max_message_size).await? {
+                Frame::Message { envelope } => match MessageEnvelope::from_bytes(&envelope) {
+                    Ok(envelope) => messages.push(envelope),
+                    Err(_) => malformed += 1,
+                },
+                Frame::End => break,
+                other => return Err(unexpected("FETCH", &other)),
+            }
+        }
+        if malformed > 0 {
+            warn!(dropped = malformed, "dropped malformed fetched messages");
+        }
+        Ok(messages)
+    }
+
+    /// Acknowledges leased messages so the relay deletes them. Returns how
+    /// many messages the relay removed.
+    pub async fn ack(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+        msg_ids: &[MessageId],
+    ) -> Result<usize> {
+        self.require(Features::ACK)?;
+        let request = Frame::Ack {
+            recipient: public_key_from_secret(signing_key),
+            msg_ids: msg_ids.to_vec(),
+        };
+        let (_send, mut recv) = self.open_authenticated(&request, signing_key).await?;
+
+        match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+            Frame::Acked { count } => Ok(count as usize),
+            other => Err(unexpected("ACK", &other)),
+        }
+    }
+
+    /// Subscribes to push delivery for the recipient owning `signing_key`.
+    ///
+    /// The relay challenges the request like a FETCH. Afterwards every
+    /// message it stores for the recipient is pushed over a server-opened
+    /// stream and yielded by the returned [`Subscription`]; envelopes whose
+    /// signature does not verify are dropped. Delivered messages are
+    /// acknowledged automatically. Messages stored before subscribing are
+    /// not pushed; read them with [`RelayClient::fetch`]. After a reconnect
+    /// the subscription is renewed. Fails with
+    /// [`QightError::UnsupportedFeature`] if the relay lacks [`Features::PUSH`].
+    pub async fn subscribe(&self, signing_key: &[u8; SECRET_KEY_LENGTH]) -> Result<Subscription> {
+        self.require(Features::PUSH)?;
+        self.send_subscribe(signing_key).await?;
+
+        let recipient = public_key_from_secret(signing_key);
+        let (tx, rx) = tokio::sync::mpsc::channel(SUBSCRIPTION_BUFFER);
+        self.inner.subscribers.lock().unwrap().push(Subscriber {
+            signing_key: *signing_key,
+            recipient,
+            tx,
+        });
+        Ok(Subscription::new(recipient, rx))
+    }
+
+    async fn send_subscribe(&self, signing_key: &[u8; SECRET_KEY_LENGTH]) -> Result<()> {
+        let request = Frame::Subscribe {
+            recipient: public_key_from_secret(signing_key),
+        };
+        let (_send, mut recv) = self.open_authenticated(&request, signing_key).await?;
 
+        match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+            Frame::Ok => Ok(()),
+            other => Err(unexpected("SUBSCRIBE", &other)),
+        }
+    }
+
+    /// The receipts the relay recorded for the message `msg_id` sent with
+    /// `signing_key`, oldest first.
+    ///
+    /// The relay challenges the request like a FETCH and only answers the
+    /// message's sender: unknown messages, other senders' messages and logs
+    /// the relay has forgotten all yield an empty list. Read receipts whose
+    /// proof does not verify are dropped. Fails with
+    /// [`QightError::UnsupportedFeature`] if the relay lacks
+    /// [`Features::RECEIPTS`].
+    pub async fn receipts(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+        msg_id: &MessageId,
+    ) -> Result<Vec<Receipt>> {
+        self.require(Features::RECEIPTS)?;
+        let sender_key = public_key_from_secret(signing_key);
+        let request = Frame::Receipts {
+            sender_key,
+            msg_id: *msg_id,
+        };
+        let (_send, mut recv) = self.open_authenticated(&request, signing_key).await?;
+
+        let mut receipts = Vec::new();
         loop {
-            let mut len_bytes = [0u8; 4];
-            match recv.read_exact(&mut len_bytes).await {
-                Ok(()) => {
-                    let len = u32::from_be_bytes(len_bytes) as usize;
+            match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+                Frame::Receipt { receipt, .. } if is_genuine(&receipt, &sender_key) => {
+                    receipts.push(receipt)
+                }
+                Frame::Receipt { receipt, .. } => {
+                    warn!(msg_id = %receipt.msg_id, "dropped read receipt with invalid proof")
+                }
+                Frame::End => break,
+                other => return Err(unexpected("RECEIPTS", &other)),
+            }
+        }
+        Ok(receipts)
+    }
 
-                    if len == 0 {
-                        break;
-                    }
+    /// Watches the receipts of messages sent with `signing_key`.
+    ///
+    /// The relay challenges the request like a FETCH. Afterwards every
+    /// receipt it records for the sender's messages is pushed and yielded by
+    /// the returned [`ReceiptStream`]; Read receipts whose proof does not
+    /// verify are dropped. Receipts recorded while disconnected are not
+    /// replayed; query them with [`RelayClient::receipts`]. After a
+    /// reconnect the watch is renewed. Fails with
+    /// [`QightError::UnsupportedFeature`] if the relay lacks
+    /// [`Features::RECEIPTS`].
+    pub async fn watch_receipts(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+    ) -> Result<ReceiptStream> {
+        self.require(Features::RECEIPTS)?;
+        self.send_watch(signing_key).await?;
+
+        let sender_key = public_key_from_secret(signing_key);
+        let (tx, rx) = tokio::sync::mpsc::channel(SUBSCRIPTION_BUFFER);
+        self.inner.receipt_watchers.lock().unwrap().push(ReceiptWatcher {
+            signing_key: *signing_key,
+            sender_key,
+            tx,
+        });
+        Ok(ReceiptStream::new(sender_key, rx))
+    }
+
+    async fn send_watch(&self, signing_key: &[u8; SECRET_KEY_LENGTH]) -> Result<()> {
+        let request = Frame::Watch {
+            sender_key: public_key_from_secret(signing_key),
+        };
+        let (_send, mut recv) = self.open_authenticated(&request, signing_key).await?;
+
+        match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+            Frame::Ok => Ok(()),
+            other => Err(unexpected("WATCH", &other)),
+        }
+    }
+
+    /// Tells the sender of `original` that it was read, with a read receipt
+    /// signed by `signing_key`, the recipient's key.
+    ///
+    /// The receipt travels like any other message, through the outbox if
+    /// there is one. A relay supporting [`Features::RECEIPTS`] records it as
+    /// a [`ReceiptStatus::Read`] receipt for the original message.
+    pub async fn send_read_receipt(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+        original: &MessageEnvelope,
+    ) -> Result<SendStatus> {
+        let sender = self.inner.hello.client_id.clone();
+        let mut receipt = MessageEnvelope::read_receipt_for(original, sender, unix_now());
+        receipt.sign(signing_key);
+        self.send(&receipt).await
+    }
+
+    /// Renews the subscriptions and receipt watches that are still being
+    /// consumed on the current connection.
+    async fn resubscribe(&self) {
+        let keys: Vec<_> = {
+            let mut subscribers = self.inner.subscribers.lock().unwrap();
+            subscribers.retain(|s| !s.tx.is_closed());
+            let mut keys: Vec<_> = subscribers
+                .iter()
+                .map(|s| (s.signing_key, s.recipient))
+                .collect();
+            keys.sort();
+            keys.dedup();
+            keys
+        };
+        for (key, recipient) in keys {
+            if let Err(e) = self.send_subscribe(&key).await {
+                let recipient = key_fingerprint(&recipient);
+                warn!(%recipient, error = %e, "failed to renew subscription");
+            }
+        }
+
+        let keys: Vec<_> = {
+            let mut watchers = self.inner.receipt_watchers.lock().unwrap();
+            watchers.retain(|w| !w.tx.is_closed());
+            let mut keys: Vec<_> = watchers
+                .iter()
+                .map(|w| (w.signing_key, w.sender_key))
+                .collect();
+            keys.sort();
+            keys.dedup();
+            keys
+        };
+        for (key, sender_key) in keys {
+            if let Err(e) = self.send_watch(&key).await {
+                let sender = key_fingerprint(&sender_key);
+                warn!(%sender, error = %e, "failed to renew receipt watch");
+            }
+        }
+    }
+
+    /// Hands a pushed receipt to the watches of its sender.
+    async fn deliver_receipt(&self, sender_key: [u8; PUBLIC_KEY_LENGTH], receipt: Receipt) {
+        if !is_genuine(&receipt, &sender_key) {
+            warn!(msg_id = %receipt.msg_id, "dropped read receipt with invalid proof");
+            return;
+        }
+        let watchers: Vec<ReceiptWatcher> = {
+            let mut watchers = self.inner.receipt_watchers.lock().unwrap();
+            watchers.retain(|w| !w.tx.is_closed());
+            watchers
+                .iter()
+                .filter(|w| w.sender_key == sender_key)
+                .cloned()
+                .collect()
+        };
+        for watcher in watchers {
+            let _ = watcher.tx.send(receipt.clone()).await;
+        }
+    }
+
+    /// Hands a pushed envelope to the matching subscriptions and acknowledges
+    /// it once at least one of them took it.
+    async fn deliver_push(&self, envelope: MessageEnvelope) {
+        if !envelope.verify() {
+            warn!(msg_id = %envelope.msg_id, "dropped pushed message with invalid signature");
+            return;
+        }
+        let subscribers: Vec<Subscriber> = {
+            let mut subscribers = self.inner.subscribers.lock().unwrap();
+            subscribers.retain(|s| !s.tx.is_closed());
+            subscribers
+                .iter()
+                .filter(|s| s.recipient == envelope.recipient)
+                .cloned()
+                .collect()
+        };
+
+        let mut delivered_with = None;
+        for subscriber in subscribers {
+            if subscriber.tx.send(envelope.clone()).await.is_ok() {
+                delivered_with = Some(subscriber.signing_key);
+            }
+        }
+
+        // Unclaimed messages stay on the relay for a later FETCH. Without ACK
+        // support the relay has already forgotten the message.
+        if let Some(signing_key) = delivered_with.filter(|_| self.relay_supports(Features::ACK)) {
+            if let Err(e) = self.ack(&signing_key, &[envelope.msg_id]).await {
+                warn!(msg_id = %envelope.msg_id, error = %e, "failed to acknowledge pushed message");
+            }
+        }
+    }
+
+    /// Sends a recipient-scoped request and answers the relay's challenge
+    /// by signing the nonce bound to this QUIC connection.
+    async fn open_authenticated(
+        &self,
+        request: &Frame,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+    ) -> Result<(quinn::SendStream, quinn::RecvStream)> {
+        let conn = self.connection()?;
+        let recipient = public_key_from_secret(signing_key);
+        let (mut send, mut recv) = conn.open_bi().await?;
+        write_frame(&mut send, request).await?;
+
+        let nonce = match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+            Frame::Challenge { nonce } => nonce,
+            other => return Err(unexpected("a challenged request", &other)),
+        };
+
+        let mut binding = [0u8; 32];
+        conn.export_keying_material(&mut binding, CHALLENGE_EXPORTER_LABEL, &nonce)
+            .map_err(|_| QightError::ChannelBinding)?;
+        let signature = sign_message(signing_key, &challenge_message(&nonce, &recipient, &binding));
+        write_frame(&mut send, &Frame::ChallengeResponse { signature }).await?;
+        send.finish()?;
+
+        Ok((send, recv))
+    }
+
+    /// Fetches messages for the recipient owning `signing_key` and keeps only those whose signature
+    /// verifies. Envelopes that fail [`MessageEnvelope::verify`] are dropped.
+    pub async fn fetch_verified(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+    ) -> Result<Vec<MessageEnvelope>> {
+        let (verified, rejected) = self.fetch_checked(signing_key).await?;
+        if !rejected.is_empty() {
+            warn!(dropped = rejected.len(), "dropped fetched messages with invalid signatures");
+        }
+        Ok(verified)
+    }
 
-                    if len > 5_000_000 {
-                        anyhow::bail!(
-                            "refusing to read suspiciously large message ({} bytes)",
-                            len
-                        );
+    /// Fetches messages for the recipient owning `signing_key`, split into `(verified, rejected)`
+    /// according to [`MessageEnvelope::verify`].
+    pub async fn fetch_checked(
+        &self,
+        signing_key: &[u8; SECRET_KEY_LENGTH],
+    ) -> Result<(Vec<MessageEnvelope>, Vec<MessageEnvelope>)> {
+        let messages = self.fetch(signing_key).await?;
+        Ok(messages.into_iter().partition(|msg| msg.verify()))
+    }
+    /// Replays queued messages whose backoff has elapsed.
+    ///
+    /// Expired entries are dropped first. Each due envelope is sent as
+    /// stored; accepted ones are removed, rejected ones dead-lettered, and
+    /// transport failures rescheduled with exponential backoff.
+    pub async fn drain_queue(&self) -> Result<DrainReport> {
+        let report = self.drain().await?;
+        if self.inner.outbox.is_some() {
+            crate::metrics::record_drain(&report);
+        }
+        Ok(report)
+    }
+
+    async fn drain(&self) -> Result<DrainReport> {
+        let _guard = self.inner.drain_lock.lock().await;
+        let mut report = DrainReport::default();
+        let now = unix_now();
+        let Some((expired, due)) = self
+            .with_outbox(move |outbox| Ok((outbox.expire(now)?, outbox.due(now)?)))
+            .await?
+        else {
+            return Ok(report);
+        };
+        report.expired = expired;
+        if due.is_empty() {
+            return Ok(report);
+        }
+        let conn = self.connection()?;
+
+        for entry in due {
+            let msg_id = entry.envelope.msg_id;
+            let bytes = entry.envelope.to_bytes()?;
+            match transmit(&conn, &bytes).await {
+                Ok(SendOutcome::Accepted) => {
+                    self.with_outbox(move |outbox| outbox.remove(&msg_id))
+                        .await?;
+                    report.delivered += 1;
+                }
+                Ok(SendOutcome::Rejected { message, .. }) => {
+                    self.with_outbox(move |outbox| outbox.dead_letter(&msg_id, &message))
+                        .await?;
+                    report.dead_lettered += 1;
+                }
+                Err(e) => {
+                    let error = e.to_string();
+                    self.with_outbox(move |outbox| outbox.record_failure(&msg_id, &error, now))
+                        .await?;
+                    report.deferred += 1;
+                    if e.retry_after().is_some() {
+                        // The rest stay due for the next drain.
+                        break;
                     }
+                }
+            }
+        }
 
-                    let mut payload = vec![0u8; len];
-                    recv.read_exact(&mut payload)
-                        .await
-                        .context("failed to read message payload")?;
+        Ok(report)
+    }
+    /// Closes the connection and stops reconnecting.
+    pub async fn close(&self, reason: Option<&str>) {
+        let reason_bytes = reason.unwrap_or("done").as_bytes();
+        self.inner.set_state(ConnectionState::Closed);
+        let _ = self.inner.shutdown.send(true);
+        self.inner.subscribers.lock().unwrap().clear();
+        self.inner.receipt_watchers.lock().unwrap().clear();
+        if let Some(conn) = self.inner.connection.lock().unwrap().take() {
+            conn.close(CloseCode::Normal.as_u32().into(), reason_bytes);
+        }
+    }
+}
 
-                    let envelope = wincode::deserialize(&payload)
-                        .context("failed to deserialize MessageEnvelope")?;
+/// Background task that keeps a client connected.
+///
+/// While connected it waits for the connection to drop, periodically
+/// retrying outbox entries that are backing off. Once disconnected it
+/// reconnects with exponential backoff and drains the outbox. Holds only a
+/// weak reference, so it ends when the last client clone is dropped or
+/// [`RelayClient::close`] is called.
+async fn supervise(inner: Weak<Inner>, mut shutdown: watch::Receiver<bool>, policy: ReconnectPolicy) {
+    let mut delay = policy.initial;
+    loop {
+        let current = match inner.upgrade() {
+            Some(inner) => inner.connection.lock().unwrap().clone(),
+            None => return,
+        };
 
-                    messages.push(envelope);
+        if let Some(conn) = current {
+            let start = tokio::time::Instant::now() + OUTBOX_RETRY_INTERVAL;
+            let mut retry = tokio::time::interval_at(start, OUTBOX_RETRY_INTERVAL);
+            loop {
+                tokio::select! {
+                    reason = conn.closed() => {
+                        warn!(%reason, "connection to relay lost");
+                        break;
+                    }
+                    _ = retry.tick() => {
+                        let Some(inner) = inner.upgrade() else { return };
+                        if let Err(e) = (RelayClient { inner }).drain_queue().await {
+                            warn!(error = %e, "failed to replay outbox");
+                        }
+                    }
+                    _ = shutdown.changed() => return,
                 }
+            }
+            let Some(inner) = inner.upgrade() else { return };
+            if *shutdown.borrow() {
+                return;
+            }
+            inner.set_connection(None);
+            delay = policy.initial;
+        }
 
-                Err(e) => {
-                    return Err(e.into());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => return,
+        }
+
+        let Some(inner) = inner.upgrade() else { return };
+        inner.set_state(ConnectionState::Connecting);
+        let client = RelayClient { inner };
+        match client.establish().await {
+            Ok((conn, capabilities)) => {
+                if *shutdown.borrow() {
+                    conn.close(CloseCode::Normal.as_u32().into(), b"done");
+                    return;
+                }
+                client.attach(conn, capabilities);
+                client.resubscribe().await;
+                match client.drain_queue().await {
+                    Ok(report) if report.delivered > 0 => {
+                        info!(delivered = report.delivered, "replayed queued messages")
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!(error = %e, "failed to replay outbox"),
                 }
             }
+            Err(e) => {
+                // A rate-limited relay says when to come back.
+                delay = policy.next(delay).max(e.retry_after().unwrap_or_default());
+                warn!(error = %e, retry_in = ?delay, "reconnect failed");
+                client.inner.set_state(ConnectionState::Disconnected);
+            }
         }
-        Ok(messages)
     }
-    pub async fn drain_queue(&self) -> Result<()> {
-        let pending_messages = tokio::task::spawn_blocking({
-            let pool = self.outbox.clone();
-            move || -> Result<Vec<(String, Vec<u8>)>> {
-                let conn = pool.get()?;
-                let mut stmt = conn.prepare("SELECT msg_id, payload FROM outbox")?;
-                let rows = stmt.query_map([], |row| {
-                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
-                })?;
-
-                let mut msgs = Vec::new();
-                for msg in rows {
-                    msgs.push(msg?);
+}
+
+/// Accepts the unidirectional streams the relay opens to push messages and
+/// receipts on `conn`. Each carries one [`Frame::Message`] or
+/// [`Frame::Receipt`].
+async fn listen_for_pushes(
+    inner: Weak<Inner>,
+    mut shutdown: watch::Receiver<bool>,
+    conn: quinn::Connection,
+) {
+    loop {
+        let mut stream = tokio::select! {
+            stream = conn.accept_uni() => match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            },
+            _ = shutdown.changed() => return,
+        };
+        let Some(inner) = inner.upgrade() else { return };
+        let client = RelayClient { inner };
+        let limit = client.inner.limits.max_message_size;
+
+        let push = async move {
+            let envelope = match read_frame(&mut stream, limit).await {
+                Ok(Some(Frame::Message { envelope })) => MessageEnvelope::from_bytes(&envelope).ok(),
+                Ok(Some(Frame::Receipt {
+                    sender_key,
+                    receipt,
+                })) => return client.deliver_receipt(sender_key, receipt).await,
+                Ok(_) => None,
+                Err(e) => {
+                    warn!(error = %e, "failed to read pushed message");
+                    return;
                 }
-             
-                Ok(msgs)
+            };
+            match envelope {
+                Some(envelope) => client.deliver_push(envelope).await,
+                None => warn!("dropped malformed pushed message"),
             }
-        })
-        .await
-        .context("Database read task panicked")??;
-
-        for (msg_id, payload) in pending_messages {
-            let conn = self.connection.as_ref().context("Not connected")?;
-            let (mut send, mut recv) = conn.open_bi().await?;
-
-            send.write_all(b"SEND").await?;
-            send.write_all(&(payload.len() as u32).to_be_bytes())
-                .await?;
-            send.write_all(&payload).await?;
-            send.finish()?;
-
-            let mut resp = [0u8; 2];
-            recv.read_exact(&mut resp)
-                .await
-                .context("Failed to read OK response")?;
-
-            if &resp == b"OK" {
-                let pool_clone = self.outbox.clone();
-                tokio::task::spawn_blocking(move || -> Result<()> {
-                    let conn = pool_clone.get()?;
-                    conn.execute("DELETE FROM outbox WHERE msg_id = ?", [msg_id])?;
-                    Ok(())
-                })
-                .await
-                .context("Database delete task panicked")??;
+        };
+        tokio::spawn(push.in_current_span());
+    }
+}
+
+/// Whether a receipt for a message from `sender_key` holds up: a Read
+/// receipt must carry a verified read receipt addressed to the sender.
+fn is_genuine(receipt: &Receipt, sender_key: &[u8; PUBLIC_KEY_LENGTH]) -> bool {
+    match receipt.status {
+        ReceiptStatus::Read => receipt
+            .read_receipt()
+            .is_some_and(|proof| proof.recipient == *sender_key),
+        _ => true,
+    }
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// Says HELLO on `conn` and checks the relay's WELCOME: a version in our
+/// range, the required features, and a valid identity signature over this
+/// connection's channel binding (matching the pinned key, if any).
+async fn negotiate(conn: &quinn::Connection, params: &HelloParams) -> Result<Capabilities> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    let hello = Frame::Hello {
+        client_id: params.client_id.clone(),
+        min_version: MIN_PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION,
+        features: Features::supported(),
+    };
+    write_frame(&mut send, &hello).await?;
+    send.finish()?;
+
+    let (capabilities, signature) = match read_response(&mut recv, MAX_CONTROL_PAYLOAD).await? {
+        Frame::Welcome {
+            capabilities,
+            signature,
+            ..
+        } => (capabilities, signature),
+        other => return Err(unexpected("HELLO", &other)),
+    };
+
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&capabilities.version) {
+        return Err(QightError::UnsupportedProtocolVersion);
+    }
+
+    let mut binding = [0u8; 32];
+    conn.export_keying_material(&mut binding, RELAY_IDENTITY_EXPORTER_LABEL, &[])
+        .map_err(|_| QightError::ChannelBinding)?;
+    let message = relay_identity_message(&capabilities.relay_key, &binding);
+    let pinned = params.relay_key.unwrap_or(capabilities.relay_key);
+    if pinned != capabilities.relay_key
+        || !verify_message(&capabilities.relay_key, &message, &signature)
+    {
+        return Err(QightError::RelayIdentityMismatch);
+    }
+
+    if !capabilities.supports(params.required) {
+        return Err(QightError::UnsupportedFeature);
+    }
+    Ok(capabilities)
+}
+
+/// Whether `error` means the relay can be reached but must not be used.
+fn is_incompatible(error: &QightError) -> bool {
+    matches!(
+        error,
+        QightError::UnsupportedProtocolVersion
+            | QightError::UnsupportedFeature
+            | QightError::RelayIdentityMismatch
+    )
+}
+
+/// Writes one SEND request and reads the relay's verdict.
+async fn transmit(conn: &quinn::Connection, bytes: &[u8]) -> Result<SendOutcome> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    let request = Frame::Send {
+        envelope: bytes.to_vec(),
+    };
+    write_frame(&mut send, &request).await?;
+    send.finish()?;
+
+    let reply = read_frame(&mut recv, MAX_CONTROL_PAYLOAD).await?;
+    match reply {
+        Some(Frame::Ok) => Ok(SendOutcome::Accepted),
+        Some(Frame::Error { code, message }) => {
+            let retryable = ErrorCode::from_u16(code).is_some_and(ErrorCode::is_retryable);
+            if retryable {
+                Err(error_from_code(code))
             } else {
-                anyhow::bail!("Relay sent unexpected response: {:?}", resp);
+                Ok(SendOutcome::Rejected { code, message })
             }
         }
-
-        Ok(())
+        Some(Frame::RateLimited { retry_after_ms }) => Err(QightError::RateLimited {
+            retry_after_ms: retry_after_ms.into(),
+        }),
+        Some(other) => Err(unexpected("SEND", &other)),
+        None => Err(no_response()),
     }
-    pub async fn close(&self, reason: Option<&str>) {
-        let reason_bytes = reason.unwrap_or("done").as_bytes();
-        if let Some(conn) = &self.connection {
-            conn.close(0u8.into(), reason_bytes);
+}
+
+/// Reads one response frame, turning error frames into errors.
+async fn read_response(recv: &mut quinn::RecvStream, max_payload: usize) -> Result<Frame> {
+    match read_frame(recv, max_payload).await? {
+        Some(Frame::Error { code, message }) => {
+            debug!(code, %message, "relay rejected request");
+            Err(error_from_code(code))
         }
+        Some(Frame::RateLimited { retry_after_ms }) => Err(QightError::RateLimited {
+            retry_after_ms: retry_after_ms.into(),
+        }),
+        Some(frame) => Ok(frame),
+        None => Err(no_response()),
     }
 }
 
+fn unexpected(request: &str, frame: &Frame) -> QightError {
+    QightError::UnexpectedResponse(format!(
+        "{:#04x} in reply to {}",
+        frame.frame_type(),
+        request
+    ))
+}
+
+fn no_response() -> QightError {
+    QightError::ConnectionLost("relay closed the stream without a response".to_string())
+}
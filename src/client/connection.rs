@@ -0,0 +1,117 @@
+use crate::errors::Result;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use quinn::Endpoint;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::protocol::Features;
+
+/// Interval at which QUIC keep-alives are sent, keeping idle connections open.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often a connected client retries outbox entries that are backing off.
+pub const OUTBOX_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connection state of a [`crate::RelayClient`], observable through
+/// [`crate::RelayClient::state_changes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection attempt is in progress.
+    Connecting,
+    /// Connected; sends go straight to the relay.
+    Connected,
+    /// The relay is unreachable; sends are queued in the outbox.
+    Disconnected,
+    /// [`crate::RelayClient::close`] was called; no further reconnects.
+    Closed,
+}
+
+/// Delay between reconnection attempts, doubling from `initial` up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay after `delay`, capped at `max`.
+    pub fn next(&self, delay: Duration) -> Duration {
+        delay.saturating_mul(2).min(self.max)
+    }
+}
+
+/// What a client announces in HELLO and insists on in WELCOME.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct HelloParams {
+    pub client_id: String,
+    /// Features the relay must agree to, or the connection is refused.
+    pub required: Features,
+    /// Pinned relay identity key.
+    pub relay_key: Option<[u8; PUBLIC_KEY_LENGTH]>,
+}
+
+impl Default for HelloParams {
+    fn default() -> Self {
+        Self {
+            client_id: "qight-client".to_string(),
+            required: Features::empty(),
+            relay_key: None,
+        }
+    }
+}
+
+/// Per-client limits set on [`crate::RelayClientBuilder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ClientLimits {
+    /// Largest single message accepted from the relay, in bytes.
+    pub max_message_size: usize,
+    /// Resends of a SEND whose reply was lost.
+    pub send_retries: u32,
+}
+
+impl Default for ClientLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 5_000_000,
+            send_retries: crate::client::DEFAULT_SEND_RETRIES,
+        }
+    }
+}
+
+/// Performs one connection attempt and handshake.
+pub(crate) async fn connect_once(
+    endpoint: &Endpoint,
+    server_addr: SocketAddr,
+    server_name: &str,
+) -> Result<quinn::Connection> {
+    let conn = endpoint.connect(server_addr, server_name)?.await?;
+    tracing::debug!(relay = %server_addr, "connected");
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay_doubles_up_to_max() {
+        let policy = ReconnectPolicy {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(350),
+        };
+        let delay = policy.next(policy.initial);
+        assert_eq!(delay, Duration::from_millis(200));
+        let delay = policy.next(delay);
+        assert_eq!(delay, Duration::from_millis(350));
+        assert_eq!(policy.next(delay), Duration::from_millis(350));
+    }
+}
@@ -1,2 +1,11 @@
+mod builder;
+#[allow(clippy::module_inception)]
 mod client;
-pub use client::*;
\ No newline at end of file
+mod connection;
+mod outbox;
+mod subscription;
+pub use builder::*;
+pub use client::*;
+pub use connection::*;
+pub use outbox::*;
+pub use subscription::*;
\ No newline at end of file
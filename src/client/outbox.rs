@@ -0,0 +1,447 @@
+use crate::errors::Result;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OptionalExtension;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{MessageEnvelope, MessageId};
+
+/// Default number of pooled SQLite connections for a file outbox.
+pub const DEFAULT_OUTBOX_POOL_SIZE: u32 = 5;
+
+/// Delay before the first retry of a failed delivery.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for the delay between retries.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// Delivery state of an [`OutboxEntry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutboxState {
+    /// Waiting for (another) delivery attempt.
+    Pending,
+    /// Rejected by the relay; never retried automatically.
+    DeadLetter,
+}
+
+impl OutboxState {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutboxState::Pending => "pending",
+            OutboxState::DeadLetter => "dead",
+        }
+    }
+
+    fn parse(raw: &str) -> Self {
+        match raw {
+            "dead" => OutboxState::DeadLetter,
+            _ => OutboxState::Pending,
+        }
+    }
+}
+
+/// A queued message together with its delivery bookkeeping.
+#[derive(Clone, Debug)]
+pub struct OutboxEntry {
+    pub envelope: MessageEnvelope,
+    pub state: OutboxState,
+    /// Failed delivery attempts so far.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Unix time (seconds) of the next replay attempt.
+    pub next_attempt_at: u64,
+    /// Unix time (seconds) after which the relay would discard the message.
+    pub expires_at: u64,
+}
+
+/// Persistent queue of signed envelopes that have not reached the relay.
+///
+/// Entries hold the complete serialized envelope, so a replay sends exactly
+/// the bytes that were signed. Failed attempts are retried with exponential
+/// backoff; envelopes the relay rejects are moved to the dead-letter state,
+/// and entries past their TTL are dropped.
+#[derive(Clone)]
+pub struct Outbox {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl std::fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Outbox").finish_non_exhaustive()
+    }
+}
+
+impl Outbox {
+    /// Opens (or creates) the outbox at `path` and migrates its schema.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_pool_size(path, DEFAULT_OUTBOX_POOL_SIZE)
+    }
+
+    pub fn open_with_pool_size(path: impl AsRef<Path>, pool_size: u32) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path);
+        Self::from_pool(Pool::builder().max_size(pool_size).build(manager)?)
+    }
+
+    /// Private in-memory outbox, lost when the last clone is dropped.
+    pub fn in_memory() -> Result<Self> {
+        let manager = SqliteConnectionManager::memory();
+        Self::from_pool(Pool::builder().max_size(1).build(manager)?)
+    }
+
+    fn from_pool(pool: Pool<SqliteConnectionManager>) -> Result<Self> {
+        init_schema(&*pool.get()?)?;
+        Ok(Self { pool })
+    }
+
+    /// Queues `envelope` for delivery. Queuing an already queued message is a
+    /// no-op.
+    pub fn enqueue(&self, envelope: &MessageEnvelope, now: u64) -> Result<()> {
+        let conn = self.pool.get()?;
+        conn.execute(
+            "INSERT OR IGNORE INTO outbox (msg_id, envelope, state, attempts, next_attempt_at, expires_at)
+             VALUES (?1, ?2, ?3, 0, ?4, ?5)",
+            (
+                &envelope.msg_id,
+                envelope.to_bytes()?,
+                OutboxState::Pending.as_str(),
+                now as i64,
+                expires_at(envelope) as i64,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Removes a delivered message.
+    pub fn remove(&self, msg_id: &MessageId) -> Result<bool> {
+        let conn = self.pool.get()?;
+        Ok(conn.execute("DELETE FROM outbox WHERE msg_id = ?1", [msg_id])? > 0)
+    }
+
+    /// Pending entries whose backoff has elapsed and whose TTL has not, in
+    /// queue order.
+    pub fn due(&self, now: u64) -> Result<Vec<OutboxEntry>> {
+        self.select(
+            "WHERE state = 'pending' AND next_attempt_at <= ?1 AND expires_at > ?1",
+            [now as i64],
+        )
+    }
+
+    /// All pending entries, including those waiting out their backoff.
+    pub fn pending(&self) -> Result<Vec<OutboxEntry>> {
+        self.select("WHERE state = 'pending'", [])
+    }
+
+    /// Entries the relay rejected.
+    pub fn dead_letters(&self) -> Result<Vec<OutboxEntry>> {
+        self.select("WHERE state = 'dead'", [])
+    }
+
+    /// Looks up one entry by message id.
+    pub fn get(&self, msg_id: &MessageId) -> Result<Option<OutboxEntry>> {
+        let conn = self.pool.get()?;
+        let row = conn
+            .query_row(
+                &format!("{} WHERE msg_id = ?1", SELECT_ENTRY),
+                [msg_id],
+                read_row,
+            )
+            .optional()?;
+        row.map(into_entry).transpose()
+    }
+
+    /// Records a failed delivery attempt and schedules the next one with
+    /// exponential backoff.
+    pub fn record_failure(
+        &self,
+        msg_id: &MessageId,
+        error: &str,
+        now: u64,
+    ) -> Result<()> {
+        let conn = self.pool.get()?;
+        let attempts: Option<u32> = conn
+            .query_row(
+                "SELECT attempts FROM outbox WHERE msg_id = ?1",
+                [msg_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(attempts) = attempts else {
+            return Ok(());
+        };
+        let attempts = attempts.saturating_add(1);
+        let next_attempt_at = now.saturating_add(backoff(attempts).as_secs());
+        conn.execute(
+            "UPDATE outbox SET attempts = ?2, last_error = ?3, next_attempt_at = ?4
+             WHERE msg_id = ?1",
+            (msg_id, attempts, error, next_attempt_at as i64),
+        )?;
+        Ok(())
+    }
+
+    /// Moves an entry to the dead-letter state after the relay rejected it.
+    pub fn dead_letter(&self, msg_id: &MessageId, error: &str) -> Result<()> {
+        let conn = self.pool.get()?;
+        conn.execute(
+            "UPDATE outbox SET state = ?2, attempts = attempts + 1, last_error = ?3
+             WHERE msg_id = ?1",
+            (msg_id, OutboxState::DeadLetter.as_str(), error),
+        )?;
+        Ok(())
+    }
+
+    /// Returns a dead-lettered entry to the queue for immediate replay.
+    pub fn requeue(&self, msg_id: &MessageId, now: u64) -> Result<bool> {
+        let conn = self.pool.get()?;
+        let updated = conn.execute(
+            "UPDATE outbox SET state = ?2, next_attempt_at = ?3 WHERE msg_id = ?1",
+            (msg_id, OutboxState::Pending.as_str(), now as i64),
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Drops pending entries whose TTL has passed. Returns how many were removed.
+    pub fn expire(&self, now: u64) -> Result<usize> {
+        let conn = self.pool.get()?;
+        Ok(conn.execute(
+            "DELETE FROM outbox WHERE state = 'pending' AND expires_at <= ?1",
+            [now as i64],
+        )?)
+    }
+
+    fn select(&self, filter: &str, params: impl rusqlite::Params) -> Result<Vec<OutboxEntry>> {
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(&format!("{} {} ORDER BY rowid", SELECT_ENTRY, filter))?;
+        let rows = stmt.query_map(params, read_row)?;
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(into_entry(row?)?);
+        }
+        Ok(entries)
+    }
+}
+
+/// Delay before retry number `attempts` (1-based): doubles from
+/// [`INITIAL_BACKOFF`] up to [`MAX_BACKOFF`].
+pub fn backoff(attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(31);
+    INITIAL_BACKOFF
+        .saturating_mul(1u32 << exponent)
+        .min(MAX_BACKOFF)
+}
+
+fn expires_at(envelope: &MessageEnvelope) -> u64 {
+    envelope.timestamp.saturating_add(envelope.ttl as u64)
+}
+
+const SELECT_ENTRY: &str =
+    "SELECT envelope, state, attempts, last_error, next_attempt_at, expires_at FROM outbox";
+
+type Row = (Vec<u8>, String, u32, Option<String>, i64, i64);
+
+fn read_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Row> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+    ))
+}
+
+fn into_entry(
+    (envelope, state, attempts, last_error, next_attempt_at, expires_at): Row,
+) -> Result<OutboxEntry> {
+    Ok(OutboxEntry {
+        envelope: MessageEnvelope::from_bytes(&envelope)?,
+        state: OutboxState::parse(&state),
+        attempts,
+        last_error,
+        next_attempt_at: next_attempt_at as u64,
+        expires_at: expires_at as u64,
+    })
+}
+
+/// Creates the `outbox` table. Outboxes written by earlier releases stored
+/// unsigned envelope fields that cannot be replayed; they are kept aside as
+/// `outbox_legacy` for manual inspection. Envelopes queued before message ids
+/// became 16 bytes no longer decode; their table is kept aside as `outbox_v1`.
+fn init_schema(conn: &rusqlite::Connection) -> Result<()> {
+    let columns = conn
+        .prepare("PRAGMA table_info(outbox)")?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if !columns.is_empty() && !columns.iter().any(|c| c == "envelope") {
+        conn.execute("DROP TABLE IF EXISTS outbox_legacy", ())?;
+        conn.execute("ALTER TABLE outbox RENAME TO outbox_legacy", ())?;
+    } else if !columns.is_empty() {
+        let v1: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM outbox WHERE LENGTH(msg_id) != 16)",
+            [],
+            |row| row.get(0),
+        )?;
+        if v1 {
+            conn.execute("DROP TABLE IF EXISTS outbox_v1", ())?;
+            conn.execute("ALTER TABLE outbox RENAME TO outbox_v1", ())?;
+        }
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS outbox (
+        msg_id          BLOB PRIMARY KEY,
+        envelope        BLOB NOT NULL,
+        state           TEXT NOT NULL DEFAULT 'pending',
+        attempts        INTEGER NOT NULL DEFAULT 0,
+        last_error      TEXT,
+        next_attempt_at INTEGER NOT NULL DEFAULT 0,
+        expires_at      INTEGER NOT NULL
+    )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_outbox_due ON outbox (state, next_attempt_at)",
+        (),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_keypair;
+
+    fn signed(ttl: u32) -> MessageEnvelope {
+        let (recipient, _) = gen_keypair();
+        let (sender_pub, sender_priv) = gen_keypair();
+        let mut envelope =
+            MessageEnvelope::new("alice".to_string(), recipient, sender_pub, b"hi".to_vec(), ttl);
+        envelope.sign(&sender_priv);
+        envelope
+    }
+
+    #[test]
+    fn test_outbox_stores_signed_envelope() {
+        let outbox = Outbox::in_memory().unwrap();
+        let envelope = signed(60);
+        outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+        outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+
+        let due = outbox.due(envelope.timestamp).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].envelope.signature, envelope.signature);
+        assert!(due[0].envelope.verify());
+        assert_eq!(due[0].expires_at, envelope.timestamp + 60);
+        assert_eq!(due[0].state, OutboxState::Pending);
+
+        assert!(outbox.remove(&envelope.msg_id).unwrap());
+        assert!(outbox.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_outbox_backoff_defers_retries() {
+        let outbox = Outbox::in_memory().unwrap();
+        let envelope = signed(3600);
+        let now = envelope.timestamp;
+        outbox.enqueue(&envelope, now).unwrap();
+
+        outbox.record_failure(&envelope.msg_id, "timed out", now).unwrap();
+        assert!(outbox.due(now).unwrap().is_empty());
+        assert_eq!(outbox.due(now + 1).unwrap().len(), 1);
+
+        outbox.record_failure(&envelope.msg_id, "timed out", now).unwrap();
+        assert!(outbox.due(now + 1).unwrap().is_empty());
+        let entry = outbox.get(&envelope.msg_id).unwrap().unwrap();
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(entry.next_attempt_at, now + 2);
+        assert_eq!(entry.last_error.as_deref(), Some("timed out"));
+    }
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(16));
+        assert_eq!(backoff(40), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_outbox_expires_by_ttl() {
+        let outbox = Outbox::in_memory().unwrap();
+        let envelope = signed(10);
+        outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+
+        assert!(outbox.due(envelope.timestamp + 10).unwrap().is_empty());
+        assert_eq!(outbox.expire(envelope.timestamp + 10).unwrap(), 1);
+        assert!(outbox.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_outbox_dead_letter_and_requeue() {
+        let outbox = Outbox::in_memory().unwrap();
+        let envelope = signed(3600);
+        outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+
+        outbox.dead_letter(&envelope.msg_id, "Invalid signature").unwrap();
+        assert!(outbox.due(envelope.timestamp).unwrap().is_empty());
+        let dead = outbox.dead_letters().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].state, OutboxState::DeadLetter);
+        assert_eq!(dead[0].last_error.as_deref(), Some("Invalid signature"));
+
+        // Dead letters survive expiry so they can still be inspected.
+        assert_eq!(outbox.expire(u64::MAX / 2).unwrap(), 0);
+
+        assert!(outbox.requeue(&envelope.msg_id, envelope.timestamp).unwrap());
+        assert_eq!(outbox.due(envelope.timestamp).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_outbox_migrates_legacy_table() {
+        let outbox = Outbox::in_memory().unwrap();
+        {
+            let conn = outbox.pool.get().unwrap();
+            conn.execute("DROP TABLE outbox", ()).unwrap();
+            conn.execute(
+                "CREATE TABLE outbox (msg_id BLOB PRIMARY KEY, sender TEXT NOT NULL, payload BLOB NOT NULL)",
+                (),
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO outbox VALUES (x'01', 'alice', x'02')",
+                (),
+            )
+            .unwrap();
+            init_schema(&conn).unwrap();
+            let legacy: i64 = conn
+                .query_row("SELECT COUNT(*) FROM outbox_legacy", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(legacy, 1);
+        }
+        let envelope = signed(60);
+        outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+        assert_eq!(outbox.pending().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_outbox_sets_aside_v1_message_ids() {
+        let outbox = Outbox::in_memory().unwrap();
+        let envelope = signed(60);
+        outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+        {
+            let conn = outbox.pool.get().unwrap();
+            init_schema(&conn).unwrap();
+            conn.execute(
+                "INSERT INTO outbox (msg_id, envelope, expires_at) VALUES (zeroblob(32), x'00', 0)",
+                (),
+            )
+            .unwrap();
+            init_schema(&conn).unwrap();
+            let v1: i64 = conn
+                .query_row("SELECT COUNT(*) FROM outbox_v1", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(v1, 2);
+        }
+        assert!(outbox.pending().unwrap().is_empty());
+    }
+}
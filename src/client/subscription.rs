@@ -0,0 +1,88 @@
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use futures::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+use crate::protocol::Receipt;
+use crate::MessageEnvelope;
+
+/// Envelopes buffered per subscription before pushes wait for the consumer.
+pub(crate) const SUBSCRIPTION_BUFFER: usize = 256;
+
+/// Stream of envelopes the relay pushes for one recipient, returned by
+/// [`crate::RelayClient::subscribe`].
+///
+/// Ends when the client is closed or dropped.
+pub struct Subscription {
+    recipient: [u8; PUBLIC_KEY_LENGTH],
+    rx: mpsc::Receiver<MessageEnvelope>,
+}
+
+impl Subscription {
+    pub(crate) fn new(
+        recipient: [u8; PUBLIC_KEY_LENGTH],
+        rx: mpsc::Receiver<MessageEnvelope>,
+    ) -> Self {
+        Self { recipient, rx }
+    }
+
+    /// The recipient this subscription receives messages for.
+    pub fn recipient(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.recipient
+    }
+}
+
+impl Stream for Subscription {
+    type Item = MessageEnvelope;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Client-side end of a subscription, used to route pushed envelopes and to
+/// re-subscribe after a reconnect.
+#[derive(Clone)]
+pub(crate) struct Subscriber {
+    pub signing_key: [u8; SECRET_KEY_LENGTH],
+    pub recipient: [u8; PUBLIC_KEY_LENGTH],
+    pub tx: mpsc::Sender<MessageEnvelope>,
+}
+
+/// Stream of receipts the relay pushes for messages from one sender,
+/// returned by [`crate::RelayClient::watch_receipts`].
+///
+/// Ends when the client is closed or dropped.
+pub struct ReceiptStream {
+    sender_key: [u8; PUBLIC_KEY_LENGTH],
+    rx: mpsc::Receiver<Receipt>,
+}
+
+impl ReceiptStream {
+    pub(crate) fn new(sender_key: [u8; PUBLIC_KEY_LENGTH], rx: mpsc::Receiver<Receipt>) -> Self {
+        Self { sender_key, rx }
+    }
+
+    /// The sender whose messages this stream reports on.
+    pub fn sender_key(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.sender_key
+    }
+}
+
+impl Stream for ReceiptStream {
+    type Item = Receipt;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Client-side end of a receipt watch, used to route pushed receipts and to
+/// renew the watch after a reconnect.
+#[derive(Clone)]
+pub(crate) struct ReceiptWatcher {
+    pub signing_key: [u8; SECRET_KEY_LENGTH],
+    pub sender_key: [u8; PUBLIC_KEY_LENGTH],
+    pub tx: mpsc::Sender<Receipt>,
+}
@@ -1,11 +1,61 @@
-use crate::{errors::QightError,keys_auth::key_fn::{gen_key, sign_message, verify_message}};
+use crate::{
+    envelope::MessageId,
+    errors::QightError,
+    keys_auth::key_fn::{
+        gen_x25519_keypair, sign_message, verify_message, x25519_public_from_ed25519,
+        x25519_secret_from_ed25519, x25519_shared_secret,
+    },
+};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH, SECRET_KEY_LENGTH};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
 use wincode::{SchemaRead, SchemaWrite};
 
+/// Domain separation tag prefixed to every canonical signing encoding.
+const SIGNING_DOMAIN: &[u8] = b"qight-envelope";
+
+/// Version of the canonical signing encoding produced by [`MessageEnvelope::signing_bytes`].
+/// Version 1 carried 32-byte message ids.
+pub const SIGNING_VERSION: u8 = 2;
+
+/// Length of the random message ids of version 1 envelopes.
+const V1_MSG_ID_LENGTH: usize = 32;
+
+/// Bytes the encoding from [`MessageEnvelope::to_bytes`] adds to the
+/// `sender` and `payload` bytes: the fixed-size fields and two u64 lengths.
+pub const ENVELOPE_OVERHEAD: usize =
+    MessageId::LEN + 8 + PUBLIC_KEY_LENGTH * 2 + 8 + 4 + 8 + SIGNATURE_LENGTH;
+
+/// Marker prefixed to sealed payloads produced by [`MessageEnvelope::encrypt_for`].
+const SEALED_MAGIC: &[u8; 4] = b"QEC1";
+
+/// HKDF `info` string for payload encryption keys.
+const SEALED_KDF_INFO: &[u8] = b"qight-payload-v1";
+
+const SEALED_NONCE_LENGTH: usize = 12;
+
+/// Sealed payload header: magic, ephemeral X25519 public key, AEAD nonce.
+const SEALED_HEADER_LENGTH: usize = SEALED_MAGIC.len() + 32 + SEALED_NONCE_LENGTH;
+
+/// Which bytes an envelope signature was computed over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// Canonical v1 encoding of an envelope with a 32-byte message id. Such
+    /// envelopes no longer decode as a [`MessageEnvelope`].
+    V1,
+    /// Canonical v2 encoding covering every header field and the payload.
+    V2,
+    /// Signature covering only the payload, from before the canonical
+    /// encodings. Headers are unauthenticated.
+    LegacyPayload,
+}
+
 #[repr(C)]
 #[derive(SchemaRead, SchemaWrite, Debug,Clone)]
 pub struct MessageEnvelope {
-    pub msg_id: [u8;PUBLIC_KEY_LENGTH],
+    pub msg_id: MessageId,
     pub sender: String,
     pub sender_key:[u8;PUBLIC_KEY_LENGTH],
     pub recipient: [u8;PUBLIC_KEY_LENGTH],
@@ -27,9 +77,8 @@ impl MessageEnvelope {
         payload: Vec<u8>,
         ttl: u32,
     ) -> MessageEnvelope {
-        let message_id = gen_key();
-        let message_object = MessageEnvelope {
-            msg_id: message_id,
+        MessageEnvelope {
+            msg_id: MessageId::generate(),
             sender,
             sender_key,
             recipient,
@@ -37,28 +86,196 @@ impl MessageEnvelope {
             timestamp: chrono::Utc::now().timestamp() as u64,
             ttl,
             signature: [0u8; SIGNATURE_LENGTH],
-        };
-        message_object
+        }
     }
 
+    /// Like [`MessageEnvelope::new`], but with the deterministic
+    /// [`MessageId::for_content`] id, so a relay accepts the same payload
+    /// from the same sender to the same recipient only once while it is
+    /// stored or remembered. Sealing the payload afterwards keeps this id,
+    /// which then no longer matches the sealed content.
+    pub fn new_content_addressed(
+        sender: String,
+        recipient: [u8; PUBLIC_KEY_LENGTH],
+        sender_key: [u8; PUBLIC_KEY_LENGTH],
+        payload: Vec<u8>,
+        ttl: u32,
+    ) -> MessageEnvelope {
+        let mut envelope = MessageEnvelope::new(sender, recipient, sender_key, payload, ttl);
+        envelope.msg_id = MessageId::for_content(&sender_key, &recipient, &envelope.payload);
+        envelope
+    }
+
+    /// Whether `msg_id` is the [`MessageId::for_content`] id of this
+    /// envelope's sender, recipient and payload.
+    pub fn has_content_id(&self) -> bool {
+        self.msg_id.is_content_addressed()
+            && self.msg_id == MessageId::for_content(&self.sender_key, &self.recipient, &self.payload)
+    }
+
+    /// Canonical bytes covered by the envelope signature.
+    ///
+    /// Layout (all integers big-endian): `"qight-envelope"`, version byte,
+    /// `msg_id`, sender length (u32) and UTF-8 bytes, `sender_key`,
+    /// `recipient`, `timestamp` (u64), `ttl` (u32), payload length (u64) and
+    /// payload bytes.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            SIGNING_DOMAIN.len() + 1 + MessageId::LEN + PUBLIC_KEY_LENGTH * 2 + 4 + self.sender.len()
+                + 8 + 4 + 8 + self.payload.len(),
+        );
+        buf.extend_from_slice(SIGNING_DOMAIN);
+        buf.push(SIGNING_VERSION);
+        buf.extend_from_slice(self.msg_id.as_bytes());
+        buf.extend_from_slice(&(self.sender.len() as u32).to_be_bytes());
+        buf.extend_from_slice(self.sender.as_bytes());
+        buf.extend_from_slice(&self.sender_key);
+        buf.extend_from_slice(&self.recipient);
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.ttl.to_be_bytes());
+        buf.extend_from_slice(&(self.payload.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    /// Signs the canonical encoding of all header fields and the payload.
     pub fn sign(&mut self, private_key: &[u8; SECRET_KEY_LENGTH]) {
-        self.signature = sign_message(private_key, &self.payload);
+        self.signature = sign_message(private_key, &self.signing_bytes());
     }
 
+    /// Checks the signature against the canonical v2 encoding.
+    ///
+    /// Legacy payload-only signatures are rejected; use
+    /// [`MessageEnvelope::signature_scheme`] to identify them.
     pub fn verify(&self) -> bool {
-        verify_message(&self.sender_key, &self.payload, &self.signature)
+        verify_message(&self.sender_key, &self.signing_bytes(), &self.signature)
     }
 
-    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+    /// SHA-256 over the canonical encoding and the signature. A relay uses
+    /// it to tell a retried SEND from a different message reusing `msg_id`.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.signing_bytes());
+        hasher.update(self.signature);
+        hasher.finalize().into()
+    }
+
+    /// Reports which scheme the signature verifies under, if any. Never
+    /// [`SignatureScheme::V1`]; use [`MessageEnvelope::signature_scheme_of`]
+    /// on the encoded envelope for those.
+    pub fn signature_scheme(&self) -> Option<SignatureScheme> {
+        if self.verify() {
+            Some(SignatureScheme::V2)
+        } else if verify_message(&self.sender_key, &self.payload, &self.signature) {
+            Some(SignatureScheme::LegacyPayload)
+        } else {
+            None
+        }
+    }
+
+    /// Seals the payload for `recipient` and sets it as the envelope recipient.
+    ///
+    /// An ephemeral X25519 key is agreed with the recipient's Ed25519 identity
+    /// (converted to X25519), the shared secret is expanded with HKDF-SHA256
+    /// and the payload is sealed with ChaCha20-Poly1305. `msg_id`,
+    /// `sender_key` and `recipient` are bound as associated data. Only the
+    /// header stays readable by the relay.
+    ///
+    /// Any existing signature is cleared; call [`MessageEnvelope::sign`]
+    /// afterwards.
+    pub fn encrypt_for(&mut self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<(), QightError> {
+        let recipient_x = x25519_public_from_ed25519(recipient).ok_or(QightError::InvalidPublicKey)?;
+        let (ephemeral_public, ephemeral_secret) = gen_x25519_keypair();
+        let shared = x25519_shared_secret(&ephemeral_secret, &recipient_x)
+            .ok_or(QightError::InvalidPublicKey)?;
+
+        self.recipient = *recipient;
+        let cipher = payload_cipher(&shared, &ephemeral_public, &recipient_x)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = self.sealed_aad();
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: &self.payload, aad: &aad })
+            .map_err(|_| QightError::CannotEncryptPayload)?;
+
+        let mut sealed = Vec::with_capacity(SEALED_HEADER_LENGTH + ciphertext.len());
+        sealed.extend_from_slice(SEALED_MAGIC);
+        sealed.extend_from_slice(&ephemeral_public);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        self.payload = sealed;
+        self.signature = [0u8; SIGNATURE_LENGTH];
+        Ok(())
+    }
+
+    /// Opens a payload sealed with [`MessageEnvelope::encrypt_for`] using the
+    /// recipient's Ed25519 secret key and returns the plaintext.
+    pub fn decrypt(&self, own_secret: &[u8; SECRET_KEY_LENGTH]) -> Result<Vec<u8>, QightError> {
+        if !self.is_encrypted() {
+            return Err(QightError::PayloadNotEncrypted);
+        }
+
+        let (header, ciphertext) = self.payload.split_at(SEALED_HEADER_LENGTH);
+        let ephemeral_public: [u8; 32] = header[SEALED_MAGIC.len()..SEALED_MAGIC.len() + 32]
+            .try_into()
+            .map_err(|_| QightError::CannotDecryptPayload)?;
+        let nonce = Nonce::from_slice(&header[SEALED_MAGIC.len() + 32..]);
+
+        let own_x = x25519_secret_from_ed25519(own_secret);
+        let recipient_x =
+            x25519_public_from_ed25519(&self.recipient).ok_or(QightError::InvalidPublicKey)?;
+        let shared = x25519_shared_secret(&own_x, &ephemeral_public)
+            .ok_or(QightError::CannotDecryptPayload)?;
+
+        let cipher = payload_cipher(&shared, &ephemeral_public, &recipient_x)?;
+        let aad = self.sealed_aad();
+        let plaintext = cipher
+            .decrypt(nonce, Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| QightError::CannotDecryptPayload)?;
+        Ok(plaintext)
+    }
+
+    /// Whether the payload looks like the output of [`MessageEnvelope::encrypt_for`].
+    pub fn is_encrypted(&self) -> bool {
+        self.payload.len() > SEALED_HEADER_LENGTH && self.payload.starts_with(SEALED_MAGIC)
+    }
+
+    fn sealed_aad(&self) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(MessageId::LEN + PUBLIC_KEY_LENGTH * 2);
+        aad.extend_from_slice(self.msg_id.as_bytes());
+        aad.extend_from_slice(&self.sender_key);
+        aad.extend_from_slice(&self.recipient);
+        aad
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, QightError> {
         let bytes = 
             wincode::serialize(self).map_err(|_| QightError::CannotSerializeBytes)?;
         Ok(bytes)
     }
 
-    pub  fn from_bytes(bytes: &[u8]) -> Result<MessageEnvelope, anyhow::Error> {
-        let bytes: MessageEnvelope =
-            wincode::deserialize(bytes).map_err(|_| QightError::CannotDeserialzeBytes)?;
-        Ok(bytes)
+    /// Decodes an envelope, reporting a correctly signed version 1 envelope
+    /// as [`QightError::V1Envelope`] rather than as undecodable.
+    pub  fn from_bytes(bytes: &[u8]) -> Result<MessageEnvelope, QightError> {
+        match wincode::deserialize(bytes) {
+            Ok(envelope) => Ok(envelope),
+            Err(_) if EnvelopeV1::decode(bytes).is_some_and(|v1| v1.verify()) => {
+                Err(QightError::V1Envelope)
+            }
+            Err(_) => Err(QightError::CannotDeserialzeBytes),
+        }
+    }
+
+    /// Reports which scheme the signature of an encoded envelope verifies
+    /// under, including version 1 envelopes that [`MessageEnvelope::from_bytes`]
+    /// refuses.
+    pub fn signature_scheme_of(bytes: &[u8]) -> Option<SignatureScheme> {
+        match wincode::deserialize::<MessageEnvelope>(bytes) {
+            Ok(envelope) => envelope.signature_scheme(),
+            Err(_) => EnvelopeV1::decode(bytes)
+                .filter(EnvelopeV1::verify)
+                .map(|_| SignatureScheme::V1),
+        }
     }
 
     pub fn is_expired(&self, current_time: u64) -> bool {
@@ -66,11 +283,85 @@ impl MessageEnvelope {
         current_time > message_time + self.ttl as u64
     }
 
+    /// Checks the signature and the TTL at `current_time`, reporting the
+    /// first failure as [`QightError::InvalidSignature`] or
+    /// [`QightError::Expired`].
+    pub fn validate(&self, current_time: u64) -> Result<(), QightError> {
+        if !self.verify() {
+            return Err(QightError::InvalidSignature);
+        }
+        if self.is_expired(current_time) {
+            return Err(QightError::Expired);
+        }
+        Ok(())
+    }
+
     pub fn display(&self) -> &MessageEnvelope{
-        return self
+        self
+    }
+}
+
+/// Wire layout of a version 1 envelope, kept to recognise its signature.
+#[derive(SchemaRead, SchemaWrite)]
+struct EnvelopeV1 {
+    msg_id: [u8; V1_MSG_ID_LENGTH],
+    sender: String,
+    sender_key: [u8; PUBLIC_KEY_LENGTH],
+    recipient: [u8; PUBLIC_KEY_LENGTH],
+    timestamp: u64,
+    ttl: u32,
+    payload: Vec<u8>,
+    signature: [u8; SIGNATURE_LENGTH],
+}
+
+impl EnvelopeV1 {
+    fn decode(bytes: &[u8]) -> Option<EnvelopeV1> {
+        wincode::deserialize(bytes).ok()
+    }
+
+    /// The version 1 canonical encoding: the v2 layout with version byte 1
+    /// and a 32-byte `msg_id`.
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            SIGNING_DOMAIN.len() + 1 + V1_MSG_ID_LENGTH + PUBLIC_KEY_LENGTH * 2 + 4
+                + self.sender.len() + 8 + 4 + 8 + self.payload.len(),
+        );
+        buf.extend_from_slice(SIGNING_DOMAIN);
+        buf.push(1);
+        buf.extend_from_slice(&self.msg_id);
+        buf.extend_from_slice(&(self.sender.len() as u32).to_be_bytes());
+        buf.extend_from_slice(self.sender.as_bytes());
+        buf.extend_from_slice(&self.sender_key);
+        buf.extend_from_slice(&self.recipient);
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.ttl.to_be_bytes());
+        buf.extend_from_slice(&(self.payload.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    fn verify(&self) -> bool {
+        verify_message(&self.sender_key, &self.signing_bytes(), &self.signature)
     }
 }
 
+/// Expands an X25519 shared secret into the payload AEAD.
+fn payload_cipher(
+    shared: &[u8; 32],
+    ephemeral_public: &[u8; 32],
+    recipient_x: &[u8; 32],
+) -> Result<ChaCha20Poly1305, QightError> {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public);
+    salt[32..].copy_from_slice(recipient_x);
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(SEALED_KDF_INFO, &mut key)
+        .map_err(|_| QightError::CannotEncryptPayload)?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,6 +407,28 @@ mod tests {
         assert!(envelope.is_expired(expired_time));
     }
 
+    #[test]
+    fn test_envelope_validate_reports_typed_errors() {
+        let (recipient_key, _) = gen_keypair();
+        let (sender_key, sender_priv) = gen_keypair();
+        let mut envelope = MessageEnvelope::new(
+            "test".to_string(),
+            recipient_key,
+            sender_key,
+            vec![],
+            100,
+        );
+        assert_eq!(envelope.validate(envelope.timestamp), Err(QightError::InvalidSignature));
+
+        envelope.sign(&sender_priv);
+        assert_eq!(envelope.validate(envelope.timestamp + 50), Ok(()));
+        assert_eq!(envelope.validate(envelope.timestamp + 150), Err(QightError::Expired));
+        assert_eq!(
+            MessageEnvelope::from_bytes(b"garbage").unwrap_err(),
+            QightError::CannotDeserialzeBytes
+        );
+    }
+
     #[test]
     fn test_envelope_from_bytes_invalid() {
         let invalid_bytes = b"not an envelope";
@@ -145,4 +458,199 @@ mod tests {
             tampered.payload = b"tampered payload".to_vec();
             assert!(!tampered.verify());
         }
+
+        #[test]
+        fn test_envelope_signature_covers_headers() {
+            let (recipient_key, _) = gen_keypair();
+            let (other_recipient, _) = gen_keypair();
+            let (sender_key, sender_priv) = gen_keypair();
+
+            let mut envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"test payload".to_vec(),
+                3600,
+            );
+            envelope.sign(&sender_priv);
+
+            let mut tampered = envelope.clone();
+            tampered.recipient = other_recipient;
+            assert!(!tampered.verify());
+
+            let mut tampered = envelope.clone();
+            tampered.sender = "mallory".to_string();
+            assert!(!tampered.verify());
+
+            let mut tampered = envelope.clone();
+            tampered.timestamp += 1;
+            assert!(!tampered.verify());
+
+            let mut tampered = envelope.clone();
+            tampered.ttl = u32::MAX;
+            assert!(!tampered.verify());
+
+            let mut tampered = envelope.clone();
+            tampered.msg_id = MessageId::generate();
+            assert!(!tampered.verify());
+        }
+
+        #[test]
+        fn test_envelope_digest_covers_content_and_signature() {
+            let (recipient_key, _) = gen_keypair();
+            let (sender_key, sender_priv) = gen_keypair();
+            let mut envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"test payload".to_vec(),
+                3600,
+            );
+            let unsigned = envelope.digest();
+            envelope.sign(&sender_priv);
+            assert_ne!(envelope.digest(), unsigned);
+
+            // Ed25519 signatures are deterministic, so re-signing is a resend.
+            let mut resigned = envelope.clone();
+            resigned.sign(&sender_priv);
+            assert_eq!(resigned.digest(), envelope.digest());
+
+            let mut other = envelope.clone();
+            other.payload = b"other payload".to_vec();
+            other.sign(&sender_priv);
+            assert_ne!(other.digest(), envelope.digest());
+        }
+
+        #[test]
+        fn test_envelope_legacy_signature_identified() {
+            let (recipient_key, _) = gen_keypair();
+            let (sender_key, sender_priv) = gen_keypair();
+
+            let mut envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"test payload".to_vec(),
+                3600,
+            );
+            envelope.signature = sign_message(&sender_priv, &envelope.payload);
+
+            assert!(!envelope.verify());
+            assert_eq!(envelope.signature_scheme(), Some(SignatureScheme::LegacyPayload));
+
+            envelope.sign(&sender_priv);
+            assert_eq!(envelope.signature_scheme(), Some(SignatureScheme::V2));
+
+            envelope.signature = [0u8; SIGNATURE_LENGTH];
+            assert_eq!(envelope.signature_scheme(), None);
+        }
+
+        #[test]
+        fn test_envelope_overhead_matches_encoding() {
+            let (recipient_key, _) = gen_keypair();
+            let (sender_key, _) = gen_keypair();
+            let envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"test payload".to_vec(),
+                3600,
+            );
+            assert_eq!(
+                envelope.to_bytes().unwrap().len(),
+                ENVELOPE_OVERHEAD + envelope.sender.len() + envelope.payload.len()
+            );
+        }
+
+        #[test]
+        fn test_v1_envelope_identified() {
+            let (recipient_key, _) = gen_keypair();
+            let (sender_key, sender_priv) = gen_keypair();
+
+            let mut v1 = EnvelopeV1 {
+                msg_id: [7u8; V1_MSG_ID_LENGTH],
+                sender: "test_sender".to_string(),
+                sender_key,
+                recipient: recipient_key,
+                timestamp: 1_700_000_000,
+                ttl: 3600,
+                payload: b"test payload".to_vec(),
+                signature: [0u8; SIGNATURE_LENGTH],
+            };
+            let unsigned = wincode::serialize(&v1).unwrap();
+            v1.signature = sign_message(&sender_priv, &v1.signing_bytes());
+            let signed = wincode::serialize(&v1).unwrap();
+
+            assert_eq!(MessageEnvelope::signature_scheme_of(&signed), Some(SignatureScheme::V1));
+            assert_eq!(MessageEnvelope::from_bytes(&signed).unwrap_err(), QightError::V1Envelope);
+
+            assert_eq!(MessageEnvelope::signature_scheme_of(&unsigned), None);
+            assert_eq!(
+                MessageEnvelope::from_bytes(&unsigned).unwrap_err(),
+                QightError::CannotDeserialzeBytes
+            );
+        }
+    
+        #[test]
+        fn test_envelope_encrypt_decrypt_round_trip() {
+            let (recipient_key, recipient_priv) = gen_keypair();
+            let (sender_key, sender_priv) = gen_keypair();
+
+            let mut envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"secret payload".to_vec(),
+                3600,
+            );
+            envelope.encrypt_for(&recipient_key).unwrap();
+            envelope.sign(&sender_priv);
+
+            assert!(envelope.is_encrypted());
+            assert!(envelope.verify());
+            assert_ne!(envelope.payload, b"secret payload".to_vec());
+            assert_eq!(envelope.decrypt(&recipient_priv).unwrap(), b"secret payload".to_vec());
+        }
+
+        #[test]
+        fn test_envelope_decrypt_rejects_wrong_key_and_tampering() {
+            let (recipient_key, recipient_priv) = gen_keypair();
+            let (_, other_priv) = gen_keypair();
+            let (sender_key, _) = gen_keypair();
+
+            let mut envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"secret payload".to_vec(),
+                3600,
+            );
+            envelope.encrypt_for(&recipient_key).unwrap();
+
+            assert!(envelope.decrypt(&other_priv).is_err());
+
+            let mut tampered = envelope.clone();
+            let last = tampered.payload.len() - 1;
+            tampered.payload[last] ^= 0x01;
+            assert!(tampered.decrypt(&recipient_priv).is_err());
+
+            let mut tampered = envelope.clone();
+            tampered.sender_key = gen_keypair().0;
+            assert!(tampered.decrypt(&recipient_priv).is_err());
+        }
+
+        #[test]
+        fn test_envelope_decrypt_plaintext_fails() {
+            let (recipient_key, recipient_priv) = gen_keypair();
+            let (sender_key, _) = gen_keypair();
+            let envelope = MessageEnvelope::new(
+                "test_sender".to_string(),
+                recipient_key,
+                sender_key,
+                b"plain".to_vec(),
+                3600,
+            );
+            assert!(!envelope.is_encrypted());
+            assert_eq!(envelope.decrypt(&recipient_priv), Err(QightError::PayloadNotEncrypted));
+        }
     }
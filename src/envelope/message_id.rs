@@ -0,0 +1,182 @@
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use wincode::{SchemaRead, SchemaWrite};
+
+use crate::errors::QightError;
+
+/// Domain separation tag for [`MessageId::for_content`].
+const CONTENT_ID_DOMAIN: &[u8] = b"qight-msg-id";
+
+/// 128-bit message identifier, laid out as a UUID.
+///
+/// [`MessageId::generate`] makes time-ordered UUIDv7 ids: ids sort by
+/// creation time, byte-wise and as strings. [`MessageId::for_content`] makes
+/// deterministic UUIDv8 ids from a message's content, so sending the same
+/// content twice yields the same id.
+///
+/// Displays as a hyphenated UUID; parses from that or from 32 hex digits.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, SchemaRead, SchemaWrite)]
+pub struct MessageId([u8; MessageId::LEN]);
+
+impl MessageId {
+    /// Encoded length in bytes.
+    pub const LEN: usize = 16;
+
+    /// A new UUIDv7 id. Ids generated by one process are strictly
+    /// increasing.
+    pub fn generate() -> Self {
+        MessageId(Uuid::now_v7().into_bytes())
+    }
+
+    /// The UUIDv8 id of a message from `sender_key` to `recipient` carrying
+    /// `payload`: SHA-256 over a domain tag, both keys, the payload length
+    /// (u64, big-endian) and the payload, truncated to 16 bytes with the
+    /// UUID version and variant bits set.
+    pub fn for_content(
+        sender_key: &[u8; PUBLIC_KEY_LENGTH],
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        payload: &[u8],
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(CONTENT_ID_DOMAIN);
+        hasher.update(sender_key);
+        hasher.update(recipient);
+        hasher.update((payload.len() as u64).to_be_bytes());
+        hasher.update(payload);
+        let digest = hasher.finalize();
+        let mut bytes = [0u8; MessageId::LEN];
+        bytes.copy_from_slice(&digest[..MessageId::LEN]);
+        MessageId(Uuid::new_v8(bytes).into_bytes())
+    }
+
+    pub const fn from_bytes(bytes: [u8; MessageId::LEN]) -> Self {
+        MessageId(bytes)
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; MessageId::LEN] {
+        &self.0
+    }
+
+    /// Creation time in Unix milliseconds, for ids made by
+    /// [`MessageId::generate`].
+    pub fn timestamp_ms(&self) -> Option<u64> {
+        let (secs, nanos) = self.as_uuid().get_timestamp()?.to_unix();
+        Some(secs * 1000 + u64::from(nanos) / 1_000_000)
+    }
+
+    /// Whether the id was derived with [`MessageId::for_content`].
+    pub fn is_content_addressed(&self) -> bool {
+        self.as_uuid().get_version_num() == 8
+    }
+
+    /// 32 lowercase hex digits, without hyphens.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, QightError> {
+        let bytes = hex::decode(hex).map_err(|_| QightError::InvalidMessageId)?;
+        let bytes = bytes.try_into().map_err(|_| QightError::InvalidMessageId)?;
+        Ok(MessageId(bytes))
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        Uuid::from_bytes(self.0)
+    }
+}
+
+impl From<Uuid> for MessageId {
+    fn from(uuid: Uuid) -> Self {
+        MessageId(uuid.into_bytes())
+    }
+}
+
+impl From<MessageId> for Uuid {
+    fn from(id: MessageId) -> Self {
+        id.as_uuid()
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.as_uuid().hyphenated(), f)
+    }
+}
+
+impl fmt::Debug for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MessageId({})", self)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = QightError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::try_parse(s)
+            .map(MessageId::from)
+            .map_err(|_| QightError::InvalidMessageId)
+    }
+}
+
+impl ToSql for MessageId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(&self.0[..]))
+    }
+}
+
+impl FromSql for MessageId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let blob = value.as_blob()?;
+        let bytes = blob.try_into().map_err(|_| FromSqlError::InvalidBlobSize {
+            expected_size: MessageId::LEN,
+            blob_size: blob.len(),
+        })?;
+        Ok(MessageId(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_ids_are_time_ordered() {
+        let ids: Vec<MessageId> = (0..100).map(|_| MessageId::generate()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.windows(2).all(|pair| pair[0].to_string() < pair[1].to_string()));
+
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let created = ids[0].timestamp_ms().unwrap();
+        assert!(now_ms.abs_diff(created) < 5_000);
+        assert!(!ids[0].is_content_addressed());
+    }
+
+    #[test]
+    fn test_text_forms_round_trip() {
+        let id = MessageId::generate();
+        let text = id.to_string();
+        assert_eq!(text.len(), 36);
+        assert_eq!(text.parse::<MessageId>().unwrap(), id);
+        assert_eq!(id.to_hex().parse::<MessageId>().unwrap(), id);
+        assert_eq!(MessageId::from_hex(&id.to_hex()).unwrap(), id);
+        assert_eq!(format!("{:?}", id), format!("MessageId({})", text));
+
+        assert_eq!("not-an-id".parse::<MessageId>(), Err(QightError::InvalidMessageId));
+        assert_eq!(MessageId::from_hex("abcd"), Err(QightError::InvalidMessageId));
+    }
+
+    #[test]
+    fn test_content_ids_are_deterministic() {
+        let id = MessageId::for_content(&[1; 32], &[2; 32], b"hello");
+        assert_eq!(id, MessageId::for_content(&[1; 32], &[2; 32], b"hello"));
+        assert_ne!(id, MessageId::for_content(&[1; 32], &[3; 32], b"hello"));
+        assert_ne!(id, MessageId::for_content(&[1; 32], &[2; 32], b"hello!"));
+        assert!(id.is_content_addressed());
+        assert_eq!(id.timestamp_ms(), None);
+    }
+}
@@ -1,2 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod envelope;
-pub use envelope::*;
\ No newline at end of file
+pub use envelope::*;
+
+mod message_id;
+pub use message_id::*;
+
+mod read_receipt;
+pub use read_receipt::*;
@@ -0,0 +1,94 @@
+use crate::envelope::{MessageEnvelope, MessageId};
+
+/// Marker prefixed to read receipt payloads.
+const READ_RECEIPT_MAGIC: &[u8; 4] = b"QRR1";
+
+/// Payload of a read receipt: the recipient of `msg_id` read it at
+/// `read_at` (Unix seconds).
+///
+/// Read receipts travel as ordinary signed envelopes from the recipient back
+/// to the sender, so they prove who read the message. Relays recognise them
+/// and record a read event for the sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadReceipt {
+    pub msg_id: MessageId,
+    pub read_at: u64,
+}
+
+impl ReadReceipt {
+    /// Encoded length: magic, `msg_id`, `read_at`.
+    pub const LEN: usize = READ_RECEIPT_MAGIC.len() + MessageId::LEN + 8;
+
+    /// `"QRR1"`, `msg_id`, `read_at` (big-endian `u64`).
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::LEN);
+        payload.extend_from_slice(READ_RECEIPT_MAGIC);
+        payload.extend_from_slice(self.msg_id.as_bytes());
+        payload.extend_from_slice(&self.read_at.to_be_bytes());
+        payload
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        if payload.len() != Self::LEN {
+            return None;
+        }
+        let rest = payload.strip_prefix(READ_RECEIPT_MAGIC)?;
+        let (msg_id, read_at) = rest.split_at(MessageId::LEN);
+        Some(ReadReceipt {
+            msg_id: MessageId::from_bytes(msg_id.try_into().ok()?),
+            read_at: u64::from_be_bytes(read_at.try_into().ok()?),
+        })
+    }
+}
+
+impl MessageEnvelope {
+    /// A read receipt for `original`, addressed from its recipient back to
+    /// its sender with the same TTL. The id is content-addressed, so a
+    /// resent receipt is recorded once. Sign it with the recipient's key.
+    pub fn read_receipt_for(original: &MessageEnvelope, sender: String, read_at: u64) -> Self {
+        let receipt = ReadReceipt {
+            msg_id: original.msg_id,
+            read_at,
+        };
+        MessageEnvelope::new_content_addressed(
+            sender,
+            original.sender_key,
+            original.recipient,
+            receipt.to_payload(),
+            original.ttl,
+        )
+    }
+
+    /// The read receipt this envelope carries, if it is one.
+    pub fn read_receipt(&self) -> Option<ReadReceipt> {
+        ReadReceipt::from_payload(&self.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_keypair;
+
+    #[test]
+    fn test_read_receipt_envelope_goes_back_to_sender() {
+        let (recipient_key, recipient_priv) = gen_keypair();
+        let (sender_key, _) = gen_keypair();
+        let original = MessageEnvelope::new("alice".into(), recipient_key, sender_key, vec![7], 600);
+
+        let mut receipt = MessageEnvelope::read_receipt_for(&original, "bob".into(), 42);
+        receipt.sign(&recipient_priv);
+        assert!(receipt.verify());
+        assert!(receipt.has_content_id());
+        assert_eq!((receipt.sender_key, receipt.recipient), (recipient_key, sender_key));
+        assert_eq!(
+            receipt.read_receipt(),
+            Some(ReadReceipt { msg_id: original.msg_id, read_at: 42 })
+        );
+
+        assert_eq!(original.read_receipt(), None);
+        let mut truncated = receipt.payload.clone();
+        truncated.pop();
+        assert_eq!(ReadReceipt::from_payload(&truncated), None);
+    }
+}
@@ -1,14 +1,259 @@
 
+use std::time::Duration;
 use thiserror::Error;
 use wincode::{SchemaRead, SchemaWrite};
 
-#[derive(Error,Debug, SchemaRead,SchemaWrite)]
+use crate::protocol::CloseCode;
+
+/// Result type of the client, envelope and protocol APIs.
+pub type Result<T, E = QightError> = std::result::Result<T, E>;
+
+/// Errors returned by [`crate::RelayClient`], [`crate::MessageEnvelope`],
+/// the [`crate::Outbox`] and the frame codec.
+///
+/// Variants are grouped by the layer that failed; [`QightError::kind`]
+/// reports the group for callers that do not need the detail.
+#[derive(Error, Debug, Clone, PartialEq, Eq, SchemaRead, SchemaWrite)]
 pub enum QightError{
+    // Transport
+    #[error("Not connected to relay!")]
+    NotConnected,
+    #[error("Cannot connect to relay: {0}!")]
+    ConnectFailed(String),
+    #[error("Connection to relay lost: {0}!")]
+    ConnectionLost(String),
+    #[error("Invalid TLS configuration: {0}!")]
+    Tls(String),
+    #[error("Client is closed!")]
+    ClientClosed,
+
+    // Protocol
+    #[error("Malformed protocol frame!")]
+    MalformedFrame,
+    #[error("Unsupported protocol version!")]
+    UnsupportedProtocolVersion,
+    #[error("Unknown command!")]
+    UnknownCommand,
+    #[error("Unexpected response from relay: {0}!")]
+    UnexpectedResponse(String),
+    #[error("Relay does not support a required feature!")]
+    UnsupportedFeature,
+
+    // Storage
+    #[error("Storage error: {0}!")]
+    Storage(String),
+
+    // Crypto
+    #[error("Invalid public key!")]
+    InvalidPublicKey,
+    #[error("Cannot encrypt payload!")]
+    CannotEncryptPayload,
+    #[error("Cannot decrypt payload!")]
+    CannotDecryptPayload,
+    #[error("Payload is not encrypted!")]
+    PayloadNotEncrypted,
+    #[error("Invalid signature!")]
+    InvalidSignature,
+    #[error("Cannot derive channel binding!")]
+    ChannelBinding,
+    #[error("Relay identity could not be verified!")]
+    RelayIdentityMismatch,
+
+    // Validation
     #[error("Cannot serialize message to bytes!")]
     CannotSerializeBytes,
     #[error("Cannot deserialize from bytes!")]
     CannotDeserialzeBytes,
+    #[error("Invalid message envelope!")]
+    InvalidEnvelope,
+    #[error("Payload too large!")]
+    PayloadTooLarge,
+    #[error("TTL outside the accepted range!")]
+    TtlOutOfRange,
+    #[error("Timestamp too far in the future!")]
+    InvalidTimestamp,
+    #[error("Message expired!")]
+    Expired,
+    #[error("Field exceeds its length limit!")]
+    FieldTooLong,
+    #[error("Duplicate message id!")]
+    DuplicateMessage,
+    #[error("Message id already used for different content!")]
+    MessageConflict,
+    #[error("Invalid recipient!")]
+    InvalidRecipient,
+    #[error("Invalid message id!")]
+    InvalidMessageId,
+    #[error("Version 1 envelope, re-sign it under the current scheme!")]
+    V1Envelope,
+
+    // Relay verdicts without a more specific variant
+    #[error("Unauthorized!")]
+    Unauthorized,
+    #[error("Relay storage quota exceeded!")]
+    QuotaExceeded,
+    #[error("Rate limited by relay, retry after {retry_after_ms} ms!")]
+    RateLimited { retry_after_ms: u64 },
+    #[error("Relay internal error!")]
+    RelayInternal,
+    #[error("Relay error code {0}!")]
+    UnknownRelayError(u16),
+}
+
+/// Layer a [`QightError`] originates from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The relay could not be reached or the connection failed.
+    Transport,
+    /// The peer violated or does not speak the wire protocol.
+    Protocol,
+    /// The local outbox failed.
+    Storage,
+    /// Key, signature or encryption failures.
+    Crypto,
+    /// A message or request was rejected as invalid.
+    Validation,
+    /// The relay refused the request for another reason.
+    Relay,
+}
+
+impl QightError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            QightError::NotConnected
+            | QightError::ConnectFailed(_)
+            | QightError::ConnectionLost(_)
+            | QightError::Tls(_)
+            | QightError::ClientClosed => ErrorKind::Transport,
+            QightError::MalformedFrame
+            | QightError::UnsupportedProtocolVersion
+            | QightError::UnknownCommand
+            | QightError::UnexpectedResponse(_)
+            | QightError::UnsupportedFeature => ErrorKind::Protocol,
+            QightError::Storage(_) => ErrorKind::Storage,
+            QightError::InvalidPublicKey
+            | QightError::CannotEncryptPayload
+            | QightError::CannotDecryptPayload
+            | QightError::PayloadNotEncrypted
+            | QightError::InvalidSignature
+            | QightError::ChannelBinding
+            | QightError::RelayIdentityMismatch => ErrorKind::Crypto,
+            QightError::CannotSerializeBytes
+            | QightError::CannotDeserialzeBytes
+            | QightError::InvalidEnvelope
+            | QightError::PayloadTooLarge
+            | QightError::TtlOutOfRange
+            | QightError::InvalidTimestamp
+            | QightError::Expired
+            | QightError::FieldTooLong
+            | QightError::DuplicateMessage
+            | QightError::MessageConflict
+            | QightError::InvalidRecipient
+            | QightError::InvalidMessageId
+            | QightError::V1Envelope => ErrorKind::Validation,
+            QightError::Unauthorized
+            | QightError::QuotaExceeded
+            | QightError::RateLimited { .. }
+            | QightError::RelayInternal
+            | QightError::UnknownRelayError(_) => ErrorKind::Relay,
+        }
+    }
+
+    /// Whether repeating the operation later can succeed: the relay was
+    /// unreachable, full, rate limiting or failed internally. Everything
+    /// else rejects the request itself.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            QightError::NotConnected
+                | QightError::ConnectFailed(_)
+                | QightError::ConnectionLost(_)
+                | QightError::QuotaExceeded
+                | QightError::RateLimited { .. }
+                | QightError::RelayInternal
+        )
+    }
+
+    /// How long the relay asked the client to wait, for
+    /// [`QightError::RateLimited`].
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            QightError::RateLimited { retry_after_ms } => {
+                Some(Duration::from_millis(*retry_after_ms))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<quinn::ConnectError> for QightError {
+    fn from(e: quinn::ConnectError) -> Self {
+        QightError::ConnectFailed(e.to_string())
+    }
+}
+
+impl From<quinn::ConnectionError> for QightError {
+    fn from(e: quinn::ConnectionError) -> Self {
+        if let quinn::ConnectionError::ApplicationClosed(close) = &e {
+            if CloseCode::from_u32(close.error_code.into_inner() as u32) == Some(CloseCode::RateLimited) {
+                let retry_after_ms = std::str::from_utf8(&close.reason)
+                    .ok()
+                    .and_then(|reason| reason.parse().ok())
+                    .unwrap_or(0);
+                return QightError::RateLimited { retry_after_ms };
+            }
+        }
+        QightError::ConnectionLost(e.to_string())
+    }
+}
+
+impl From<quinn::ClosedStream> for QightError {
+    fn from(e: quinn::ClosedStream) -> Self {
+        QightError::ConnectionLost(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for QightError {
+    fn from(e: std::io::Error) -> Self {
+        QightError::ConnectionLost(e.to_string())
+    }
 }
 
+impl From<rusqlite::Error> for QightError {
+    fn from(e: rusqlite::Error) -> Self {
+        QightError::Storage(e.to_string())
+    }
+}
+
+impl From<r2d2::Error> for QightError {
+    fn from(e: r2d2::Error) -> Self {
+        QightError::Storage(e.to_string())
+    }
+}
 
+impl From<rustls::Error> for QightError {
+    fn from(e: rustls::Error) -> Self {
+        QightError::Tls(e.to_string())
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(QightError::NotConnected.kind(), ErrorKind::Transport);
+        assert_eq!(QightError::InvalidSignature.kind(), ErrorKind::Crypto);
+        assert_eq!(QightError::TtlOutOfRange.kind(), ErrorKind::Validation);
+        assert_eq!(QightError::Storage("full".into()).kind(), ErrorKind::Storage);
+        assert!(QightError::ConnectionLost("reset".into()).is_transient());
+        assert!(!QightError::PayloadTooLarge.is_transient());
+        assert!(QightError::QuotaExceeded.is_transient());
+
+        let limited = QightError::RateLimited { retry_after_ms: 250 };
+        assert_eq!(limited.kind(), ErrorKind::Relay);
+        assert_eq!(limited.retry_after(), Some(Duration::from_millis(250)));
+        assert_eq!(QightError::RelayInternal.retry_after(), None);
+    }
+}
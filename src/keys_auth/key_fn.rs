@@ -1,7 +1,10 @@
+use curve25519_dalek::montgomery::MontgomeryPoint;
 use ed25519_dalek::{
     PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SigningKey, Signer, Verifier, VerifyingKey, SIGNATURE_LENGTH,
 };
 use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 
 
 pub fn gen_key() -> [u8;PUBLIC_KEY_LENGTH]{
@@ -16,7 +19,7 @@ pub fn gen_keypair() -> ([u8;PUBLIC_KEY_LENGTH] , [u8;SECRET_KEY_LENGTH]){
     let signing_key: SigningKey = SigningKey::generate(&mut cspring);
     let public_key: [u8; PUBLIC_KEY_LENGTH] = *signing_key.verifying_key().as_bytes();
     let private_key: [u8; SECRET_KEY_LENGTH] = signing_key.to_bytes();
-    return (public_key,private_key);
+    (public_key,private_key)
 
 }
 pub fn sign_message(private_key: &[u8; SECRET_KEY_LENGTH], message: &[u8]) -> [u8; SIGNATURE_LENGTH] {
@@ -34,7 +37,96 @@ pub fn verify_message(public_key: &[u8; PUBLIC_KEY_LENGTH], message: &[u8], sign
     }
 }
 
+/// Derives the Ed25519 public key belonging to `private_key`.
+pub fn public_key_from_secret(private_key: &[u8; SECRET_KEY_LENGTH]) -> [u8; PUBLIC_KEY_LENGTH] {
+    *SigningKey::from_bytes(private_key).verifying_key().as_bytes()
+}
+
+/// Short identifier of a public key for logs: the hex of the first eight
+/// bytes of its SHA-256 digest.
+pub fn key_fingerprint(public_key: &[u8; PUBLIC_KEY_LENGTH]) -> String {
+    hex::encode(&Sha256::digest(public_key)[..8])
+}
+
+/// Length of the nonce a relay issues for recipient challenges.
+pub const CHALLENGE_NONCE_LENGTH: usize = 32;
+
+/// TLS exporter label used to bind challenge responses to a QUIC connection.
+pub const CHALLENGE_EXPORTER_LABEL: &[u8] = b"EXPORTER-qight-challenge";
 
+/// Generates a fresh random challenge nonce.
+pub fn gen_challenge_nonce() -> [u8; CHALLENGE_NONCE_LENGTH] {
+    let mut nonce = [0u8; CHALLENGE_NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Bytes a recipient signs to prove possession of its secret key.
+///
+/// `channel_binding` is keying material exported from the QUIC connection
+/// with [`CHALLENGE_EXPORTER_LABEL`], so a response cannot be replayed on a
+/// different connection.
+pub fn challenge_message(
+    nonce: &[u8; CHALLENGE_NONCE_LENGTH],
+    recipient: &[u8; PUBLIC_KEY_LENGTH],
+    channel_binding: &[u8],
+) -> Vec<u8> {
+    const LABEL: &[u8] = b"qight-recipient-challenge";
+    let mut msg = Vec::with_capacity(LABEL.len() + CHALLENGE_NONCE_LENGTH + PUBLIC_KEY_LENGTH + channel_binding.len());
+    msg.extend_from_slice(LABEL);
+    msg.extend_from_slice(nonce);
+    msg.extend_from_slice(recipient);
+    msg.extend_from_slice(channel_binding);
+    msg
+}
+
+/// TLS exporter label for the channel binding a relay signs in WELCOME.
+pub const RELAY_IDENTITY_EXPORTER_LABEL: &[u8] = b"EXPORTER-qight-relay-identity";
+
+/// Bytes a relay signs with its identity key to prove it terminates this
+/// QUIC connection. `channel_binding` is exported with
+/// [`RELAY_IDENTITY_EXPORTER_LABEL`] and an empty context.
+pub fn relay_identity_message(
+    relay_key: &[u8; PUBLIC_KEY_LENGTH],
+    channel_binding: &[u8],
+) -> Vec<u8> {
+    const LABEL: &[u8] = b"qight-relay-identity";
+    let mut msg = Vec::with_capacity(LABEL.len() + PUBLIC_KEY_LENGTH + channel_binding.len());
+    msg.extend_from_slice(LABEL);
+    msg.extend_from_slice(relay_key);
+    msg.extend_from_slice(channel_binding);
+    msg
+}
+
+/// Converts an Ed25519 public key to its X25519 (Montgomery) form.
+pub fn x25519_public_from_ed25519(public_key: &[u8; PUBLIC_KEY_LENGTH]) -> Option<[u8; 32]> {
+    let pk = VerifyingKey::from_bytes(public_key).ok()?;
+    Some(pk.to_montgomery().to_bytes())
+}
+
+/// Converts an Ed25519 secret key to the X25519 scalar bytes it corresponds to.
+pub fn x25519_secret_from_ed25519(private_key: &[u8; SECRET_KEY_LENGTH]) -> [u8; 32] {
+    SigningKey::from_bytes(private_key).to_scalar_bytes()
+}
+
+/// Generates an ephemeral X25519 keypair as `(public, secret)`.
+pub fn gen_x25519_keypair() -> ([u8; 32], [u8; 32]) {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let public = MontgomeryPoint::mul_base_clamped(secret).to_bytes();
+    (public, secret)
+}
+
+/// X25519 Diffie-Hellman. Returns `None` for low-order peer points, which
+/// would yield an all-zero shared secret.
+pub fn x25519_shared_secret(secret: &[u8; 32], peer_public: &[u8; 32]) -> Option<[u8; 32]> {
+    let shared = MontgomeryPoint(*peer_public).mul_clamped(*secret).to_bytes();
+    if shared == [0u8; 32] {
+        None
+    } else {
+        Some(shared)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -46,6 +138,14 @@ mod tests {
         assert_eq!(key.len(), PUBLIC_KEY_LENGTH);
     }
 
+    #[test]
+    fn test_key_fingerprint_is_short_and_stable() {
+        let (public, _) = gen_keypair();
+        assert_eq!(key_fingerprint(&public).len(), 16);
+        assert_eq!(key_fingerprint(&public), key_fingerprint(&public));
+        assert_ne!(key_fingerprint(&public), key_fingerprint(&[0; 32]));
+    }
+
     #[test]
     fn test_gen_keypair_uniqueness() {
         let (pub1, priv1) = gen_keypair();
@@ -55,4 +155,42 @@ mod tests {
         assert_eq!(pub1.len(), PUBLIC_KEY_LENGTH);
         assert_eq!(priv1.len(), SECRET_KEY_LENGTH);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_x25519_from_ed25519_agrees() {
+        let (alice_pub, alice_priv) = gen_keypair();
+        let (bob_pub, bob_priv) = gen_keypair();
+
+        let alice_shared = x25519_shared_secret(
+            &x25519_secret_from_ed25519(&alice_priv),
+            &x25519_public_from_ed25519(&bob_pub).unwrap(),
+        )
+        .unwrap();
+        let bob_shared = x25519_shared_secret(
+            &x25519_secret_from_ed25519(&bob_priv),
+            &x25519_public_from_ed25519(&alice_pub).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(alice_shared, bob_shared);
+    }
+
+    #[test]
+    fn test_x25519_rejects_low_order_point() {
+        let (_, secret) = gen_x25519_keypair();
+        assert!(x25519_shared_secret(&secret, &[0u8; 32]).is_none());
+    }
+
+    #[test]
+    fn test_challenge_response_verifies() {
+        let (public, private) = gen_keypair();
+        assert_eq!(public_key_from_secret(&private), public);
+
+        let nonce = gen_challenge_nonce();
+        let msg = challenge_message(&nonce, &public, b"binding");
+        let sig = sign_message(&private, &msg);
+        assert!(verify_message(&public, &msg, &sig));
+
+        let other = challenge_message(&nonce, &public, b"other-binding");
+        assert!(!verify_message(&public, &other, &sig));
+    }
+}
@@ -8,4 +8,10 @@ pub mod client;
 pub use client::*;
 
 pub mod keys_auth;
-pub use keys_auth::*;
\ No newline at end of file
+pub use keys_auth::*;
+
+pub mod metrics;
+
+pub mod relay;
+
+pub mod protocol;
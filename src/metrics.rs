@@ -0,0 +1,229 @@
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::client::DrainReport;
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+pub(crate) const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// A monotonically increasing count.
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down.
+#[derive(Debug, Default)]
+pub(crate) struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Durations sorted into [`LATENCY_BUCKETS`].
+#[derive(Debug, Default)]
+pub(crate) struct Histogram {
+    /// Non-cumulative; the last slot holds observations above every bound.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let slot = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[slot].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Builds a scrape in the Prometheus text exposition format, version 0.0.4.
+#[derive(Debug, Default)]
+pub(crate) struct TextEncoder {
+    out: String,
+}
+
+impl TextEncoder {
+    /// Content type of [`TextEncoder::finish`]'s output.
+    pub const CONTENT_TYPE: &'static str = "text/plain; version=0.0.4; charset=utf-8";
+
+    /// Starts a metric family. Its samples must follow before the next one.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    /// One sample. Label values must not need escaping.
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, value))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    /// A family with a single unlabelled sample.
+    pub fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    /// The `_bucket`, `_sum` and `_count` samples of `histogram`.
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let mut cumulative = 0;
+        for (i, bucket) in histogram.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = LATENCY_BUCKETS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &bound));
+            self.sample(&format!("{}_bucket", name), &bucket_labels, cumulative);
+        }
+        let sum = histogram.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        self.sample(&format!("{}_sum", name), labels, sum);
+        self.sample(&format!("{}_count", name), labels, cumulative);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// Outbox replays across every client in the process.
+#[derive(Debug, Default)]
+struct OutboxMetrics {
+    drains: Counter,
+    delivered: Counter,
+    dead_lettered: Counter,
+    deferred: Counter,
+    expired: Counter,
+}
+
+static OUTBOX: OutboxMetrics = OutboxMetrics {
+    drains: Counter(AtomicU64::new(0)),
+    delivered: Counter(AtomicU64::new(0)),
+    dead_lettered: Counter(AtomicU64::new(0)),
+    deferred: Counter(AtomicU64::new(0)),
+    expired: Counter(AtomicU64::new(0)),
+};
+
+/// Counts one completed `RelayClient::drain_queue`.
+pub(crate) fn record_drain(report: &DrainReport) {
+    OUTBOX.drains.inc();
+    OUTBOX.delivered.add(report.delivered as u64);
+    OUTBOX.dead_lettered.add(report.dead_lettered as u64);
+    OUTBOX.deferred.add(report.deferred as u64);
+    OUTBOX.expired.add(report.expired as u64);
+}
+
+pub(crate) fn encode_client(encoder: &mut TextEncoder) {
+    let families = [
+        (
+            "qight_outbox_drains_total",
+            "Outbox drains completed.",
+            &OUTBOX.drains,
+        ),
+        (
+            "qight_outbox_delivered_total",
+            "Queued messages the relay accepted on replay.",
+            &OUTBOX.delivered,
+        ),
+        (
+            "qight_outbox_dead_lettered_total",
+            "Queued messages the relay rejected on replay.",
+            &OUTBOX.dead_lettered,
+        ),
+        (
+            "qight_outbox_deferred_total",
+            "Queued messages rescheduled after a failed replay.",
+            &OUTBOX.deferred,
+        ),
+        (
+            "qight_outbox_expired_total",
+            "Queued messages dropped because their TTL passed.",
+            &OUTBOX.expired,
+        ),
+    ];
+    for (name, help, counter) in families {
+        encoder.single(name, "counter", help, counter.get());
+    }
+}
+
+/// Client-side metrics of this process, such as outbox drains, in the
+/// Prometheus text format. A relay includes them in its own `/metrics`.
+pub fn render() -> String {
+    let mut encoder = TextEncoder::default();
+    encode_client(&mut encoder);
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_encodes_cumulative_buckets() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(10));
+
+        let mut encoder = TextEncoder::default();
+        encoder.family("latency_seconds", "histogram", "Request latency.");
+        encoder.histogram("latency_seconds", &[("command", "send")], &histogram);
+        let text = encoder.finish();
+
+        assert!(text.starts_with("# HELP latency_seconds Request latency.\n"));
+        assert!(text.contains("latency_seconds_bucket{command=\"send\",le=\"0.001\"} 1\n"));
+        assert!(text.contains("latency_seconds_bucket{command=\"send\",le=\"0.025\"} 2\n"));
+        assert!(text.contains("latency_seconds_bucket{command=\"send\",le=\"5\"} 2\n"));
+        assert!(text.contains("latency_seconds_bucket{command=\"send\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("latency_seconds_sum{command=\"send\"} 10.0205\n"));
+        assert!(text.contains("latency_seconds_count{command=\"send\"} 3\n"));
+    }
+
+    #[test]
+    fn test_drains_are_counted() {
+        let before = OUTBOX.drains.get();
+        record_drain(&DrainReport {
+            delivered: 2,
+            ..DrainReport::default()
+        });
+        assert!(OUTBOX.drains.get() > before);
+        assert!(render().contains("# TYPE qight_outbox_delivered_total counter\n"));
+    }
+}
@@ -0,0 +1,159 @@
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use std::fmt;
+
+use crate::protocol::PROTOCOL_VERSION;
+
+/// Oldest protocol version this build can speak. Version 1 peers use
+/// 32-byte message ids and are refused.
+pub const MIN_PROTOCOL_VERSION: u8 = 2;
+
+/// Optional protocol features, exchanged as a `u32` bit set in HELLO.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Features(u32);
+
+impl Features {
+    /// End-to-end payload encryption (`MessageEnvelope::encrypt_for`).
+    pub const ENCRYPTION: Features = Features(1 << 0);
+    /// SUBSCRIBE and server-pushed messages.
+    pub const PUSH: Features = Features(1 << 1);
+    /// Leased FETCH with ACK.
+    pub const ACK: Features = Features(1 << 2);
+    /// Payload compression.
+    pub const COMPRESSION: Features = Features(1 << 3);
+    /// SEND is idempotent per `msg_id`: resending an accepted envelope is
+    /// answered OK, reusing its `msg_id` for another one `MESSAGE_CONFLICT`.
+    pub const IDEMPOTENT_SEND: Features = Features(1 << 4);
+    /// Delivery receipts: RECEIPTS, WATCH and read receipt tracking.
+    pub const RECEIPTS: Features = Features(1 << 5);
+
+    pub const fn empty() -> Self {
+        Features(0)
+    }
+
+    /// Every feature this build implements.
+    pub const fn supported() -> Self {
+        Features(
+            Self::ENCRYPTION.0
+                | Self::PUSH.0
+                | Self::ACK.0
+                | Self::IDEMPOTENT_SEND.0
+                | Self::RECEIPTS.0,
+        )
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Features(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Features) -> Self {
+        Features(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Features) -> Self {
+        Features(self.0 & other.0)
+    }
+
+    /// Features in `self` that `other` lacks.
+    pub const fn difference(self, other: Features) -> Self {
+        Features(self.0 & !other.0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        self.union(other)
+    }
+}
+
+impl fmt::Debug for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Features::ENCRYPTION, "ENCRYPTION"),
+            (Features::PUSH, "PUSH"),
+            (Features::ACK, "ACK"),
+            (Features::COMPRESSION, "COMPRESSION"),
+            (Features::IDEMPOTENT_SEND, "IDEMPOTENT_SEND"),
+            (Features::RECEIPTS, "RECEIPTS"),
+        ];
+        let mut set = f.debug_set();
+        let mut known = Features::empty();
+        for (feature, name) in names {
+            if self.contains(feature) {
+                set.entry(&format_args!("{}", name));
+                known = known | feature;
+            }
+        }
+        let unknown = self.difference(known);
+        if !unknown.is_empty() {
+            set.entry(&format_args!("{:#x}", unknown.bits()));
+        }
+        set.finish()
+    }
+}
+
+/// What a relay agreed to in its WELCOME reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Protocol version selected for the connection.
+    pub version: u8,
+    /// Features both sides support.
+    pub features: Features,
+    /// Largest serialized envelope the relay accepts, in bytes.
+    pub max_payload: u32,
+    /// Longest TTL the relay accepts, in seconds.
+    pub max_ttl: u32,
+    /// The relay's Ed25519 identity key.
+    pub relay_key: [u8; PUBLIC_KEY_LENGTH],
+}
+
+impl Capabilities {
+    pub fn supports(&self, features: Features) -> bool {
+        self.features.contains(features)
+    }
+}
+
+/// Highest version in both `[min, max]` ranges.
+pub fn negotiate_version(min: u8, max: u8) -> Option<u8> {
+    let version = max.min(PROTOCOL_VERSION);
+    (version >= min.max(MIN_PROTOCOL_VERSION)).then_some(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_sets() {
+        let relay = Features::supported();
+        let client = Features::PUSH | Features::COMPRESSION;
+        assert_eq!(relay.intersection(client), Features::PUSH);
+        assert_eq!(client.difference(relay), Features::COMPRESSION);
+        assert!(relay.contains(Features::ACK | Features::ENCRYPTION));
+        assert_eq!(
+            format!("{:?}", Features::from_bits(Features::ACK.bits() | 1 << 9)),
+            "{ACK, 0x200}"
+        );
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(1, 1), None);
+        assert_eq!(negotiate_version(1, 7), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(PROTOCOL_VERSION + 1, 9), None);
+        assert_eq!(negotiate_version(0, 0), None);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::errors::QightError;
+
+/// Numeric error codes carried by [`crate::protocol::Frame::Error`].
+///
+/// Codes are stable across protocol versions; new codes may be added, so
+/// clients must accept values they do not know.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    MalformedFrame = 1,
+    UnsupportedVersion = 2,
+    UnknownCommand = 3,
+    PayloadTooLarge = 4,
+    InvalidEnvelope = 5,
+    InvalidSignature = 6,
+    InvalidRecipient = 7,
+    InvalidMessageId = 8,
+    Unauthorized = 9,
+    Internal = 10,
+    TtlOutOfRange = 11,
+    InvalidTimestamp = 12,
+    Expired = 13,
+    FieldTooLong = 14,
+    DuplicateMessage = 15,
+    QuotaExceeded = 16,
+    MessageConflict = 17,
+}
+
+impl ErrorCode {
+    pub fn from_u16(code: u16) -> Option<Self> {
+        Some(match code {
+            1 => ErrorCode::MalformedFrame,
+            2 => ErrorCode::UnsupportedVersion,
+            3 => ErrorCode::UnknownCommand,
+            4 => ErrorCode::PayloadTooLarge,
+            5 => ErrorCode::InvalidEnvelope,
+            6 => ErrorCode::InvalidSignature,
+            7 => ErrorCode::InvalidRecipient,
+            8 => ErrorCode::InvalidMessageId,
+            9 => ErrorCode::Unauthorized,
+            10 => ErrorCode::Internal,
+            11 => ErrorCode::TtlOutOfRange,
+            12 => ErrorCode::InvalidTimestamp,
+            13 => ErrorCode::Expired,
+            14 => ErrorCode::FieldTooLong,
+            15 => ErrorCode::DuplicateMessage,
+            16 => ErrorCode::QuotaExceeded,
+            17 => ErrorCode::MessageConflict,
+            _ => return None,
+        })
+    }
+
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    /// Whether resending the same request can succeed. Only internal relay
+    /// failures and full quotas are transient; everything else rejects the
+    /// request itself.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorCode::Internal | ErrorCode::QuotaExceeded)
+    }
+
+    /// The code a relay reports for `error`, if it is a protocol error.
+    pub fn for_error(error: &QightError) -> Option<Self> {
+        Some(match error {
+            QightError::MalformedFrame => ErrorCode::MalformedFrame,
+            QightError::UnsupportedProtocolVersion => ErrorCode::UnsupportedVersion,
+            QightError::UnknownCommand => ErrorCode::UnknownCommand,
+            QightError::PayloadTooLarge => ErrorCode::PayloadTooLarge,
+            QightError::InvalidEnvelope
+            | QightError::CannotDeserialzeBytes
+            | QightError::V1Envelope => {
+                ErrorCode::InvalidEnvelope
+            }
+            QightError::InvalidSignature => ErrorCode::InvalidSignature,
+            QightError::InvalidRecipient => ErrorCode::InvalidRecipient,
+            QightError::InvalidMessageId => ErrorCode::InvalidMessageId,
+            QightError::Unauthorized => ErrorCode::Unauthorized,
+            QightError::RelayInternal => ErrorCode::Internal,
+            QightError::TtlOutOfRange => ErrorCode::TtlOutOfRange,
+            QightError::InvalidTimestamp => ErrorCode::InvalidTimestamp,
+            QightError::Expired => ErrorCode::Expired,
+            QightError::FieldTooLong => ErrorCode::FieldTooLong,
+            QightError::DuplicateMessage => ErrorCode::DuplicateMessage,
+            QightError::QuotaExceeded => ErrorCode::QuotaExceeded,
+            QightError::MessageConflict => ErrorCode::MessageConflict,
+            _ => return None,
+        })
+    }
+}
+
+impl From<ErrorCode> for QightError {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::MalformedFrame => QightError::MalformedFrame,
+            ErrorCode::UnsupportedVersion => QightError::UnsupportedProtocolVersion,
+            ErrorCode::UnknownCommand => QightError::UnknownCommand,
+            ErrorCode::PayloadTooLarge => QightError::PayloadTooLarge,
+            ErrorCode::InvalidEnvelope => QightError::InvalidEnvelope,
+            ErrorCode::InvalidSignature => QightError::InvalidSignature,
+            ErrorCode::InvalidRecipient => QightError::InvalidRecipient,
+            ErrorCode::InvalidMessageId => QightError::InvalidMessageId,
+            ErrorCode::Unauthorized => QightError::Unauthorized,
+            ErrorCode::Internal => QightError::RelayInternal,
+            ErrorCode::TtlOutOfRange => QightError::TtlOutOfRange,
+            ErrorCode::InvalidTimestamp => QightError::InvalidTimestamp,
+            ErrorCode::Expired => QightError::Expired,
+            ErrorCode::FieldTooLong => QightError::FieldTooLong,
+            ErrorCode::DuplicateMessage => QightError::DuplicateMessage,
+            ErrorCode::QuotaExceeded => QightError::QuotaExceeded,
+            ErrorCode::MessageConflict => QightError::MessageConflict,
+        }
+    }
+}
+
+/// QUIC application close codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum CloseCode {
+    /// Orderly close with nothing further to report.
+    Normal = 0,
+    /// The relay refused the connection over its rate limit. The close
+    /// reason is the retry delay in milliseconds, as ASCII digits.
+    RateLimited = 1,
+    /// The relay is shutting down; reconnect later, possibly elsewhere.
+    ShuttingDown = 2,
+}
+
+impl CloseCode {
+    pub fn from_u32(code: u32) -> Option<Self> {
+        Some(match code {
+            0 => CloseCode::Normal,
+            1 => CloseCode::RateLimited,
+            2 => CloseCode::ShuttingDown,
+            _ => return None,
+        })
+    }
+
+    pub fn as_u32(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Maps a wire error code to a [`QightError`], keeping unknown codes.
+pub fn error_from_code(code: u16) -> QightError {
+    match ErrorCode::from_u16(code) {
+        Some(code) => code.into(),
+        None => QightError::UnknownRelayError(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_round_trip() {
+        for raw in 1..=17 {
+            let code = ErrorCode::from_u16(raw).unwrap();
+            assert_eq!(code.as_u16(), raw);
+            assert_eq!(ErrorCode::for_error(&code.into()), Some(code));
+        }
+        assert!(ErrorCode::from_u16(0).is_none());
+        assert!(matches!(
+            error_from_code(999),
+            QightError::UnknownRelayError(999)
+        ));
+    }
+
+    #[test]
+    fn test_close_codes_round_trip() {
+        for code in [CloseCode::Normal, CloseCode::RateLimited, CloseCode::ShuttingDown] {
+            assert_eq!(CloseCode::from_u32(code.as_u32()), Some(code));
+        }
+        assert!(CloseCode::from_u32(3).is_none());
+    }
+}
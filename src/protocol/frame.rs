@@ -0,0 +1,563 @@
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::errors::QightError;
+use crate::protocol::{
+    Capabilities, ErrorCode, Features, Receipt, RelayStats, MIN_PROTOCOL_VERSION,
+};
+use crate::{MessageId, CHALLENGE_NONCE_LENGTH};
+
+/// Version byte written in every frame header. Version 1 carried 32-byte
+/// message ids.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Version byte, type byte and big-endian `u32` payload length.
+pub const FRAME_HEADER_LEN: usize = 6;
+
+/// Frame type bytes. Requests use `0x01..=0x7f`, responses `0x80..=0xff`.
+pub mod frame_type {
+    pub const HELLO: u8 = 0x01;
+    pub const SEND: u8 = 0x02;
+    pub const FETCH: u8 = 0x03;
+    pub const ACK: u8 = 0x04;
+    pub const SUBSCRIBE: u8 = 0x05;
+    pub const CHALLENGE_RESPONSE: u8 = 0x06;
+    pub const STATS: u8 = 0x07;
+    pub const RECEIPTS: u8 = 0x08;
+    pub const WATCH: u8 = 0x09;
+
+    pub const OK: u8 = 0x80;
+    pub const ERROR: u8 = 0x81;
+    pub const CHALLENGE: u8 = 0x82;
+    pub const MESSAGE: u8 = 0x83;
+    pub const END: u8 = 0x84;
+    pub const WELCOME: u8 = 0x85;
+    pub const ACKED: u8 = 0x86;
+    pub const STATS_REPLY: u8 = 0x87;
+    pub const RATE_LIMITED: u8 = 0x88;
+    pub const RECEIPT: u8 = 0x89;
+}
+
+/// One protocol frame. See `docs/PROTOCOL.md` for the wire layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// Opens negotiation: the protocol versions and features the client
+    /// supports.
+    Hello {
+        client_id: String,
+        min_version: u8,
+        max_version: u8,
+        features: Features,
+    },
+    /// Stores a serialized, signed envelope.
+    Send { envelope: Vec<u8> },
+    /// Reads a recipient's inbox. `lease_secs == 0` removes fetched messages;
+    /// otherwise they are leased until acknowledged.
+    Fetch {
+        recipient: [u8; PUBLIC_KEY_LENGTH],
+        lease_secs: u32,
+    },
+    /// Acknowledges leased messages.
+    Ack {
+        recipient: [u8; PUBLIC_KEY_LENGTH],
+        msg_ids: Vec<MessageId>,
+    },
+    /// Asks for push delivery of a recipient's new messages.
+    Subscribe { recipient: [u8; PUBLIC_KEY_LENGTH] },
+    /// Answers a [`Frame::Challenge`].
+    ChallengeResponse { signature: [u8; SIGNATURE_LENGTH] },
+    /// Asks for storage figures and quota settings.
+    Stats,
+    /// Asks for the receipts of a message `sender_key` signed.
+    Receipts {
+        sender_key: [u8; PUBLIC_KEY_LENGTH],
+        msg_id: MessageId,
+    },
+    /// Asks for push delivery of new receipts of messages `sender_key`
+    /// signed.
+    Watch { sender_key: [u8; PUBLIC_KEY_LENGTH] },
+
+    /// The request succeeded.
+    Ok,
+    /// The request failed; `code` is an [`ErrorCode`] value.
+    Error { code: u16, message: String },
+    /// Proof of key possession required before a recipient-scoped request.
+    Challenge { nonce: [u8; CHALLENGE_NONCE_LENGTH] },
+    /// One serialized envelope, in a FETCH response or a push stream.
+    Message { envelope: Vec<u8> },
+    /// No more messages follow.
+    End,
+    /// Reply to [`Frame::Hello`]. `signature` is the relay identity key's
+    /// signature over [`crate::relay_identity_message`] for this connection.
+    Welcome {
+        capabilities: Capabilities,
+        signature: [u8; SIGNATURE_LENGTH],
+        message: String,
+    },
+    /// Number of messages an [`Frame::Ack`] removed.
+    Acked { count: u32 },
+    /// Reply to [`Frame::Stats`].
+    StatsReply { stats: RelayStats },
+    /// The request was refused by a rate limit; it may be repeated after
+    /// `retry_after_ms` milliseconds.
+    RateLimited { retry_after_ms: u32 },
+    /// One receipt of a message `sender_key` signed, in a RECEIPTS
+    /// response or a receipt push stream.
+    Receipt {
+        sender_key: [u8; PUBLIC_KEY_LENGTH],
+        receipt: Receipt,
+    },
+}
+
+impl Frame {
+    /// Builds an error frame for `code`.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Frame::Error {
+            code: code.as_u16(),
+            message: message.into(),
+        }
+    }
+
+    pub fn frame_type(&self) -> u8 {
+        match self {
+            Frame::Hello { .. } => frame_type::HELLO,
+            Frame::Send { .. } => frame_type::SEND,
+            Frame::Fetch { .. } => frame_type::FETCH,
+            Frame::Ack { .. } => frame_type::ACK,
+            Frame::Subscribe { .. } => frame_type::SUBSCRIBE,
+            Frame::ChallengeResponse { .. } => frame_type::CHALLENGE_RESPONSE,
+            Frame::Stats => frame_type::STATS,
+            Frame::Receipts { .. } => frame_type::RECEIPTS,
+            Frame::Watch { .. } => frame_type::WATCH,
+            Frame::Ok => frame_type::OK,
+            Frame::Error { .. } => frame_type::ERROR,
+            Frame::Challenge { .. } => frame_type::CHALLENGE,
+            Frame::Message { .. } => frame_type::MESSAGE,
+            Frame::End => frame_type::END,
+            Frame::Welcome { .. } => frame_type::WELCOME,
+            Frame::Acked { .. } => frame_type::ACKED,
+            Frame::StatsReply { .. } => frame_type::STATS_REPLY,
+            Frame::RateLimited { .. } => frame_type::RATE_LIMITED,
+            Frame::Receipt { .. } => frame_type::RECEIPT,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Frame::Hello {
+                client_id,
+                min_version,
+                max_version,
+                features,
+            } => [
+                &[*min_version, *max_version][..],
+                &features.bits().to_be_bytes(),
+                client_id.as_bytes(),
+            ]
+            .concat(),
+            Frame::Send { envelope } | Frame::Message { envelope } => envelope.clone(),
+            Frame::Fetch {
+                recipient,
+                lease_secs,
+            } => [&recipient[..], &lease_secs.to_be_bytes()].concat(),
+            Frame::Ack { recipient, msg_ids } => {
+                let mut payload = recipient.to_vec();
+                for id in msg_ids {
+                    payload.extend_from_slice(id.as_bytes());
+                }
+                payload
+            }
+            Frame::Subscribe { recipient } => recipient.to_vec(),
+            Frame::Receipts { sender_key, msg_id } => {
+                [&sender_key[..], msg_id.as_bytes()].concat()
+            }
+            Frame::Watch { sender_key } => sender_key.to_vec(),
+            Frame::ChallengeResponse { signature } => signature.to_vec(),
+            Frame::Ok | Frame::End | Frame::Stats => Vec::new(),
+            Frame::Error { code, message } => {
+                [&code.to_be_bytes()[..], message.as_bytes()].concat()
+            }
+            Frame::Challenge { nonce } => nonce.to_vec(),
+            Frame::Welcome {
+                capabilities,
+                signature,
+                message,
+            } => [
+                &[capabilities.version][..],
+                &capabilities.features.bits().to_be_bytes(),
+                &capabilities.max_payload.to_be_bytes(),
+                &capabilities.max_ttl.to_be_bytes(),
+                &capabilities.relay_key,
+                signature,
+                message.as_bytes(),
+            ]
+            .concat(),
+            Frame::Acked { count } => count.to_be_bytes().to_vec(),
+            Frame::StatsReply { stats } => stats.to_bytes(),
+            Frame::RateLimited { retry_after_ms } => retry_after_ms.to_be_bytes().to_vec(),
+            Frame::Receipt {
+                sender_key,
+                receipt,
+            } => [&sender_key[..], &receipt.to_bytes()].concat(),
+        }
+    }
+
+    /// Header and payload as sent on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = self.payload();
+        let mut bytes = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        bytes.push(PROTOCOL_VERSION);
+        bytes.push(self.frame_type());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Decodes one complete frame, header included.
+    pub fn decode(bytes: &[u8]) -> Result<Self, QightError> {
+        let (header, payload) = bytes
+            .split_first_chunk::<FRAME_HEADER_LEN>()
+            .ok_or(QightError::MalformedFrame)?;
+        let (version, kind, len) = parse_header(header);
+        if !readable_version(version, kind) {
+            return Err(QightError::UnsupportedProtocolVersion);
+        }
+        if len as usize != payload.len() {
+            return Err(QightError::MalformedFrame);
+        }
+        Self::decode_payload(kind, payload)
+    }
+
+    /// Decodes the payload of a frame of type `kind`.
+    pub fn decode_payload(kind: u8, payload: &[u8]) -> Result<Self, QightError> {
+        use frame_type::*;
+        let frame = match kind {
+            HELLO => {
+                let mut reader = Reader(payload);
+                let [min_version, max_version] = reader.take()?;
+                let features = Features::from_bits(u32::from_be_bytes(reader.take()?));
+                Frame::Hello {
+                    client_id: utf8(reader.0)?,
+                    min_version,
+                    max_version,
+                    features,
+                }
+            }
+            SEND => Frame::Send {
+                envelope: payload.to_vec(),
+            },
+            FETCH => {
+                let (recipient, lease) = payload
+                    .split_first_chunk::<PUBLIC_KEY_LENGTH>()
+                    .ok_or(QightError::MalformedFrame)?;
+                Frame::Fetch {
+                    recipient: *recipient,
+                    lease_secs: u32::from_be_bytes(exact(lease)?),
+                }
+            }
+            ACK => {
+                let (recipient, ids) = payload
+                    .split_first_chunk::<PUBLIC_KEY_LENGTH>()
+                    .ok_or(QightError::MalformedFrame)?;
+                if ids.len() % MessageId::LEN != 0 {
+                    return Err(QightError::MalformedFrame);
+                }
+                Frame::Ack {
+                    recipient: *recipient,
+                    msg_ids: ids
+                        .chunks_exact(MessageId::LEN)
+                        .map(|id| MessageId::from_bytes(id.try_into().unwrap()))
+                        .collect(),
+                }
+            }
+            SUBSCRIBE => Frame::Subscribe {
+                recipient: exact(payload)?,
+            },
+            CHALLENGE_RESPONSE => Frame::ChallengeResponse {
+                signature: exact(payload)?,
+            },
+            STATS => {
+                exact::<0>(payload)?;
+                Frame::Stats
+            }
+            RECEIPTS => {
+                let (sender_key, msg_id) = payload
+                    .split_first_chunk::<PUBLIC_KEY_LENGTH>()
+                    .ok_or(QightError::MalformedFrame)?;
+                Frame::Receipts {
+                    sender_key: *sender_key,
+                    msg_id: MessageId::from_bytes(exact(msg_id)?),
+                }
+            }
+            WATCH => Frame::Watch {
+                sender_key: exact(payload)?,
+            },
+            OK => {
+                exact::<0>(payload)?;
+                Frame::Ok
+            }
+            ERROR => {
+                let (code, message) = payload
+                    .split_first_chunk::<2>()
+                    .ok_or(QightError::MalformedFrame)?;
+                Frame::Error {
+                    code: u16::from_be_bytes(*code),
+                    message: utf8(message)?,
+                }
+            }
+            CHALLENGE => Frame::Challenge {
+                nonce: exact(payload)?,
+            },
+            MESSAGE => Frame::Message {
+                envelope: payload.to_vec(),
+            },
+            END => {
+                exact::<0>(payload)?;
+                Frame::End
+            }
+            WELCOME => {
+                let mut reader = Reader(payload);
+                let version = reader.take::<1>()?[0];
+                let features = Features::from_bits(u32::from_be_bytes(reader.take()?));
+                let max_payload = u32::from_be_bytes(reader.take()?);
+                let max_ttl = u32::from_be_bytes(reader.take()?);
+                let relay_key = reader.take()?;
+                let signature = reader.take()?;
+                Frame::Welcome {
+                    capabilities: Capabilities {
+                        version,
+                        features,
+                        max_payload,
+                        max_ttl,
+                        relay_key,
+                    },
+                    signature,
+                    message: utf8(reader.0)?,
+                }
+            }
+            ACKED => Frame::Acked {
+                count: u32::from_be_bytes(exact(payload)?),
+            },
+            STATS_REPLY => Frame::StatsReply {
+                stats: RelayStats::from_bytes(payload)?,
+            },
+            RATE_LIMITED => Frame::RateLimited {
+                retry_after_ms: u32::from_be_bytes(exact(payload)?),
+            },
+            RECEIPT => {
+                let (sender_key, receipt) = payload
+                    .split_first_chunk::<PUBLIC_KEY_LENGTH>()
+                    .ok_or(QightError::MalformedFrame)?;
+                Frame::Receipt {
+                    sender_key: *sender_key,
+                    receipt: Receipt::from_bytes(receipt)?,
+                }
+            }
+            _ => return Err(QightError::UnknownCommand),
+        };
+        Ok(frame)
+    }
+}
+
+fn parse_header(header: &[u8; FRAME_HEADER_LEN]) -> (u8, u8, u32) {
+    let len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+    (header[0], header[1], len)
+}
+
+/// Consumes fixed-size fields from the front of a payload.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], QightError> {
+        let (field, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or(QightError::MalformedFrame)?;
+        self.0 = rest;
+        Ok(*field)
+    }
+}
+
+fn exact<const N: usize>(payload: &[u8]) -> Result<[u8; N], QightError> {
+    payload.try_into().map_err(|_| QightError::MalformedFrame)
+}
+
+fn utf8(payload: &[u8]) -> Result<String, QightError> {
+    String::from_utf8(payload.to_vec()).map_err(|_| QightError::MalformedFrame)
+}
+
+/// Reads one frame whose payload may not exceed `max_payload` bytes.
+///
+/// Returns `Ok(None)` when the stream ends cleanly before a frame starts.
+/// Stream failures are reported as [`QightError::ConnectionLost`]; an
+/// oversized payload is rejected before it is read.
+pub async fn read_frame<R>(reader: &mut R, max_payload: usize) -> Result<Option<Frame>, QightError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let mut filled = 0;
+    while filled < FRAME_HEADER_LEN {
+        match reader.read(&mut header[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(QightError::MalformedFrame),
+            n => filled += n,
+        }
+    }
+
+    let (version, kind, len) = parse_header(&header);
+    if !readable_version(version, kind) {
+        return Err(QightError::UnsupportedProtocolVersion);
+    }
+    if len as usize > max_payload {
+        return Err(QightError::PayloadTooLarge);
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .map_err(|_| QightError::MalformedFrame)?;
+    Ok(Some(Frame::decode_payload(kind, &payload)?))
+}
+
+/// Whether a frame of type `kind` under header `version` can be decoded.
+/// HELLO is laid out the same in every version, so it is read whatever its
+/// header says and its version range settles the version; every other frame
+/// must carry a version this build speaks.
+fn readable_version(version: u8, kind: u8) -> bool {
+    kind == frame_type::HELLO || (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// Writes one frame.
+pub async fn write_frame<W>(writer: &mut W, frame: &Frame) -> Result<(), QightError>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&frame.encode()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ReceiptStatus;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let frames = [
+            Frame::Hello {
+                client_id: "alice".to_string(),
+                min_version: 1,
+                max_version: 1,
+                features: Features::PUSH | Features::ACK,
+            },
+            Frame::Welcome {
+                capabilities: Capabilities {
+                    version: 1,
+                    features: Features::supported(),
+                    max_payload: 1024,
+                    max_ttl: 3600,
+                    relay_key: [9u8; 32],
+                },
+                signature: [8u8; 64],
+                message: "hi".to_string(),
+            },
+            Frame::Fetch {
+                recipient: [7u8; 32],
+                lease_secs: 30,
+            },
+            Frame::Ack {
+                recipient: [7u8; 32],
+                msg_ids: vec![MessageId::from_bytes([1u8; 16]), MessageId::from_bytes([2u8; 16])],
+            },
+            Frame::Ack {
+                recipient: [7u8; 32],
+                msg_ids: Vec::new(),
+            },
+            Frame::error(ErrorCode::InvalidSignature, "Invalid signature"),
+            Frame::Acked { count: 3 },
+            Frame::Stats,
+            Frame::StatsReply {
+                stats: RelayStats {
+                    messages: 4,
+                    payload_bytes: 1 << 40,
+                    inbox_max_messages: Some(100),
+                    evict_oldest: true,
+                    ..RelayStats::default()
+                },
+            },
+            Frame::RateLimited { retry_after_ms: 1500 },
+            Frame::Receipts {
+                sender_key: [5u8; 32],
+                msg_id: MessageId::from_bytes([3u8; 16]),
+            },
+            Frame::Watch { sender_key: [5u8; 32] },
+            Frame::Receipt {
+                sender_key: [5u8; 32],
+                receipt: Receipt {
+                    proof: vec![1, 2, 3],
+                    ..Receipt::new(MessageId::from_bytes([3u8; 16]), ReceiptStatus::Read, 99)
+                },
+            },
+            Frame::End,
+        ];
+        for frame in frames {
+            assert_eq!(Frame::decode(&frame.encode()).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_frames() {
+        let mut wrong_version = Frame::Ok.encode();
+        wrong_version[0] = PROTOCOL_VERSION + 1;
+        assert!(matches!(
+            Frame::decode(&wrong_version),
+            Err(QightError::UnsupportedProtocolVersion)
+        ));
+
+        // A HELLO from a newer client is still read, so the relay can
+        // offer an older version from its range.
+        let hello = Frame::Hello {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION + 1,
+            features: Features::empty(),
+            client_id: "next".to_string(),
+        };
+        let mut newer = hello.encode();
+        newer[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(Frame::decode(&newer).unwrap(), hello);
+
+        let mut truncated = Frame::Subscribe { recipient: [1; 32] }.encode();
+        truncated.pop();
+        assert!(matches!(
+            Frame::decode(&truncated),
+            Err(QightError::MalformedFrame)
+        ));
+
+        let short_ack = Frame::decode_payload(frame_type::ACK, &[0u8; 40]);
+        assert!(matches!(short_ack, Err(QightError::MalformedFrame)));
+
+        assert!(matches!(
+            Frame::decode_payload(0x7f, &[]),
+            Err(QightError::UnknownCommand)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_limits_payload() {
+        let bytes = Frame::Send {
+            envelope: vec![0u8; 64],
+        }
+        .encode();
+
+        let frame = read_frame(&mut &bytes[..], 64).await.unwrap();
+        assert!(matches!(frame, Some(Frame::Send { .. })));
+
+        let err = read_frame(&mut &bytes[..], 63).await.unwrap_err();
+        assert_eq!(err, QightError::PayloadTooLarge);
+
+        assert!(read_frame(&mut &[][..], 64).await.unwrap().is_none());
+        assert!(read_frame(&mut &bytes[..3], 64).await.is_err());
+    }
+}
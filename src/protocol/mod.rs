@@ -0,0 +1,10 @@
+mod capabilities;
+mod codes;
+mod frame;
+mod receipts;
+mod stats;
+pub use capabilities::*;
+pub use codes::*;
+pub use frame::*;
+pub use receipts::*;
+pub use stats::*;
@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::errors::QightError;
+use crate::{MessageEnvelope, MessageId};
+
+/// What happened to a message, as reported to its sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReceiptStatus {
+    /// The relay accepted and stored the message.
+    Stored = 1,
+    /// The message was handed to the recipient by FETCH or push.
+    Delivered = 2,
+    /// The recipient acknowledged the message and the relay deleted it.
+    Acked = 3,
+    /// The TTL elapsed while the message was still stored.
+    Expired = 4,
+    /// The recipient sent a signed read receipt.
+    Read = 5,
+}
+
+impl ReceiptStatus {
+    pub const fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => ReceiptStatus::Stored,
+            2 => ReceiptStatus::Delivered,
+            3 => ReceiptStatus::Acked,
+            4 => ReceiptStatus::Expired,
+            5 => ReceiptStatus::Read,
+            _ => return None,
+        })
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ReceiptStatus::Stored => "stored",
+            ReceiptStatus::Delivered => "delivered",
+            ReceiptStatus::Acked => "acked",
+            ReceiptStatus::Expired => "expired",
+            ReceiptStatus::Read => "read",
+        }
+    }
+}
+
+impl fmt::Display for ReceiptStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One delivery event of a message, recorded by the relay and returned to
+/// the sender by a RECEIPTS query or pushed to its WATCH.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Receipt {
+    pub msg_id: MessageId,
+    pub status: ReceiptStatus,
+    /// When the relay recorded the event, Unix seconds.
+    pub at: u64,
+    /// For [`ReceiptStatus::Read`], the recipient's signed read receipt
+    /// envelope, serialized; empty otherwise.
+    pub proof: Vec<u8>,
+}
+
+/// Encoded size without the proof: `msg_id`, status byte, `at`.
+const RECEIPT_HEADER_LEN: usize = MessageId::LEN + 1 + 8;
+
+impl Receipt {
+    pub fn new(msg_id: MessageId, status: ReceiptStatus, at: u64) -> Self {
+        Self {
+            msg_id,
+            status,
+            at,
+            proof: Vec::new(),
+        }
+    }
+
+    /// RECEIPT payload: `msg_id`, status byte, `at` (big-endian `u64`),
+    /// then the proof.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RECEIPT_HEADER_LEN + self.proof.len());
+        bytes.extend_from_slice(self.msg_id.as_bytes());
+        bytes.push(self.status.as_u8());
+        bytes.extend_from_slice(&self.at.to_be_bytes());
+        bytes.extend_from_slice(&self.proof);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, QightError> {
+        let (header, proof) = bytes
+            .split_first_chunk::<RECEIPT_HEADER_LEN>()
+            .ok_or(QightError::MalformedFrame)?;
+        let msg_id = MessageId::from_bytes(header[..MessageId::LEN].try_into().unwrap());
+        let status =
+            ReceiptStatus::from_u8(header[MessageId::LEN]).ok_or(QightError::MalformedFrame)?;
+        let at = u64::from_be_bytes(header[MessageId::LEN + 1..].try_into().unwrap());
+        Ok(Receipt {
+            msg_id,
+            status,
+            at,
+            proof: proof.to_vec(),
+        })
+    }
+
+    /// The read receipt envelope proving a [`ReceiptStatus::Read`] receipt:
+    /// it decodes, its signature verifies and it names this `msg_id`. Its
+    /// `sender_key` is the reader.
+    pub fn read_receipt(&self) -> Option<MessageEnvelope> {
+        if self.status != ReceiptStatus::Read {
+            return None;
+        }
+        let envelope = MessageEnvelope::from_bytes(&self.proof).ok()?;
+        let read = envelope.read_receipt()?;
+        (read.msg_id == self.msg_id && envelope.verify()).then_some(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_keypair;
+
+    #[test]
+    fn test_receipt_round_trip_and_read_proof() {
+        let (recipient_key, recipient_priv) = gen_keypair();
+        let (sender_key, _) = gen_keypair();
+        let original = MessageEnvelope::new("alice".into(), recipient_key, sender_key, vec![1], 60);
+
+        let stored = Receipt::new(original.msg_id, ReceiptStatus::Stored, 100);
+        assert_eq!(Receipt::from_bytes(&stored.to_bytes()).unwrap(), stored);
+        assert!(stored.read_receipt().is_none());
+
+        let mut proof = MessageEnvelope::read_receipt_for(&original, "bob".into(), 120);
+        proof.sign(&recipient_priv);
+        let read = Receipt {
+            proof: proof.to_bytes().unwrap(),
+            ..Receipt::new(original.msg_id, ReceiptStatus::Read, 121)
+        };
+        let decoded = Receipt::from_bytes(&read.to_bytes()).unwrap();
+        assert_eq!(decoded.read_receipt().unwrap().sender_key, recipient_key);
+
+        let other = Receipt {
+            msg_id: MessageId::generate(),
+            ..read.clone()
+        };
+        assert!(other.read_receipt().is_none());
+
+        let mut bytes = stored.to_bytes();
+        bytes[MessageId::LEN] = 9;
+        assert_eq!(Receipt::from_bytes(&bytes), Err(QightError::MalformedFrame));
+        assert_eq!(Receipt::from_bytes(&bytes[..10]), Err(QightError::MalformedFrame));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::errors::QightError;
+
+/// Relay storage figures and quota settings, returned by a STATS request.
+///
+/// Limits are `None` when unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RelayStats {
+    /// Stored messages, including leased ones.
+    pub messages: u64,
+    /// Messages currently hidden by an unexpired lease.
+    pub leased: u64,
+    /// Distinct recipients with at least one stored message.
+    pub recipients: u64,
+    /// Total payload bytes stored.
+    pub payload_bytes: u64,
+    /// SENDs refused for exceeding a quota since the relay started.
+    pub quota_rejections: u64,
+    /// Messages evicted from full inboxes since the relay started.
+    pub quota_evictions: u64,
+    /// Expired messages purged by the background sweeper since the relay
+    /// started.
+    pub expired: u64,
+    /// Budget for [`RelayStats::payload_bytes`].
+    pub max_total_bytes: Option<u64>,
+    pub inbox_max_messages: Option<u64>,
+    pub inbox_max_bytes: Option<u64>,
+    pub sender_max_messages: Option<u64>,
+    pub sender_max_bytes: Option<u64>,
+    /// Whether full inboxes drop their oldest message instead of rejecting.
+    pub evict_oldest: bool,
+}
+
+/// Encoded size: twelve `u64`s and a flag byte.
+const STATS_LEN: usize = 12 * 8 + 1;
+
+impl RelayStats {
+    /// STATS reply payload: the counters, then the limits with `0` for
+    /// unlimited, all big-endian `u64`, then the eviction flag.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let limit = |value: Option<u64>| value.unwrap_or(0);
+        let fields = [
+            self.messages,
+            self.leased,
+            self.recipients,
+            self.payload_bytes,
+            self.quota_rejections,
+            self.quota_evictions,
+            self.expired,
+            limit(self.max_total_bytes),
+            limit(self.inbox_max_messages),
+            limit(self.inbox_max_bytes),
+            limit(self.sender_max_messages),
+            limit(self.sender_max_bytes),
+        ];
+        let mut bytes = Vec::with_capacity(STATS_LEN);
+        for field in fields {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes.push(self.evict_oldest as u8);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, QightError> {
+        if bytes.len() != STATS_LEN || bytes[STATS_LEN - 1] > 1 {
+            return Err(QightError::MalformedFrame);
+        }
+        let field = |i: usize| u64::from_be_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        let limit = |i: usize| Some(field(i)).filter(|&value| value > 0);
+        Ok(RelayStats {
+            messages: field(0),
+            leased: field(1),
+            recipients: field(2),
+            payload_bytes: field(3),
+            quota_rejections: field(4),
+            quota_evictions: field(5),
+            expired: field(6),
+            max_total_bytes: limit(7),
+            inbox_max_messages: limit(8),
+            inbox_max_bytes: limit(9),
+            sender_max_messages: limit(10),
+            sender_max_bytes: limit(11),
+            evict_oldest: bytes[STATS_LEN - 1] == 1,
+        })
+    }
+}
@@ -0,0 +1,552 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+
+use crate::relay::logging::LoggingConfig;
+use crate::relay::metrics::MetricsConfig;
+use crate::relay::quota::QuotaPolicy;
+use crate::relay::rate_limit::RateLimitPolicy;
+use crate::relay::sweeper::SweeperConfig;
+use crate::relay::validation::ValidationPolicy;
+
+/// Frames are length-prefixed with a `u32`, so nothing larger can be sent.
+const MAX_PAYLOAD_LIMIT: usize = u32::MAX as usize;
+
+/// Rejected relay configuration.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read config file {path}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config file {path}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid value for `{field}`: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+/// Relay configuration, loadable from TOML. Every field has a default, so an
+/// empty file is valid.
+///
+/// ```toml
+/// bind = "127.0.0.1:4433"
+/// max_concurrent_streams = 100
+/// max_payload_bytes = 10000428
+/// drain_timeout_secs = 10
+/// identity_key = "relay_identity"
+///
+/// [validation]
+/// max_ttl_secs = 604800
+/// min_ttl_secs = 1
+/// max_clock_skew_secs = 300
+/// max_sender_len = 256
+/// max_payload_len = 10000000
+/// duplicates = "accept"
+///
+/// [quotas]
+/// inbox_max_messages = 1000
+/// inbox_max_bytes = 50000000
+/// sender_max_messages = 10000
+/// max_total_bytes = 10000000000
+/// on_full = "evict_oldest"
+///
+/// [rate_limits]
+/// connections_per_ip = { per_sec = 1.0, burst = 20 }
+/// sends_per_connection = { per_sec = 50.0, burst = 100 }
+/// sends_per_sender = { per_sec = 10.0, burst = 50 }
+///
+/// [sweeper]
+/// enabled = true
+/// interval_secs = 60
+/// batch_size = 1000
+/// incremental_vacuum = false
+/// wal_checkpoint = false
+///
+/// [metrics]
+/// enabled = true
+/// bind = "127.0.0.1:9464"
+///
+/// [logging]
+/// format = "json"
+/// filter = "info,qight=debug"
+///
+/// [tls]
+/// cert = "server_cert"
+/// key = "server_key"
+///
+/// [storage]
+/// backend = "sqlite"
+/// path = "quic.db"
+/// pool_size = 15
+///
+/// [mdns]
+/// enabled = true
+/// instance_name = "qight-relay"
+///
+/// [mdns.properties]
+/// region = "eu"
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Address the QUIC endpoint listens on.
+    pub bind: SocketAddr,
+    /// Maximum concurrent bidirectional streams per connection.
+    pub max_concurrent_streams: u32,
+    /// Largest serialized envelope accepted by SEND, in bytes. Must fit an
+    /// envelope at the `[validation]` sender and payload limits; the default
+    /// is exactly that.
+    pub max_payload_bytes: usize,
+    /// Seconds shutdown waits for in-flight requests before closing
+    /// connections anyway.
+    pub drain_timeout_secs: u64,
+    /// Raw 32-byte Ed25519 secret key file for the relay identity, generated
+    /// with mode 0600 if missing. Without it the relay uses a new identity
+    /// on every start.
+    pub identity_key: Option<PathBuf>,
+    pub validation: ValidationPolicy,
+    pub quotas: QuotaPolicy,
+    pub rate_limits: RateLimitPolicy,
+    pub sweeper: SweeperConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+    pub tls: TlsConfig,
+    pub storage: StorageConfig,
+    pub mdns: MdnsConfig,
+}
+
+/// DER certificate and PKCS#8 key paths. A self-signed pair is generated at
+/// these paths when the certificate does not exist.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Sqlite,
+    Memory,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// SQLite database file. Ignored by the memory backend.
+    pub path: PathBuf,
+    /// Pooled SQLite connections.
+    pub pool_size: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MdnsConfig {
+    pub enabled: bool,
+    /// Service instance name, also used for the advertised host name.
+    pub instance_name: String,
+    /// TXT record key/value pairs.
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 4433)),
+            max_concurrent_streams: 100,
+            max_payload_bytes: ValidationPolicy::default().max_envelope_len(),
+            drain_timeout_secs: 10,
+            identity_key: None,
+            validation: ValidationPolicy::default(),
+            quotas: QuotaPolicy::default(),
+            rate_limits: RateLimitPolicy::default(),
+            sweeper: SweeperConfig::default(),
+            metrics: MetricsConfig::default(),
+            logging: LoggingConfig::default(),
+            tls: TlsConfig::default(),
+            storage: StorageConfig::default(),
+            mdns: MdnsConfig::default(),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert: PathBuf::from("server_cert"),
+            key: PathBuf::from("server_key"),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Sqlite,
+            path: PathBuf::from("quic.db"),
+            pool_size: 15,
+        }
+    }
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            instance_name: "qight-relay".to_string(),
+            properties: BTreeMap::new(),
+        }
+    }
+}
+
+impl RelayConfig {
+    /// Reads and validates a TOML config file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config: RelayConfig = toml::from_str(&text).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks values that parse but cannot work.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn invalid(field: &'static str, reason: impl Into<String>) -> ConfigError {
+            ConfigError::Invalid {
+                field,
+                reason: reason.into(),
+            }
+        }
+
+        if self.max_concurrent_streams == 0 {
+            return Err(invalid("max_concurrent_streams", "must be at least 1"));
+        }
+        if self.max_payload_bytes == 0 || self.max_payload_bytes > MAX_PAYLOAD_LIMIT {
+            return Err(invalid(
+                "max_payload_bytes",
+                format!("must be between 1 and {}", MAX_PAYLOAD_LIMIT),
+            ));
+        }
+
+        if self
+            .identity_key
+            .as_ref()
+            .is_some_and(|path| path.as_os_str().is_empty())
+        {
+            return Err(invalid("identity_key", "must not be empty"));
+        }
+
+        let validation = &self.validation;
+        if validation.max_ttl_secs == 0 {
+            return Err(invalid("validation.max_ttl_secs", "must be at least 1"));
+        }
+        if validation.min_ttl_secs > validation.max_ttl_secs {
+            return Err(invalid(
+                "validation.min_ttl_secs",
+                "must not exceed validation.max_ttl_secs",
+            ));
+        }
+        if validation.max_sender_len == 0 {
+            return Err(invalid("validation.max_sender_len", "must be at least 1"));
+        }
+        if validation.max_payload_len == 0 {
+            return Err(invalid("validation.max_payload_len", "must be at least 1"));
+        }
+        if self.max_payload_bytes < validation.max_envelope_len() {
+            return Err(invalid(
+                "max_payload_bytes",
+                format!(
+                    "must fit an envelope at the validation limits, at least {}",
+                    validation.max_envelope_len()
+                ),
+            ));
+        }
+
+        let quotas = [
+            ("quotas.inbox_max_messages", self.quotas.inbox_max_messages),
+            ("quotas.inbox_max_bytes", self.quotas.inbox_max_bytes),
+            ("quotas.sender_max_messages", self.quotas.sender_max_messages),
+            ("quotas.sender_max_bytes", self.quotas.sender_max_bytes),
+            ("quotas.max_total_bytes", self.quotas.max_total_bytes),
+        ];
+        for (field, limit) in quotas {
+            if limit == Some(0) {
+                return Err(invalid(field, "must be at least 1; omit it for no limit"));
+            }
+        }
+
+        self.rate_limits.validate()?;
+
+        if self.sweeper.interval_secs == 0 {
+            return Err(invalid("sweeper.interval_secs", "must be at least 1"));
+        }
+        if self.sweeper.batch_size == 0 {
+            return Err(invalid("sweeper.batch_size", "must be at least 1"));
+        }
+        if let Some(Err(e)) = self.logging.filter.as_deref().map(EnvFilter::try_new) {
+            return Err(invalid("logging.filter", e.to_string()));
+        }
+
+        if self.tls.cert.as_os_str().is_empty() {
+            return Err(invalid("tls.cert", "must not be empty"));
+        }
+        if self.tls.key.as_os_str().is_empty() {
+            return Err(invalid("tls.key", "must not be empty"));
+        }
+        if self.tls.cert.exists() && !self.tls.key.exists() {
+            return Err(invalid(
+                "tls.key",
+                format!(
+                    "{} does not exist but certificate {} does",
+                    self.tls.key.display(),
+                    self.tls.cert.display()
+                ),
+            ));
+        }
+
+        if self.storage.pool_size == 0 {
+            return Err(invalid("storage.pool_size", "must be at least 1"));
+        }
+        if self.storage.backend == StorageBackend::Sqlite && self.storage.path.as_os_str().is_empty() {
+            return Err(invalid("storage.path", "must not be empty for the sqlite backend"));
+        }
+
+        let name = &self.mdns.instance_name;
+        if name.is_empty() || name.len() > 63 {
+            return Err(invalid("mdns.instance_name", "must be 1 to 63 characters"));
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(invalid(
+                "mdns.instance_name",
+                "may only contain ASCII letters, digits and '-'",
+            ));
+        }
+        for (key, value) in &self.mdns.properties {
+            if key.is_empty() || key.contains('=') || !key.chars().all(|c| c.is_ascii_graphic()) {
+                return Err(invalid(
+                    "mdns.properties",
+                    format!("invalid TXT key {:?}", key),
+                ));
+            }
+            if key.len() + value.len() + 1 > 255 {
+                return Err(invalid(
+                    "mdns.properties",
+                    format!("TXT entry {:?} exceeds 255 bytes", key),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::logging::LogFormat;
+    use crate::relay::quota::OverflowPolicy;
+    use crate::relay::rate_limit::RateLimit;
+    use crate::relay::validation::DuplicatePolicy;
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config: RelayConfig = toml::from_str("").unwrap();
+        assert_eq!(config, RelayConfig::default());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_full_config_parses() {
+        let config: RelayConfig = toml::from_str(
+            r#"
+            bind = "0.0.0.0:5000"
+            max_concurrent_streams = 8
+            max_payload_bytes = 1024
+            drain_timeout_secs = 3
+            identity_key = "id.key"
+
+            [validation]
+            max_payload_len = 512
+            max_ttl_secs = 86400
+            max_clock_skew_secs = 30
+            duplicates = "reject"
+
+            [quotas]
+            inbox_max_messages = 10
+            max_total_bytes = 4096
+            on_full = "evict_oldest"
+
+            [rate_limits]
+            sends_per_sender = { per_sec = 0.5, burst = 4 }
+
+            [sweeper]
+            interval_secs = 5
+            wal_checkpoint = true
+
+            [metrics]
+            enabled = true
+
+            [logging]
+            format = "json"
+            filter = "warn,qight=debug"
+
+            [tls]
+            cert = "c.der"
+            key = "k.der"
+
+            [storage]
+            backend = "memory"
+            pool_size = 2
+
+            [mdns]
+            enabled = false
+            instance_name = "edge-1"
+
+            [mdns.properties]
+            region = "eu"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.bind, "0.0.0.0:5000".parse().unwrap());
+        assert_eq!(config.max_concurrent_streams, 8);
+        assert_eq!(config.max_payload_bytes, 1024);
+        assert_eq!(config.drain_timeout_secs, 3);
+        assert_eq!(config.validation.max_ttl_secs, 86400);
+        assert_eq!(config.validation.max_clock_skew_secs, 30);
+        assert_eq!(config.validation.max_sender_len, 256);
+        assert_eq!(config.validation.duplicates, DuplicatePolicy::Reject);
+        assert_eq!(config.quotas.inbox_max_messages, Some(10));
+        assert_eq!(config.quotas.inbox_max_bytes, None);
+        assert_eq!(config.quotas.max_total_bytes, Some(4096));
+        assert_eq!(config.quotas.on_full, OverflowPolicy::EvictOldest);
+        assert_eq!(
+            config.rate_limits.sends_per_sender,
+            Some(RateLimit::new(0.5, 4))
+        );
+        assert_eq!(config.rate_limits.connections_per_ip, None);
+        assert_eq!(config.sweeper.interval_secs, 5);
+        assert_eq!(config.sweeper.batch_size, 1000);
+        assert!(config.sweeper.wal_checkpoint && config.sweeper.enabled);
+        assert!(config.metrics.enabled);
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert_eq!(config.logging.filter.as_deref(), Some("warn,qight=debug"));
+        assert_eq!(config.metrics.bind, "127.0.0.1:9464".parse().unwrap());
+        assert_eq!(config.identity_key, Some(PathBuf::from("id.key")));
+        assert_eq!(config.tls.cert, PathBuf::from("c.der"));
+        assert_eq!(config.storage.backend, StorageBackend::Memory);
+        assert_eq!(config.storage.path, PathBuf::from("quic.db"));
+        assert!(!config.mdns.enabled);
+        assert_eq!(config.mdns.properties["region"], "eu");
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        assert!(toml::from_str::<RelayConfig>("bnid = \"127.0.0.1:1\"").is_err());
+        assert!(toml::from_str::<RelayConfig>("[storage]\nbackend = \"postgres\"").is_err());
+    }
+
+    #[test]
+    fn test_invalid_values_rejected() {
+        let config = RelayConfig {
+            max_payload_bytes: 0,
+            ..RelayConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "max_payload_bytes", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.validation.max_payload_len += 1;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "max_payload_bytes", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.validation.max_ttl_secs = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "validation.max_ttl_secs", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.validation.min_ttl_secs = config.validation.max_ttl_secs + 1;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "validation.min_ttl_secs", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.quotas.sender_max_bytes = Some(0);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "quotas.sender_max_bytes", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.rate_limits.fetches_per_ip = Some(RateLimit::new(0.0, 5));
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "rate_limits.fetches_per_ip", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.sweeper.batch_size = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "sweeper.batch_size", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.logging.filter = Some("qight=loud".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "logging.filter", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.storage.pool_size = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "storage.pool_size", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.mdns.instance_name = "my relay".to_string();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "mdns.instance_name", .. })
+        ));
+
+        let mut config = RelayConfig::default();
+        config.mdns.properties.insert("a=b".to_string(), "c".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid { field: "mdns.properties", .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_file_reports_path() {
+        let err = RelayConfig::from_file("/nonexistent/qight.toml").unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/qight.toml"));
+    }
+}
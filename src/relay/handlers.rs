@@ -0,0 +1,701 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SIGNATURE_LENGTH};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{watch, Notify};
+use tracing::{debug, error, info, warn, Instrument, Span};
+
+use crate::errors::QightError;
+use crate::protocol::{
+    negotiate_version, read_frame, write_frame, Capabilities, ErrorCode, Features, Frame, Receipt,
+    ReceiptStatus, RelayStats,
+};
+use crate::relay::metrics::{Command, RateLimitScope, RelayMetrics, SignatureCheck};
+use crate::relay::quota::{Admission, Quotas};
+use crate::relay::rate_limit::{retry_after_ms, ConnectionLimits, RateLimiter};
+use crate::relay::store::{MessageStore, ReceiptEvent};
+use crate::relay::subscriptions::{self, Subscriptions};
+use crate::relay::sweeper::Sweeper;
+use crate::relay::validation::{DuplicatePolicy, ValidationPolicy};
+use crate::{
+    challenge_message, gen_challenge_nonce, key_fingerprint, relay_identity_message, sign_message,
+    verify_message, MessageEnvelope, MessageId, CHALLENGE_EXPORTER_LABEL,
+    RELAY_IDENTITY_EXPORTER_LABEL,
+};
+
+/// Longest visibility timeout a client may request for leased FETCH.
+const MAX_LEASE_SECS: u64 = 3600;
+
+/// State shared by every connection a relay accepts.
+pub(crate) struct RelayContext {
+    pub store: Arc<dyn MessageStore>,
+    pub max_payload_bytes: usize,
+    pub validation: ValidationPolicy,
+    pub quotas: Quotas,
+    pub rate_limiter: RateLimiter,
+    /// Features the relay offers in WELCOME.
+    pub features: Features,
+    pub identity_key: [u8; PUBLIC_KEY_LENGTH],
+    pub identity_secret: [u8; SECRET_KEY_LENGTH],
+    pub subscriptions: Subscriptions,
+    /// Connections watching receipts, keyed by sender key.
+    pub receipt_watchers: Subscriptions,
+    pub sweeper: Arc<Sweeper>,
+    pub metrics: RelayMetrics,
+    pub in_flight: Arc<InFlight>,
+    /// Flips to `true` when the relay starts shutting down.
+    pub shutdown: watch::Receiver<bool>,
+}
+
+/// Streams being handled and pushes being sent, so shutdown can wait for
+/// them to finish.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    streams: AtomicUsize,
+    idle: Notify,
+}
+
+impl InFlight {
+    /// Counts a stream or push until the returned guard drops. The guard
+    /// owns a handle, so it can be taken before the task is spawned.
+    pub fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.streams.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.streams.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once no stream is being handled.
+    pub async fn idle(&self) {
+        loop {
+            let idle = self.idle.notified();
+            if self.count() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+pub(crate) struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.streams.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+pub(crate) async fn handle_connection(
+    connection: quinn::Connection,
+    context: Arc<RelayContext>,
+) -> Result<()> {
+    let limits = Arc::new(context.rate_limiter.connection(connection.remote_address().ip()));
+    let mut shutdown = context.shutdown.clone();
+    loop {
+        // Streams opened once shutdown starts are left unanswered; the
+        // relay closes the connection when in-flight ones are done.
+        let (send, recv) = tokio::select! {
+            accepted = connection.accept_bi() => match accepted {
+                Ok(stream) => stream,
+                Err(_) => break,
+            },
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        // Counted before spawning, so a drain that starts right after the
+        // accept still waits for this stream.
+        let in_flight = context.in_flight.enter();
+        let storage = context.clone();
+        let connection = connection.clone();
+        let limits = limits.clone();
+        let span = tracing::debug_span!(
+            "stream",
+            id = %send.id(),
+            command = tracing::field::Empty,
+            recipient = tracing::field::Empty,
+            msg_id = tracing::field::Empty,
+        );
+        tokio::spawn(
+            async move {
+                let _in_flight = in_flight;
+                let context = storage.clone();
+                if let Err(e) = handle_stream(send, recv, context, connection, &limits).await {
+                    warn!(error = %e, "stream failed");
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    context.subscriptions.remove_connection(connection.stable_id());
+    context.receipt_watchers.remove_connection(connection.stable_id());
+    Ok(())
+}
+
+async fn handle_stream(
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+    storage: Arc<RelayContext>,
+    connection: quinn::Connection,
+    limits: &ConnectionLimits,
+) -> Result<()> {
+    let request = match read_frame(&mut recv, storage.max_payload_bytes).await {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            // Answer protocol violations with their code before giving up.
+            if let Some(code) = ErrorCode::for_error(&e) {
+                respond(&mut send, &Frame::error(code, e.to_string())).await?;
+                send.finish()?;
+            }
+            return Err(e.into());
+        }
+    };
+
+    let command = Command::of(&request);
+    if let Some(command) = command {
+        Span::current().record("command", command.as_str());
+    }
+    let started = Instant::now();
+    let handled = match request {
+        Frame::Hello {
+            client_id,
+            min_version,
+            max_version,
+            features,
+        } => {
+            let versions = (min_version, max_version);
+            handle_hello(&client_id, versions, features, &mut send, &storage, &connection).await
+        }
+        Frame::Send { envelope } => match storage.rate_limiter.send(limits) {
+            Ok(()) => handle_send(envelope, &mut send, storage.clone()).await,
+            Err(delay) => rate_limited(&mut send, &storage, RateLimitScope::Send, delay).await,
+        },
+        Frame::Fetch {
+            recipient,
+            lease_secs,
+        } => {
+            // A non-zero lease switches to at-least-once delivery.
+            let lease = (lease_secs > 0).then(|| (lease_secs as u64).min(MAX_LEASE_SECS));
+            match storage.rate_limiter.fetch(limits) {
+                Ok(()) => {
+                    let context = storage.clone();
+                    handle_fetch(recipient, lease, &mut recv, &mut send, context, &connection).await
+                }
+                Err(delay) => rate_limited(&mut send, &storage, RateLimitScope::Fetch, delay).await,
+            }
+        }
+        Frame::Ack { recipient, msg_ids } => {
+            let context = storage.clone();
+            handle_ack(recipient, msg_ids, &mut recv, &mut send, context, &connection).await
+        }
+        Frame::Subscribe { recipient } => {
+            let context = storage.clone();
+            handle_subscribe(recipient, &mut recv, &mut send, context, &connection).await
+        }
+        Frame::Stats => handle_stats(&mut send, storage.clone()).await,
+        Frame::Receipts { sender_key, msg_id } => match storage.rate_limiter.fetch(limits) {
+            Ok(()) => {
+                let context = storage.clone();
+                handle_receipts(sender_key, msg_id, &mut recv, &mut send, context, &connection)
+                    .await
+            }
+            Err(delay) => rate_limited(&mut send, &storage, RateLimitScope::Fetch, delay).await,
+        },
+        Frame::Watch { sender_key } => {
+            let context = storage.clone();
+            handle_watch(sender_key, &mut recv, &mut send, context, &connection).await
+        }
+        other => {
+            let message = format!("unexpected frame type {:#04x}", other.frame_type());
+            respond(&mut send, &Frame::error(ErrorCode::UnknownCommand, message)).await
+        }
+    };
+    let elapsed = started.elapsed();
+    if let Some(command) = command {
+        storage.metrics.request(command, elapsed);
+    }
+    debug!(latency_ms = elapsed.as_secs_f64() * 1000.0, "request handled");
+    handled?;
+    send.finish().context("failed to finish sending stream")?;
+    Ok(())
+}
+
+async fn respond(send: &mut quinn::SendStream, frame: &Frame) -> Result<()> {
+    write_frame(send, frame).await.context("failed to write response")
+}
+
+async fn rate_limited(
+    send: &mut quinn::SendStream,
+    context: &RelayContext,
+    scope: RateLimitScope,
+    delay: Duration,
+) -> Result<()> {
+    context.metrics.rate_limited(scope);
+    let retry_after_ms = retry_after_ms(delay);
+    respond(send, &Frame::RateLimited { retry_after_ms }).await
+}
+
+/// Issues a challenge on the stream and checks that the client answered
+/// with a signature from `key`'s secret key, bound to `connection`.
+async fn authenticate_key(
+    key: &[u8; PUBLIC_KEY_LENGTH],
+    recv: &mut quinn::RecvStream,
+    send: &mut quinn::SendStream,
+    connection: &quinn::Connection,
+    metrics: &RelayMetrics,
+) -> Result<bool> {
+    let nonce = gen_challenge_nonce();
+    respond(send, &Frame::Challenge { nonce }).await?;
+
+    let signature = match read_frame(recv, SIGNATURE_LENGTH).await {
+        Ok(Some(Frame::ChallengeResponse { signature })) => signature,
+        _ => {
+            debug!("challenge unanswered");
+            metrics.signature_failed(SignatureCheck::Challenge);
+            return Ok(false);
+        }
+    };
+
+    let mut binding = [0u8; 32];
+    connection
+        .export_keying_material(&mut binding, CHALLENGE_EXPORTER_LABEL, &nonce)
+        .map_err(|_| anyhow::anyhow!("failed to export keying material"))?;
+
+    let message = challenge_message(&nonce, key, &binding);
+    let verified = verify_message(key, &message, &signature);
+    if !verified {
+        debug!("challenge signature invalid");
+        metrics.signature_failed(SignatureCheck::Challenge);
+    }
+    Ok(verified)
+}
+
+/// Picks the protocol version and common features, and signs the relay
+/// identity key over the connection's channel binding.
+async fn handle_hello(
+    client_id: &str,
+    (min_version, max_version): (u8, u8),
+    features: Features,
+    send: &mut quinn::SendStream,
+    context: &RelayContext,
+    quic: &quinn::Connection,
+) -> Result<()> {
+    debug!(client_id, min_version, max_version, "hello received");
+
+    let Some(version) = negotiate_version(min_version, max_version) else {
+        let message = format!("no common version in {}..={}", min_version, max_version);
+        return respond(send, &Frame::error(ErrorCode::UnsupportedVersion, message)).await;
+    };
+
+    let mut binding = [0u8; 32];
+    quic.export_keying_material(&mut binding, RELAY_IDENTITY_EXPORTER_LABEL, &[])
+        .map_err(|_| anyhow::anyhow!("failed to export keying material"))?;
+    let signature = sign_message(
+        &context.identity_secret,
+        &relay_identity_message(&context.identity_key, &binding),
+    );
+
+    let capabilities = Capabilities {
+        version,
+        features: context.features.intersection(features),
+        max_payload: context.max_payload_bytes.try_into().unwrap_or(u32::MAX),
+        max_ttl: context.validation.max_ttl_secs,
+        relay_key: context.identity_key,
+    };
+    let message = format!("Welcome, {:?}", client_id);
+    respond(
+        send,
+        &Frame::Welcome {
+            capabilities,
+            signature,
+            message,
+        },
+    )
+    .await
+}
+
+async fn handle_send(
+    payload: Vec<u8>,
+    send: &mut quinn::SendStream,
+    context: Arc<RelayContext>,
+) -> Result<()> {
+    let envelope = match MessageEnvelope::from_bytes(&payload) {
+        Ok(envelope) => envelope,
+        Err(QightError::V1Envelope) => {
+            debug!(bytes = payload.len(), "version 1 envelope");
+            let reply = Frame::error(ErrorCode::InvalidEnvelope, "version 1 envelope");
+            return respond(send, &reply).await;
+        }
+        Err(_) => {
+            debug!(bytes = payload.len(), "undecodable envelope");
+            let reply = Frame::error(ErrorCode::InvalidEnvelope, "cannot decode envelope");
+            return respond(send, &reply).await;
+        }
+    };
+
+    let span = Span::current();
+    span.record("recipient", key_fingerprint(&envelope.recipient));
+    span.record("msg_id", envelope.msg_id.to_string());
+
+    if let Err(e) = context.validation.check(&envelope, unix_now()) {
+        debug!(error = %e, "envelope rejected");
+        if matches!(e, QightError::InvalidSignature) {
+            context.metrics.signature_failed(SignatureCheck::Envelope);
+        }
+        let code = ErrorCode::for_error(&e).unwrap_or(ErrorCode::InvalidEnvelope);
+        return respond(send, &Frame::error(code, rejection_message(&e, &context.validation))).await;
+    }
+    // Only a verified signature proves which sender to charge.
+    if let Err(delay) = context.rate_limiter.sender(envelope.sender_key) {
+        return rate_limited(send, &context, RateLimitScope::Send, delay).await;
+    }
+
+    let envelope_clone = envelope.clone();
+    let admitting = context.clone();
+    let now = unix_now();
+    let admission = tokio::task::spawn_blocking(move || {
+        admitting
+            .quotas
+            .store(admitting.store.as_ref(), &envelope_clone, now)
+    })
+    .await?;
+    let admission = admission.map(|(admission, expired)| {
+        // Purged to make room; pushed like the sweeper's.
+        subscriptions::publish_receipts(&context, expired);
+        admission
+    });
+    match admission {
+        Ok(Admission::Stored) => {}
+        Ok(Admission::Duplicate) => {
+            // A retried SEND, answered like the first. That copy was already
+            // stored and pushed, and may since have been delivered.
+            return match context.validation.duplicates {
+                DuplicatePolicy::Accept => respond(send, &Frame::Ok).await,
+                DuplicatePolicy::Reject => {
+                    let reply = Frame::error(ErrorCode::DuplicateMessage, "msg_id already accepted");
+                    respond(send, &reply).await
+                }
+            };
+        }
+        Ok(Admission::Conflict) => {
+            info!("msg_id reused for a different message");
+            let reply = Frame::error(
+                ErrorCode::MessageConflict,
+                "msg_id already used for a different message",
+            );
+            return respond(send, &reply).await;
+        }
+        Ok(Admission::Rejected(limit)) => {
+            info!(%limit, "message rejected over quota");
+            let reply = Frame::error(ErrorCode::QuotaExceeded, limit.to_string());
+            return respond(send, &reply).await;
+        }
+        Err(e) => {
+            error!(error = %e, "failed to store message");
+            let reply = Frame::error(ErrorCode::Internal, "cannot store message");
+            return respond(send, &reply).await;
+        }
+    }
+    info!(bytes = envelope.payload.len(), "message stored");
+    let stored = ReceiptEvent {
+        sender_key: envelope.sender_key,
+        receipt: Receipt::new(envelope.msg_id, ReceiptStatus::Stored, now),
+    };
+    subscriptions::publish_receipts(&context, vec![stored]);
+    respond(send, &Frame::Ok).await?;
+
+    if envelope.read_receipt().is_some() {
+        record_read(&context, envelope.clone(), payload.clone()).await;
+    }
+
+    // Stored messages stay in the inbox until the subscriber ACKs them, so a
+    // failed push only costs a later FETCH.
+    for subscriber in context.subscriptions.connections_for(&envelope.recipient) {
+        let frame = Frame::Message {
+            envelope: payload.clone(),
+        };
+        let context = context.clone();
+        let msg_id = envelope.msg_id;
+        let in_flight = context.in_flight.enter();
+        tokio::spawn(
+            async move {
+                let _in_flight = in_flight;
+                match subscriptions::push(&subscriber, frame).await {
+                    Ok(()) => {
+                        let delivered = [msg_id];
+                        record_receipts(&context, move |store| {
+                            record_all(store, &delivered, ReceiptStatus::Delivered)
+                        })
+                        .await
+                    }
+                    Err(e) => {
+                        warn!(subscriber = %subscriber.remote_address(), error = %e, "push failed")
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    Ok(())
+}
+
+/// Records a Read receipt for the message a read receipt envelope names,
+/// with the envelope as proof. Only the message's recipient can confirm
+/// reading it, and only to its sender; anything else is stored and
+/// delivered as an ordinary message but records nothing.
+async fn record_read(context: &Arc<RelayContext>, envelope: MessageEnvelope, proof: Vec<u8>) {
+    record_receipts(context, move |store| {
+        let Some(read) = envelope.read_receipt() else {
+            return Ok(Vec::new());
+        };
+        let Some(log) = store.receipts(&read.msg_id)? else {
+            return Ok(Vec::new());
+        };
+        if log.recipient != envelope.sender_key || log.sender_key != envelope.recipient {
+            debug!(read = %read.msg_id, "read receipt from a stranger ignored");
+            return Ok(Vec::new());
+        }
+        let receipt = Receipt {
+            proof,
+            ..Receipt::new(read.msg_id, ReceiptStatus::Read, unix_now())
+        };
+        Ok(store.record_receipt(&receipt)?.into_iter().collect())
+    })
+    .await
+}
+
+/// Records `status` for each of `msg_ids` that still has a receipt log.
+fn record_all(
+    store: &dyn MessageStore,
+    msg_ids: &[MessageId],
+    status: ReceiptStatus,
+) -> Result<Vec<ReceiptEvent>> {
+    let now = unix_now();
+    let mut events = Vec::new();
+    for msg_id in msg_ids {
+        events.extend(store.record_receipt(&Receipt::new(*msg_id, status, now))?);
+    }
+    Ok(events)
+}
+
+/// Runs `record` against the store off the async runtime and pushes the
+/// receipts it recorded to their senders' watchers. Receipts are best
+/// effort: a failure is logged and never fails the request.
+async fn record_receipts<F>(context: &Arc<RelayContext>, record: F)
+where
+    F: FnOnce(&dyn MessageStore) -> Result<Vec<ReceiptEvent>> + Send + 'static,
+{
+    let store = context.store.clone();
+    match tokio::task::spawn_blocking(move || record(store.as_ref())).await {
+        Ok(Ok(events)) => subscriptions::publish_receipts(context, events),
+        Ok(Err(e)) => error!(error = %e, "failed to record receipts"),
+        Err(e) => error!(error = %e, "receipt task failed"),
+    }
+}
+
+async fn handle_fetch(
+    recipient: [u8; PUBLIC_KEY_LENGTH],
+    lease: Option<u64>,
+    recv: &mut quinn::RecvStream,
+    send: &mut quinn::SendStream,
+    context: Arc<RelayContext>,
+    quic: &quinn::Connection,
+) -> Result<()> {
+    Span::current().record("recipient", key_fingerprint(&recipient));
+
+    if !authenticate_key(&recipient, recv, send, quic, &context.metrics).await? {
+        return respond(send, &Frame::error(ErrorCode::Unauthorized, "Unauthorized")).await;
+    }
+
+    let now = unix_now();
+    let store = context.store.clone();
+    let messages =
+        tokio::task::spawn_blocking(move || store.fetch_for_recipient(&recipient, now, lease))
+            .await??;
+
+    debug!(messages = messages.len(), "fetch served");
+    let mut delivered = Vec::with_capacity(messages.len());
+    for msg in messages {
+        let envelope = msg.to_bytes()?;
+        respond(send, &Frame::Message { envelope }).await?;
+        delivered.push(msg.msg_id);
+    }
+    respond(send, &Frame::End).await?;
+    record_receipts(&context, move |store| {
+        record_all(store, &delivered, ReceiptStatus::Delivered)
+    })
+    .await;
+    Ok(())
+}
+
+async fn handle_ack(
+    recipient: [u8; PUBLIC_KEY_LENGTH],
+    msg_ids: Vec<MessageId>,
+    recv: &mut quinn::RecvStream,
+    send: &mut quinn::SendStream,
+    context: Arc<RelayContext>,
+    quic: &quinn::Connection,
+) -> Result<()> {
+    Span::current().record("recipient", key_fingerprint(&recipient));
+
+    if !authenticate_key(&recipient, recv, send, quic, &context.metrics).await? {
+        return respond(send, &Frame::error(ErrorCode::Unauthorized, "Unauthorized")).await;
+    }
+
+    let store = context.store.clone();
+    let deleted = tokio::task::spawn_blocking(move || store.ack(&recipient, &msg_ids)).await??;
+
+    debug!(deleted = deleted.len(), "messages acknowledged");
+    let count = deleted.len() as u32;
+    respond(send, &Frame::Acked { count }).await?;
+    record_receipts(&context, move |store| {
+        record_all(store, &deleted, ReceiptStatus::Acked)
+    })
+    .await;
+    Ok(())
+}
+
+/// Registers the connection for push delivery of `recipient`'s new messages
+/// once the client proves it holds the recipient key.
+async fn handle_subscribe(
+    recipient: [u8; PUBLIC_KEY_LENGTH],
+    recv: &mut quinn::RecvStream,
+    send: &mut quinn::SendStream,
+    context: Arc<RelayContext>,
+    quic: &quinn::Connection,
+) -> Result<()> {
+    Span::current().record("recipient", key_fingerprint(&recipient));
+
+    if !authenticate_key(&recipient, recv, send, quic, &context.metrics).await? {
+        return respond(send, &Frame::error(ErrorCode::Unauthorized, "Unauthorized")).await;
+    }
+
+    context.subscriptions.subscribe(recipient, quic.clone());
+    info!("subscribed");
+    respond(send, &Frame::Ok).await
+}
+
+/// Answers a sender's query for the receipts of one of its messages once
+/// the client proves it holds the sender key. A message from another
+/// sender looks unknown: the reply is empty either way.
+async fn handle_receipts(
+    sender_key: [u8; PUBLIC_KEY_LENGTH],
+    msg_id: MessageId,
+    recv: &mut quinn::RecvStream,
+    send: &mut quinn::SendStream,
+    context: Arc<RelayContext>,
+    quic: &quinn::Connection,
+) -> Result<()> {
+    Span::current().record("msg_id", msg_id.to_string());
+
+    if !authenticate_key(&sender_key, recv, send, quic, &context.metrics).await? {
+        return respond(send, &Frame::error(ErrorCode::Unauthorized, "Unauthorized")).await;
+    }
+
+    let store = context.store.clone();
+    let log = tokio::task::spawn_blocking(move || store.receipts(&msg_id)).await??;
+    let receipts = log
+        .filter(|log| log.sender_key == sender_key)
+        .map(|log| log.receipts)
+        .unwrap_or_default();
+
+    debug!(receipts = receipts.len(), "receipts served");
+    for receipt in receipts {
+        respond(
+            send,
+            &Frame::Receipt {
+                sender_key,
+                receipt,
+            },
+        )
+        .await?;
+    }
+    respond(send, &Frame::End).await
+}
+
+/// Registers the connection for push delivery of receipts for messages
+/// from `sender_key` once the client proves it holds that key.
+async fn handle_watch(
+    sender_key: [u8; PUBLIC_KEY_LENGTH],
+    recv: &mut quinn::RecvStream,
+    send: &mut quinn::SendStream,
+    context: Arc<RelayContext>,
+    quic: &quinn::Connection,
+) -> Result<()> {
+    if !authenticate_key(&sender_key, recv, send, quic, &context.metrics).await? {
+        return respond(send, &Frame::error(ErrorCode::Unauthorized, "Unauthorized")).await;
+    }
+
+    context.receipt_watchers.subscribe(sender_key, quic.clone());
+    info!(sender = %key_fingerprint(&sender_key), "watching receipts");
+    respond(send, &Frame::Ok).await
+}
+
+/// Reports storage figures and quota settings. Carries no per-recipient
+/// data, so it needs no authentication.
+async fn handle_stats(send: &mut quinn::SendStream, context: Arc<RelayContext>) -> Result<()> {
+    let store = context.store.clone();
+    let now = unix_now();
+    let figures = tokio::task::spawn_blocking(move || store.stats(now)).await??;
+    let stats = RelayStats {
+        expired: context.sweeper.metrics().expired,
+        ..context.quotas.report(figures)
+    };
+    respond(send, &Frame::StatsReply { stats }).await
+}
+
+/// Human-readable reason sent alongside a validation error code.
+fn rejection_message(error: &QightError, policy: &ValidationPolicy) -> String {
+    match error {
+        QightError::FieldTooLong => {
+            format!("sender longer than {} bytes", policy.max_sender_len)
+        }
+        QightError::PayloadTooLarge => {
+            format!("payload larger than {} bytes", policy.max_payload_len)
+        }
+        QightError::TtlOutOfRange => format!(
+            "ttl outside {}..={} seconds",
+            policy.min_ttl_secs, policy.max_ttl_secs
+        ),
+        QightError::InvalidTimestamp => format!(
+            "timestamp more than {} seconds in the future",
+            policy.max_clock_skew_secs
+        ),
+        QightError::InvalidSignature => "Invalid signature".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_flight_idle_waits_for_guards() {
+        let in_flight = Arc::new(InFlight::default());
+        in_flight.idle().await;
+
+        let first = in_flight.enter();
+        let second = in_flight.enter();
+        assert_eq!(in_flight.count(), 2);
+        drop(first);
+        let pending = tokio::time::timeout(Duration::from_millis(20), in_flight.idle()).await;
+        assert!(pending.is_err());
+
+        let waiting = in_flight.idle();
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap();
+    }
+}
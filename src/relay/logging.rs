@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::IsTerminal;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+/// How log lines are written to stderr.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines with span fields as a prefix.
+    #[default]
+    Text,
+    /// One JSON object per event, including the fields of every open span.
+    Json,
+}
+
+/// Filter used when neither the configuration nor `RUST_LOG` sets one.
+pub const DEFAULT_FILTER: &str = "info";
+
+/// Log output of the relay binary. The library itself only emits `tracing`
+/// events and never installs a subscriber.
+///
+/// Loadable as the `[logging]` section of [`crate::relay::RelayConfig`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `EnvFilter` directives such as `info` or `qight=debug,quinn=warn`.
+    /// When unset, the relay binary uses `RUST_LOG`, then [`DEFAULT_FILTER`].
+    pub filter: Option<String>,
+}
+
+/// Changes the filter of the subscriber installed by [`init`] while the
+/// process runs. Cheap to clone.
+#[derive(Clone)]
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogHandle {
+    /// Replaces the active filter with `directives`.
+    pub fn set_filter(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)
+            .with_context(|| format!("invalid log filter {:?}", directives))?;
+        self.filter
+            .reload(filter)
+            .context("log subscriber is gone")
+    }
+}
+
+/// Installs the global `tracing` subscriber, writing to stderr. An unset
+/// filter means [`DEFAULT_FILTER`].
+pub fn init(config: &LoggingConfig) -> Result<LogHandle> {
+    let directives = config.filter.as_deref().unwrap_or(DEFAULT_FILTER);
+    let filter = EnvFilter::try_new(directives)
+        .with_context(|| format!("invalid log filter {:?}", directives))?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let output = match config.format {
+        LogFormat::Text => fmt::layer()
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .try_init()
+        .context("a log subscriber is already installed")?;
+    Ok(LogHandle { filter: handle })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_swaps_filter() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = tracing_subscriber::registry().with(filter);
+        let logs = LogHandle { filter: handle };
+
+        logs.set_filter("warn,qight=debug").unwrap();
+        assert!(logs.set_filter("qight=loud").is_err());
+        logs.filter
+            .with_current(|filter| assert_eq!(filter.to_string(), "qight=debug,warn"))
+            .unwrap();
+    }
+}
@@ -0,0 +1,413 @@
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::metrics::{self, Counter, Gauge, Histogram, TextEncoder};
+use crate::protocol::Frame;
+use crate::relay::handlers::RelayContext;
+use crate::relay::store::StoreStats;
+
+/// Longest request head the metrics listener reads.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// How long a scraper may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Optional HTTP listener serving `GET /metrics` in the Prometheus text
+/// format.
+///
+/// Loadable as the `[metrics]` section of [`crate::relay::RelayConfig`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// Address the HTTP listener binds to. The endpoint is unauthenticated,
+    /// so keep it on loopback or a private network.
+    pub bind: SocketAddr,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: SocketAddr::from(([127, 0, 0, 1], 9464)),
+        }
+    }
+}
+
+/// Requests broken down by metrics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Command {
+    Hello,
+    Send,
+    Fetch,
+    Ack,
+    Subscribe,
+    Stats,
+    Receipts,
+    Watch,
+}
+
+impl Command {
+    const ALL: [Command; 8] = [
+        Command::Hello,
+        Command::Send,
+        Command::Fetch,
+        Command::Ack,
+        Command::Subscribe,
+        Command::Stats,
+        Command::Receipts,
+        Command::Watch,
+    ];
+
+    pub fn of(frame: &Frame) -> Option<Self> {
+        match frame {
+            Frame::Hello { .. } => Some(Command::Hello),
+            Frame::Send { .. } => Some(Command::Send),
+            Frame::Fetch { .. } => Some(Command::Fetch),
+            Frame::Ack { .. } => Some(Command::Ack),
+            Frame::Subscribe { .. } => Some(Command::Subscribe),
+            Frame::Stats => Some(Command::Stats),
+            Frame::Receipts { .. } => Some(Command::Receipts),
+            Frame::Watch { .. } => Some(Command::Watch),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Command::Hello => "hello",
+            Command::Send => "send",
+            Command::Fetch => "fetch",
+            Command::Ack => "ack",
+            Command::Subscribe => "subscribe",
+            Command::Stats => "stats",
+            Command::Receipts => "receipts",
+            Command::Watch => "watch",
+        }
+    }
+}
+
+/// Which signature check failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SignatureCheck {
+    /// The sender signature over a SEND envelope.
+    Envelope,
+    /// A client's answer to a FETCH, ACK, SUBSCRIBE, RECEIPTS or WATCH challenge.
+    Challenge,
+}
+
+/// What a rate limit refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RateLimitScope {
+    Connection,
+    Send,
+    Fetch,
+}
+
+/// Relay counters since start. Storage, quota and sweeper figures are read
+/// from their owners at scrape time.
+#[derive(Debug, Default)]
+pub(crate) struct RelayMetrics {
+    connections_active: Gauge,
+    connections: Counter,
+    handshake_failures: Counter,
+    requests: [Counter; Command::ALL.len()],
+    latency: [Histogram; Command::ALL.len()],
+    signature_failures: [Counter; 2],
+    rate_limited: [Counter; 3],
+}
+
+impl RelayMetrics {
+    pub fn connection_opened(&self) {
+        self.connections.inc();
+        self.connections_active.inc();
+    }
+
+    pub fn connection_closed(&self) {
+        self.connections_active.dec();
+    }
+
+    pub fn handshake_failed(&self) {
+        self.handshake_failures.inc();
+    }
+
+    /// Counts a handled request and how long it took.
+    pub fn request(&self, command: Command, elapsed: Duration) {
+        self.requests[command as usize].inc();
+        self.latency[command as usize].observe(elapsed);
+    }
+
+    pub fn signature_failed(&self, check: SignatureCheck) {
+        self.signature_failures[check as usize].inc();
+    }
+
+    pub fn rate_limited(&self, scope: RateLimitScope) {
+        self.rate_limited[scope as usize].inc();
+    }
+}
+
+/// Every relay and client metric in the Prometheus text format.
+fn render(context: &RelayContext, store: StoreStats) -> String {
+    let metrics = &context.metrics;
+    let mut encoder = TextEncoder::default();
+
+    encoder.single(
+        "qight_connections_active",
+        "gauge",
+        "Open client connections.",
+        metrics.connections_active.get(),
+    );
+    encoder.single(
+        "qight_connections_total",
+        "counter",
+        "Client connections accepted.",
+        metrics.connections.get(),
+    );
+    encoder.single(
+        "qight_handshake_failures_total",
+        "counter",
+        "Incoming connections that failed the QUIC or TLS handshake.",
+        metrics.handshake_failures.get(),
+    );
+
+    encoder.family(
+        "qight_requests_total",
+        "counter",
+        "Requests handled, by command.",
+    );
+    for command in Command::ALL {
+        let count = metrics.requests[command as usize].get();
+        encoder.sample(
+            "qight_requests_total",
+            &[("command", command.as_str())],
+            count,
+        );
+    }
+    encoder.family(
+        "qight_request_duration_seconds",
+        "histogram",
+        "Time to handle a request, by command.",
+    );
+    for command in Command::ALL {
+        let latency = &metrics.latency[command as usize];
+        encoder.histogram(
+            "qight_request_duration_seconds",
+            &[("command", command.as_str())],
+            latency,
+        );
+    }
+
+    encoder.family(
+        "qight_signature_failures_total",
+        "counter",
+        "Rejected envelope signatures and failed recipient challenges.",
+    );
+    for (check, label) in [
+        (SignatureCheck::Envelope, "envelope"),
+        (SignatureCheck::Challenge, "challenge"),
+    ] {
+        let count = metrics.signature_failures[check as usize].get();
+        encoder.sample("qight_signature_failures_total", &[("check", label)], count);
+    }
+    encoder.family(
+        "qight_rate_limited_total",
+        "counter",
+        "Connections and requests refused by a rate limit.",
+    );
+    for (scope, label) in [
+        (RateLimitScope::Connection, "connection"),
+        (RateLimitScope::Send, "send"),
+        (RateLimitScope::Fetch, "fetch"),
+    ] {
+        let count = metrics.rate_limited[scope as usize].get();
+        encoder.sample("qight_rate_limited_total", &[("scope", label)], count);
+    }
+
+    let stats = context.quotas.report(store);
+    encoder.single(
+        "qight_stored_messages",
+        "gauge",
+        "Stored messages, including leased ones.",
+        stats.messages,
+    );
+    encoder.single(
+        "qight_stored_bytes",
+        "gauge",
+        "Payload bytes stored.",
+        stats.payload_bytes,
+    );
+    encoder.single(
+        "qight_leased_messages",
+        "gauge",
+        "Messages hidden by an unexpired lease.",
+        stats.leased,
+    );
+    encoder.single(
+        "qight_recipients",
+        "gauge",
+        "Recipients with at least one stored message.",
+        stats.recipients,
+    );
+    encoder.single(
+        "qight_quota_rejections_total",
+        "counter",
+        "SENDs refused for exceeding a quota.",
+        stats.quota_rejections,
+    );
+    encoder.single(
+        "qight_quota_evictions_total",
+        "counter",
+        "Messages evicted from full inboxes.",
+        stats.quota_evictions,
+    );
+
+    let sweeps = context.sweeper.metrics();
+    encoder.single(
+        "qight_expired_messages_total",
+        "counter",
+        "Expired messages purged by the sweeper.",
+        sweeps.expired,
+    );
+    encoder.single(
+        "qight_sweeps_total",
+        "counter",
+        "Completed expiry sweeps.",
+        sweeps.sweeps,
+    );
+    encoder.single(
+        "qight_sweep_failures_total",
+        "counter",
+        "Expiry sweeps that hit a storage error.",
+        sweeps.failures,
+    );
+    encoder.single(
+        "qight_last_sweep_duration_seconds",
+        "gauge",
+        "Duration of the latest expiry sweep.",
+        sweeps.last_duration.as_secs_f64(),
+    );
+
+    metrics::encode_client(&mut encoder);
+    encoder.finish()
+}
+
+/// Answers scrapes on `listener` until `shutdown` flips.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    context: Arc<RelayContext>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!(error = %e, "metrics listener failed to accept");
+                    continue;
+                }
+            },
+            _ = shutdown.wait_for(|stop| *stop) => return,
+        };
+        let context = context.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, context).await {
+                warn!(error = %e, "metrics request failed");
+            }
+        });
+    }
+}
+
+/// Serves one HTTP/1.x request and closes the connection.
+async fn answer(mut stream: TcpStream, context: Arc<RelayContext>) -> std::io::Result<()> {
+    let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await {
+        Ok(head) => head?,
+        Err(_) => return Ok(()),
+    };
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let path = target.split('?').next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => {
+            let store = context.store.clone();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_secs();
+            match tokio::task::spawn_blocking(move || store.stats(now)).await {
+                Ok(Ok(figures)) => ("200 OK", render(&context, figures)),
+                Ok(Err(e)) => (
+                    "500 Internal Server Error",
+                    format!("cannot read storage: {}\n", e),
+                ),
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            }
+        }
+        (_, "/metrics") => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_string(),
+        ),
+        _ => (
+            "404 Not Found",
+            "metrics are served at /metrics\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        TextEncoder::CONTENT_TYPE,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads up to the blank line ending the request head.
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD {
+            break;
+        }
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_index_their_counters() {
+        for (i, command) in Command::ALL.into_iter().enumerate() {
+            assert_eq!(command as usize, i);
+        }
+        let metrics = RelayMetrics::default();
+        metrics.request(Command::Fetch, Duration::from_millis(3));
+        metrics.rate_limited(RateLimitScope::Fetch);
+        assert_eq!(metrics.requests[Command::Fetch as usize].get(), 1);
+        assert_eq!(
+            metrics.rate_limited[RateLimitScope::Fetch as usize].get(),
+            1
+        );
+        assert_eq!(metrics.requests[Command::Send as usize].get(), 0);
+    }
+}
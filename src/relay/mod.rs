@@ -0,0 +1,20 @@
+pub mod config;
+mod handlers;
+pub mod logging;
+pub mod metrics;
+pub mod quota;
+pub mod rate_limit;
+pub mod server;
+pub mod store;
+mod subscriptions;
+pub mod sweeper;
+pub mod validation;
+pub use config::{ConfigError, RelayConfig};
+pub use logging::{LogFormat, LoggingConfig};
+pub use metrics::MetricsConfig;
+pub use quota::{OverflowPolicy, QuotaPolicy};
+pub use rate_limit::{RateLimit, RateLimitPolicy};
+pub use server::*;
+pub use store::{MemoryStore, MessageStore, SqliteStore, StoreStats, Usage};
+pub use sweeper::SweeperConfig;
+pub use validation::{DuplicatePolicy, ValidationPolicy};
@@ -0,0 +1,470 @@
+use anyhow::Result;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::protocol::RelayStats;
+use crate::relay::store::{MessageStore, ReceiptEvent, StoreStats, Usage};
+use crate::MessageEnvelope;
+
+/// What the relay does with a SEND that would overfill the recipient's inbox.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Answer `QUOTA_EXCEEDED`; the sender retries later.
+    #[default]
+    Reject,
+    /// Delete the inbox's oldest messages until the new one fits.
+    EvictOldest,
+}
+
+/// Storage limits a relay enforces on SEND. `None` means unlimited.
+///
+/// Loadable as the `[quotas]` section of [`crate::relay::RelayConfig`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaPolicy {
+    /// Messages stored per recipient.
+    pub inbox_max_messages: Option<u64>,
+    /// Payload bytes stored per recipient.
+    pub inbox_max_bytes: Option<u64>,
+    /// Messages stored per sender key.
+    pub sender_max_messages: Option<u64>,
+    /// Payload bytes stored per sender key.
+    pub sender_max_bytes: Option<u64>,
+    /// Payload bytes stored across all recipients.
+    pub max_total_bytes: Option<u64>,
+    /// Applies to the inbox limits only; sender and global limits always
+    /// reject.
+    pub on_full: OverflowPolicy,
+}
+
+/// The limit a rejected SEND would have exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuotaLimit {
+    Total(u64),
+    SenderMessages(u64),
+    SenderBytes(u64),
+    InboxMessages(u64),
+    InboxBytes(u64),
+}
+
+impl fmt::Display for QuotaLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaLimit::Total(max) => write!(f, "relay storage full ({} bytes)", max),
+            QuotaLimit::SenderMessages(max) => write!(f, "sender has {} messages stored", max),
+            QuotaLimit::SenderBytes(max) => write!(f, "sender has {} bytes stored", max),
+            QuotaLimit::InboxMessages(max) => write!(f, "recipient inbox holds {} messages", max),
+            QuotaLimit::InboxBytes(max) => write!(f, "recipient inbox holds {} bytes", max),
+        }
+    }
+}
+
+/// Outcome of [`Quotas::store`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Stored,
+    /// The same envelope, or the same content under a content-addressed
+    /// `msg_id`, was already accepted; nothing changed.
+    Duplicate,
+    /// A different envelope was already accepted under this `msg_id`.
+    Conflict,
+    Rejected(QuotaLimit),
+}
+
+/// Usage of SENDs admitted but not yet stored, counted on top of the
+/// store's so concurrent SENDs cannot overshoot a limit.
+#[derive(Default)]
+struct Reserved {
+    payload_bytes: u64,
+    senders: HashMap<[u8; PUBLIC_KEY_LENGTH], Usage>,
+    recipients: HashMap<[u8; PUBLIC_KEY_LENGTH], Usage>,
+}
+
+impl Reserved {
+    fn add(&mut self, envelope: &MessageEnvelope) {
+        let size = envelope.payload.len() as u64;
+        self.payload_bytes += size;
+        for usage in [
+            self.senders.entry(envelope.sender_key).or_default(),
+            self.recipients.entry(envelope.recipient).or_default(),
+        ] {
+            usage.messages += 1;
+            usage.payload_bytes += size;
+        }
+    }
+
+    fn remove(&mut self, envelope: &MessageEnvelope) {
+        let size = envelope.payload.len() as u64;
+        self.payload_bytes -= size;
+        for (map, key) in [
+            (&mut self.senders, envelope.sender_key),
+            (&mut self.recipients, envelope.recipient),
+        ] {
+            let usage = map.get_mut(&key).expect("reserved usage");
+            usage.messages -= 1;
+            usage.payload_bytes -= size;
+            if usage.messages == 0 {
+                map.remove(&key);
+            }
+        }
+    }
+
+    /// `usage` plus what is reserved for `key` in `map`.
+    fn on_top(
+        map: &HashMap<[u8; PUBLIC_KEY_LENGTH], Usage>,
+        key: &[u8; PUBLIC_KEY_LENGTH],
+        usage: Usage,
+    ) -> Usage {
+        let reserved = map.get(key).copied().unwrap_or_default();
+        Usage {
+            messages: usage.messages + reserved.messages,
+            payload_bytes: usage.payload_bytes + reserved.payload_bytes,
+        }
+    }
+}
+
+/// Releases a reservation once its SEND was stored or failed.
+struct Reservation<'a> {
+    reserved: &'a Mutex<Reserved>,
+    envelope: &'a MessageEnvelope,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.reserved.lock().unwrap().remove(self.envelope);
+    }
+}
+
+/// Enforces a [`QuotaPolicy`] and counts its verdicts.
+pub(crate) struct Quotas {
+    policy: QuotaPolicy,
+    /// Messages purged per transaction before a SEND is refused.
+    purge_batch: usize,
+    /// Held while a SEND is checked against the limits, not while it is
+    /// stored; admitted SENDs stay reserved until then.
+    admission: Mutex<Reserved>,
+    rejections: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Quotas {
+    pub fn new(policy: QuotaPolicy, purge_batch: usize) -> Self {
+        Self {
+            policy,
+            purge_batch: purge_batch.max(1),
+            admission: Mutex::new(Reserved::default()),
+            rejections: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Stores `envelope` if it fits, evicting from the recipient's inbox
+    /// when the policy allows, and starts its receipt log at `now`.
+    /// Before a SEND is refused, messages expired before `now` are purged
+    /// batch by batch until it fits; their expired receipts are returned
+    /// alongside the verdict.
+    pub fn store(
+        &self,
+        store: &dyn MessageStore,
+        envelope: &MessageEnvelope,
+        now: u64,
+    ) -> Result<(Admission, Vec<ReceiptEvent>)> {
+        match store.accepted(&envelope.msg_id)? {
+            Some(digest) if digest == envelope.digest() => {
+                return Ok((Admission::Duplicate, Vec::new()))
+            }
+            // A content id already names this sender, recipient and payload;
+            // only the timestamp or TTL of a re-created envelope can differ.
+            Some(_) if envelope.has_content_id() => return Ok((Admission::Duplicate, Vec::new())),
+            Some(_) => return Ok((Admission::Conflict, Vec::new())),
+            None => {}
+        }
+
+        let size = envelope.payload.len() as u64;
+        let mut expired = Vec::new();
+        {
+            let mut reserved = self.admission.lock().unwrap();
+            let mut verdict = self.check(store, envelope, size, &reserved)?;
+            while verdict.is_some() {
+                let batch = store.expire_batch(now, self.purge_batch)?;
+                if batch.expired.is_empty() {
+                    break;
+                }
+                expired.extend(batch.expired);
+                verdict = self.check(store, envelope, size, &reserved)?;
+            }
+            if let Some(limit) = verdict {
+                self.rejections.fetch_add(1, Ordering::Relaxed);
+                return Ok((Admission::Rejected(limit), expired));
+            }
+            reserved.add(envelope);
+        }
+
+        let _reservation = Reservation {
+            reserved: &self.admission,
+            envelope,
+        };
+        let admission = match store.insert_with_receipts(envelope, now)? {
+            true => Admission::Stored,
+            false => Admission::Duplicate,
+        };
+        Ok((admission, expired))
+    }
+
+    /// First limit `envelope` would exceed on top of the stored and
+    /// `reserved` usage, making room in the inbox first under
+    /// [`OverflowPolicy::EvictOldest`].
+    fn check(
+        &self,
+        store: &dyn MessageStore,
+        envelope: &MessageEnvelope,
+        size: u64,
+        reserved: &Reserved,
+    ) -> Result<Option<QuotaLimit>> {
+        let policy = &self.policy;
+        if let Some(max) = policy.max_total_bytes {
+            if store.payload_bytes()? + reserved.payload_bytes + size > max {
+                return Ok(Some(QuotaLimit::Total(max)));
+            }
+        }
+
+        if policy.sender_max_messages.is_some() || policy.sender_max_bytes.is_some() {
+            let usage = store.sender_usage(&envelope.sender_key)?;
+            let usage = Reserved::on_top(&reserved.senders, &envelope.sender_key, usage);
+            if let Some(limit) = exceeded(
+                usage,
+                size,
+                (policy.sender_max_messages, QuotaLimit::SenderMessages),
+                (policy.sender_max_bytes, QuotaLimit::SenderBytes),
+            ) {
+                return Ok(Some(limit));
+            }
+        }
+
+        if policy.inbox_max_messages.is_none() && policy.inbox_max_bytes.is_none() {
+            return Ok(None);
+        }
+        if let Some(max) = policy.inbox_max_bytes.filter(|&max| size > max) {
+            // No amount of eviction makes room.
+            return Ok(Some(QuotaLimit::InboxBytes(max)));
+        }
+        loop {
+            let usage = store.recipient_usage(&envelope.recipient)?;
+            let usage = Reserved::on_top(&reserved.recipients, &envelope.recipient, usage);
+            let Some(limit) = exceeded(
+                usage,
+                size,
+                (policy.inbox_max_messages, QuotaLimit::InboxMessages),
+                (policy.inbox_max_bytes, QuotaLimit::InboxBytes),
+            ) else {
+                return Ok(None);
+            };
+            if policy.on_full != OverflowPolicy::EvictOldest
+                || !store.evict_oldest(&envelope.recipient)?
+            {
+                return Ok(Some(limit));
+            }
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// STATS reply combining `store` figures with the quota settings and
+    /// counters. Sweeper figures are left for the caller.
+    pub fn report(&self, store: StoreStats) -> RelayStats {
+        RelayStats {
+            messages: store.messages,
+            leased: store.leased,
+            recipients: store.recipients,
+            payload_bytes: store.payload_bytes,
+            quota_rejections: self.rejections.load(Ordering::Relaxed),
+            quota_evictions: self.evictions.load(Ordering::Relaxed),
+            expired: 0,
+            max_total_bytes: self.policy.max_total_bytes,
+            inbox_max_messages: self.policy.inbox_max_messages,
+            inbox_max_bytes: self.policy.inbox_max_bytes,
+            sender_max_messages: self.policy.sender_max_messages,
+            sender_max_bytes: self.policy.sender_max_bytes,
+            evict_oldest: self.policy.on_full == OverflowPolicy::EvictOldest,
+        }
+    }
+}
+
+/// The messages or bytes limit that storing `size` more bytes on top of
+/// `usage` would exceed.
+fn exceeded(
+    usage: Usage,
+    size: u64,
+    (max_messages, messages_limit): (Option<u64>, fn(u64) -> QuotaLimit),
+    (max_bytes, bytes_limit): (Option<u64>, fn(u64) -> QuotaLimit),
+) -> Option<QuotaLimit> {
+    if let Some(max) = max_messages.filter(|&max| usage.messages + 1 > max) {
+        return Some(messages_limit(max));
+    }
+    max_bytes
+        .filter(|&max| usage.payload_bytes + size > max)
+        .map(bytes_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ReceiptStatus;
+    use crate::relay::store::MemoryStore;
+
+    fn envelope(recipient: u8, sender: u8, payload: &[u8]) -> MessageEnvelope {
+        MessageEnvelope::new(
+            "s".to_string(),
+            [recipient; 32],
+            [sender; 32],
+            payload.to_vec(),
+            3600,
+        )
+    }
+
+    #[test]
+    fn test_inbox_limits_reject_or_evict() {
+        let store = MemoryStore::new();
+        let quotas = Quotas::new(
+            QuotaPolicy {
+                inbox_max_messages: Some(2),
+                ..QuotaPolicy::default()
+            },
+            100,
+        );
+        let first = envelope(7, 1, b"a");
+        let now = first.timestamp;
+        assert_eq!(quotas.store(&store, &first, now).unwrap().0, Admission::Stored);
+        assert_eq!(quotas.store(&store, &first, now).unwrap().0, Admission::Duplicate);
+        let mut reused = envelope(7, 1, b"z");
+        reused.msg_id = first.msg_id;
+        assert_eq!(quotas.store(&store, &reused, now).unwrap().0, Admission::Conflict);
+
+        let content =
+            MessageEnvelope::new_content_addressed("s".into(), [9; 32], [1; 32], b"c".to_vec(), 60);
+        let mut recreated = content.clone();
+        recreated.ttl = 120;
+        assert_eq!(quotas.store(&store, &content, now).unwrap().0, Admission::Stored);
+        assert_eq!(quotas.store(&store, &recreated, now).unwrap().0, Admission::Duplicate);
+        let mut forged = recreated.clone();
+        forged.payload = b"d".to_vec();
+        assert_eq!(quotas.store(&store, &forged, now).unwrap().0, Admission::Conflict);
+        assert_eq!(quotas.store(&store, &envelope(7, 1, b"b"), now).unwrap().0, Admission::Stored);
+        assert_eq!(
+            quotas.store(&store, &envelope(7, 1, b"c"), now).unwrap().0,
+            Admission::Rejected(QuotaLimit::InboxMessages(2))
+        );
+        assert_eq!(quotas.store(&store, &envelope(8, 1, b"c"), now).unwrap().0, Admission::Stored);
+
+        let evicting = Quotas::new(
+            QuotaPolicy {
+                inbox_max_messages: Some(2),
+                inbox_max_bytes: Some(4),
+                on_full: OverflowPolicy::EvictOldest,
+                ..QuotaPolicy::default()
+            },
+            100,
+        );
+        assert_eq!(evicting.store(&store, &envelope(7, 1, b"cd"), now).unwrap().0, Admission::Stored);
+        assert!(!store.contains(&first.msg_id).unwrap());
+        assert_eq!(
+            evicting.store(&store, &envelope(7, 1, b"large"), now).unwrap().0,
+            Admission::Rejected(QuotaLimit::InboxBytes(4))
+        );
+        let report = evicting.report(store.stats(now).unwrap());
+        assert_eq!((report.quota_evictions, report.quota_rejections), (1, 1));
+        assert!(report.evict_oldest);
+    }
+
+    #[test]
+    fn test_sender_and_total_limits_reject() {
+        let store = MemoryStore::new();
+        let quotas = Quotas::new(
+            QuotaPolicy {
+                sender_max_bytes: Some(4),
+                max_total_bytes: Some(6),
+                on_full: OverflowPolicy::EvictOldest,
+                ..QuotaPolicy::default()
+            },
+            100,
+        );
+        let now = envelope(7, 1, b"").timestamp;
+        assert_eq!(quotas.store(&store, &envelope(7, 1, b"abc"), now).unwrap().0, Admission::Stored);
+        assert_eq!(
+            quotas.store(&store, &envelope(8, 1, b"de"), now).unwrap().0,
+            Admission::Rejected(QuotaLimit::SenderBytes(4))
+        );
+        assert_eq!(quotas.store(&store, &envelope(8, 2, b"de"), now).unwrap().0, Admission::Stored);
+        assert_eq!(
+            quotas.store(&store, &envelope(9, 3, b"fg"), now).unwrap().0,
+            Admission::Rejected(QuotaLimit::Total(6))
+        );
+        assert_eq!(store.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_expired_messages_free_quota() {
+        let store = MemoryStore::new();
+        let quotas = Quotas::new(
+            QuotaPolicy {
+                inbox_max_messages: Some(2),
+                ..QuotaPolicy::default()
+            },
+            1,
+        );
+        let short: Vec<_> = (0..2)
+            .map(|_| MessageEnvelope::new("s".into(), [7; 32], [1; 32], b"a".to_vec(), 10))
+            .collect();
+        let now = short[0].timestamp;
+        for msg in &short {
+            assert_eq!(quotas.store(&store, msg, now).unwrap().0, Admission::Stored);
+        }
+
+        // One batch of one frees enough room; the other expired message
+        // is left for the sweeper.
+        let (admission, expired) = quotas.store(&store, &envelope(7, 1, b"b"), now + 11).unwrap();
+        assert_eq!(admission, Admission::Stored);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].receipt.msg_id, short[0].msg_id);
+        assert_eq!(expired[0].receipt.status, ReceiptStatus::Expired);
+        assert_eq!(store.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_reserved_sends_count_against_limits() {
+        let store = MemoryStore::new();
+        let quotas = Quotas::new(
+            QuotaPolicy {
+                inbox_max_messages: Some(1),
+                max_total_bytes: Some(5),
+                ..QuotaPolicy::default()
+            },
+            100,
+        );
+        // Admitted by another SEND but not yet stored.
+        let pending = envelope(7, 1, b"abc");
+        quotas.admission.lock().unwrap().add(&pending);
+        let now = pending.timestamp;
+        assert_eq!(
+            quotas.store(&store, &envelope(7, 2, b"x"), now).unwrap().0,
+            Admission::Rejected(QuotaLimit::InboxMessages(1))
+        );
+        assert_eq!(
+            quotas.store(&store, &envelope(8, 2, b"xyz"), now).unwrap().0,
+            Admission::Rejected(QuotaLimit::Total(5))
+        );
+
+        drop(Reservation {
+            reserved: &quotas.admission,
+            envelope: &pending,
+        });
+        assert_eq!(quotas.store(&store, &envelope(7, 2, b"x"), now).unwrap().0, Admission::Stored);
+        let reserved = quotas.admission.lock().unwrap();
+        assert_eq!(reserved.payload_bytes, 0);
+        assert!(reserved.senders.is_empty() && reserved.recipients.is_empty());
+    }
+}
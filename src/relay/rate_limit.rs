@@ -0,0 +1,382 @@
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::relay::config::ConfigError;
+
+/// Idle buckets are dropped once a table holds this many keys, and again
+/// each time it has doubled since the last pruning.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Most keys a table holds. Pruning that leaves more than three quarters of
+/// this evicts arbitrary buckets, forgiving whatever they had used.
+const MAX_BUCKETS: usize = 100_000;
+
+/// Connections over `connections_per_ip` that still complete a handshake,
+/// per IP, so the client learns when to come back. The rest are refused
+/// before any handshake work.
+const RATE_LIMITED_HANDSHAKES: RateLimit = RateLimit {
+    per_sec: 0.2,
+    burst: 2,
+};
+
+/// A token bucket: `burst` requests at once, refilled at `per_sec`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Sustained rate, in requests per second.
+    pub per_sec: f64,
+    /// Requests allowed back to back after a quiet period.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_sec: f64, burst: u32) -> Self {
+        Self { per_sec, burst }
+    }
+}
+
+/// Request rates a relay accepts. `None` means unlimited.
+///
+/// Loadable as the `[rate_limits]` section of [`crate::relay::RelayConfig`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitPolicy {
+    /// New connections per source IP.
+    pub connections_per_ip: Option<RateLimit>,
+    /// SENDs per source IP.
+    pub sends_per_ip: Option<RateLimit>,
+    /// SENDs per connection.
+    pub sends_per_connection: Option<RateLimit>,
+    /// SENDs per sender key, counted only for correctly signed envelopes.
+    pub sends_per_sender: Option<RateLimit>,
+    /// FETCHes per source IP.
+    pub fetches_per_ip: Option<RateLimit>,
+    /// FETCHes per connection.
+    pub fetches_per_connection: Option<RateLimit>,
+}
+
+impl RateLimitPolicy {
+    /// Rejects limits a bucket cannot enforce: a `per_sec` that is not a
+    /// positive number, or a `burst` of zero.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let limits = [
+            ("rate_limits.connections_per_ip", self.connections_per_ip),
+            ("rate_limits.sends_per_ip", self.sends_per_ip),
+            ("rate_limits.sends_per_connection", self.sends_per_connection),
+            ("rate_limits.sends_per_sender", self.sends_per_sender),
+            ("rate_limits.fetches_per_ip", self.fetches_per_ip),
+            ("rate_limits.fetches_per_connection", self.fetches_per_connection),
+        ];
+        for (field, limit) in limits {
+            let Some(limit) = limit else { continue };
+            let reason = if !(limit.per_sec.is_finite() && limit.per_sec > 0.0) {
+                "per_sec must be a positive number"
+            } else if limit.burst == 0 {
+                "burst must be at least 1"
+            } else {
+                continue;
+            };
+            return Err(ConfigError::Invalid {
+                field,
+                reason: reason.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_sec).min(limit.burst as f64);
+        self.updated = now;
+    }
+
+    /// Takes one token, or reports how long until one is available.
+    fn take(&mut self, limit: RateLimit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.per_sec))
+    }
+}
+
+/// Buckets for one limit, keyed by IP or sender key.
+struct Buckets<K> {
+    limit: Option<RateLimit>,
+    table: Mutex<Table<K>>,
+}
+
+struct Table<K> {
+    buckets: HashMap<K, Bucket>,
+    /// Size at which the next new key prunes the table first.
+    prune_at: usize,
+}
+
+impl<K: Eq + Hash> Table<K> {
+    /// Drops refilled buckets, then evicts down to three quarters of
+    /// [`MAX_BUCKETS`] if needed. Waiting for the table to double before the
+    /// next pass keeps the cost amortized over the inserts.
+    fn prune(&mut self, limit: RateLimit, now: Instant) {
+        // A bucket that has refilled is indistinguishable from a new one.
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(limit, now);
+            bucket.tokens < limit.burst as f64
+        });
+        let mut excess = self.buckets.len().saturating_sub(MAX_BUCKETS / 4 * 3);
+        if excess > 0 {
+            self.buckets.retain(|_, _| {
+                if excess == 0 {
+                    return true;
+                }
+                excess -= 1;
+                false
+            });
+        }
+        self.prune_at = (self.buckets.len() * 2).clamp(PRUNE_THRESHOLD, MAX_BUCKETS);
+    }
+}
+
+impl<K: Eq + Hash> Buckets<K> {
+    fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            table: Mutex::new(Table {
+                buckets: HashMap::new(),
+                prune_at: PRUNE_THRESHOLD,
+            }),
+        }
+    }
+
+    fn take(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        let mut table = self.table.lock().unwrap();
+        if table.buckets.len() >= table.prune_at && !table.buckets.contains_key(&key) {
+            table.prune(limit, now);
+        }
+        table
+            .buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now)
+    }
+}
+
+/// A single bucket owned by one connection.
+struct ConnectionBucket {
+    limit: Option<RateLimit>,
+    bucket: Mutex<Option<Bucket>>,
+}
+
+impl ConnectionBucket {
+    fn take(&self, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        self.bucket
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now)
+    }
+}
+
+/// Per-connection buckets, created by [`RateLimiter::connection`].
+pub(crate) struct ConnectionLimits {
+    ip: IpAddr,
+    sends: ConnectionBucket,
+    fetches: ConnectionBucket,
+}
+
+/// Enforces a [`RateLimitPolicy`] across all connections.
+pub(crate) struct RateLimiter {
+    policy: RateLimitPolicy,
+    connections: Buckets<IpAddr>,
+    rate_limited_handshakes: Buckets<IpAddr>,
+    sends_by_ip: Buckets<IpAddr>,
+    sends_by_sender: Buckets<[u8; PUBLIC_KEY_LENGTH]>,
+    fetches_by_ip: Buckets<IpAddr>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            connections: Buckets::new(policy.connections_per_ip),
+            rate_limited_handshakes: Buckets::new(
+                policy.connections_per_ip.map(|_| RATE_LIMITED_HANDSHAKES),
+            ),
+            sends_by_ip: Buckets::new(policy.sends_per_ip),
+            sends_by_sender: Buckets::new(policy.sends_per_sender),
+            fetches_by_ip: Buckets::new(policy.fetches_per_ip),
+            policy,
+        }
+    }
+
+    /// Admits a new connection from `ip`.
+    pub fn accept(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.connections.take(ip, Instant::now())
+    }
+
+    /// Whether a connection [`RateLimiter::accept`] refused may still
+    /// complete its handshake to be told the retry delay.
+    pub fn explain_refusal(&self, ip: IpAddr) -> bool {
+        self.rate_limited_handshakes.take(ip, Instant::now()).is_ok()
+    }
+
+    /// Fresh buckets for a connection from `ip`.
+    pub fn connection(&self, ip: IpAddr) -> ConnectionLimits {
+        ConnectionLimits {
+            ip,
+            sends: ConnectionBucket {
+                limit: self.policy.sends_per_connection,
+                bucket: Mutex::new(None),
+            },
+            fetches: ConnectionBucket {
+                limit: self.policy.fetches_per_connection,
+                bucket: Mutex::new(None),
+            },
+        }
+    }
+
+    pub fn send(&self, connection: &ConnectionLimits) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.sends_by_ip.take(connection.ip, now)?;
+        connection.sends.take(now)
+    }
+
+    /// Charges a SEND to its sender. Call only once the envelope signature
+    /// verified, or anyone could drain another sender's bucket.
+    pub fn sender(&self, sender_key: [u8; PUBLIC_KEY_LENGTH]) -> Result<(), Duration> {
+        self.sends_by_sender.take(sender_key, Instant::now())
+    }
+
+    pub fn fetch(&self, connection: &ConnectionLimits) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.fetches_by_ip.take(connection.ip, now)?;
+        connection.fetches.take(now)
+    }
+}
+
+/// `delay` as whole milliseconds, rounded up so clients never retry early.
+pub(crate) fn retry_after_ms(delay: Duration) -> u32 {
+    let millis = delay.as_nanos().div_ceil(1_000_000);
+    millis.clamp(1, u32::MAX as u128) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_refills() {
+        let limit = RateLimit::new(2.0, 3);
+        let start = Instant::now();
+        let mut bucket = Bucket::full(limit, start);
+        for _ in 0..3 {
+            assert!(bucket.take(limit, start).is_ok());
+        }
+        assert_eq!(bucket.take(limit, start), Err(Duration::from_millis(500)));
+        assert!(bucket.take(limit, start + Duration::from_millis(500)).is_ok());
+        assert!(bucket.take(limit, start + Duration::from_millis(600)).is_err());
+
+        // Refill stops at the burst size.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.take(limit, later).is_ok());
+        }
+        assert!(bucket.take(limit, later).is_err());
+    }
+
+    #[test]
+    fn test_limiter_scopes() {
+        let limiter = RateLimiter::new(RateLimitPolicy {
+            sends_per_ip: Some(RateLimit::new(0.001, 3)),
+            sends_per_connection: Some(RateLimit::new(0.001, 2)),
+            sends_per_sender: Some(RateLimit::new(0.001, 1)),
+            ..RateLimitPolicy::default()
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let (first, second) = (limiter.connection(ip), limiter.connection(ip));
+
+        assert!(limiter.send(&first).is_ok());
+        assert!(limiter.send(&first).is_ok());
+        assert!(limiter.send(&first).is_err());
+        assert!(limiter.send(&second).is_err());
+        assert!(limiter.send(&limiter.connection("10.0.0.2".parse().unwrap())).is_ok());
+
+        assert!(limiter.sender([1; 32]).is_ok());
+        assert!(limiter.sender([1; 32]).is_err());
+        assert!(limiter.sender([2; 32]).is_ok());
+
+        assert!(limiter.fetch(&first).is_ok());
+        assert!(limiter.accept(ip).is_ok());
+    }
+
+    #[test]
+    fn test_refused_handshakes_are_capped_per_ip() {
+        let limiter = RateLimiter::new(RateLimitPolicy {
+            connections_per_ip: Some(RateLimit::new(0.001, 1)),
+            ..RateLimitPolicy::default()
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..RATE_LIMITED_HANDSHAKES.burst {
+            assert!(limiter.explain_refusal(ip));
+        }
+        assert!(!limiter.explain_refusal(ip));
+        assert!(limiter.explain_refusal("10.0.0.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_buckets_are_pruned_and_capped() {
+        let buckets = Buckets::new(Some(RateLimit::new(0.001, 1)));
+        let now = Instant::now();
+        for key in 0..PRUNE_THRESHOLD as u32 {
+            assert!(buckets.take(key, now).is_ok());
+        }
+        // Refilled buckets go; pruning then waits for the table to double.
+        let later = now + Duration::from_secs(3600);
+        assert!(buckets.take(u32::MAX, later).is_ok());
+        {
+            let table = buckets.table.lock().unwrap();
+            assert_eq!(table.buckets.len(), 1);
+            assert_eq!(table.prune_at, PRUNE_THRESHOLD);
+        }
+
+        // Drained buckets stay until the table is full, then some are evicted.
+        for key in 0..MAX_BUCKETS as u32 {
+            assert!(buckets.take(key, later).is_ok());
+        }
+        let table = buckets.table.lock().unwrap();
+        assert!(table.buckets.len() < MAX_BUCKETS);
+        assert!(table.prune_at > table.buckets.len());
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        assert_eq!(retry_after_ms(Duration::from_micros(1)), 1);
+        assert_eq!(retry_after_ms(Duration::from_micros(1500)), 2);
+        assert_eq!(retry_after_ms(Duration::ZERO), 1);
+    }
+}
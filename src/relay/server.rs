@@ -0,0 +1,786 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use quinn::{Endpoint, ServerConfig};
+use quinn_proto::crypto::rustls::QuicServerConfig;
+use rcgen::generate_simple_self_signed;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig as RustlsServerConfig;
+use std::fs::{read, write, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn, Instrument};
+
+use crate::protocol::{CloseCode, Features};
+use crate::relay::config::{RelayConfig, StorageBackend};
+use crate::relay::handlers::{handle_connection, InFlight, RelayContext};
+use crate::relay::metrics::{self, MetricsConfig, RateLimitScope, RelayMetrics};
+use crate::relay::quota::{QuotaPolicy, Quotas};
+use crate::relay::rate_limit::{retry_after_ms, RateLimitPolicy, RateLimiter};
+use crate::relay::store::{MemoryStore, MessageStore, SqliteStore};
+use crate::relay::subscriptions::Subscriptions;
+use crate::relay::sweeper::{self, Sweeper, SweeperConfig};
+use crate::relay::validation::ValidationPolicy;
+use crate::{gen_keypair, public_key_from_secret};
+
+/// mDNS service type relays advertise under.
+pub const SERVICE_TYPE: &str = "_qight._udp.local.";
+
+/// Where the relay's TLS identity comes from.
+#[derive(Clone, Debug)]
+pub enum CertificateSource {
+    /// DER files on disk, generated as a self-signed pair if the certificate is missing.
+    Files { cert: PathBuf, key: PathBuf },
+    /// An in-memory DER certificate and PKCS#8 key.
+    Der { cert: Vec<u8>, key: Vec<u8> },
+    /// A fresh self-signed certificate for `localhost` that is never persisted.
+    SelfSigned,
+}
+
+/// Where the relay's Ed25519 identity key, advertised in WELCOME, comes from.
+#[derive(Clone, Debug)]
+pub enum IdentitySource {
+    /// A fresh key for every start.
+    Ephemeral,
+    /// A raw 32-byte secret key file, generated if missing.
+    File(PathBuf),
+    /// A secret key supplied by the caller.
+    Key([u8; SECRET_KEY_LENGTH]),
+}
+
+/// Where the relay stores messages.
+#[derive(Clone)]
+pub enum StorageLocation {
+    /// A SQLite database file.
+    File(PathBuf),
+    /// A [`MemoryStore`], lost on shutdown.
+    Memory,
+    /// A caller-supplied backend.
+    Custom(Arc<dyn MessageStore>),
+}
+
+/// Builder for [`RelayServer`].
+#[derive(Clone)]
+pub struct RelayServerBuilder {
+    bind: SocketAddr,
+    certificate: CertificateSource,
+    storage: StorageLocation,
+    pool_size: u32,
+    max_concurrent_streams: u32,
+    max_payload_bytes: usize,
+    drain_timeout: Duration,
+    validation: ValidationPolicy,
+    quotas: QuotaPolicy,
+    rate_limits: RateLimitPolicy,
+    sweeper: SweeperConfig,
+    metrics: MetricsConfig,
+    identity: IdentitySource,
+    mdns: bool,
+    mdns_instance: String,
+    mdns_properties: Vec<(String, String)>,
+}
+
+impl Default for RelayServerBuilder {
+    fn default() -> Self {
+        Self::from_config(&RelayConfig::default())
+    }
+}
+
+impl RelayServerBuilder {
+    /// Starts from a (validated) [`RelayConfig`].
+    pub fn from_config(config: &RelayConfig) -> Self {
+        Self {
+            bind: config.bind,
+            certificate: CertificateSource::Files {
+                cert: config.tls.cert.clone(),
+                key: config.tls.key.clone(),
+            },
+            storage: match config.storage.backend {
+                StorageBackend::Sqlite => StorageLocation::File(config.storage.path.clone()),
+                StorageBackend::Memory => StorageLocation::Memory,
+            },
+            pool_size: config.storage.pool_size,
+            max_concurrent_streams: config.max_concurrent_streams,
+            max_payload_bytes: config.max_payload_bytes,
+            drain_timeout: Duration::from_secs(config.drain_timeout_secs),
+            validation: config.validation.clone(),
+            quotas: config.quotas.clone(),
+            rate_limits: config.rate_limits.clone(),
+            sweeper: config.sweeper.clone(),
+            metrics: config.metrics.clone(),
+            identity: match &config.identity_key {
+                Some(path) => IdentitySource::File(path.clone()),
+                None => IdentitySource::Ephemeral,
+            },
+            mdns: config.mdns.enabled,
+            mdns_instance: config.mdns.instance_name.clone(),
+            mdns_properties: config
+                .mdns
+                .properties
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    /// Address to listen on. Port `0` picks a free port; see [`RelayServer::local_addr`].
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = addr;
+        self
+    }
+
+    /// Loads the TLS certificate and key from DER files, creating a
+    /// self-signed pair at those paths if the certificate does not exist.
+    pub fn cert_paths(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.certificate = CertificateSource::Files {
+            cert: cert.into(),
+            key: key.into(),
+        };
+        self
+    }
+
+    /// Uses an in-memory DER certificate and PKCS#8 private key.
+    pub fn certificate(mut self, cert: Vec<u8>, key: Vec<u8>) -> Self {
+        self.certificate = CertificateSource::Der { cert, key };
+        self
+    }
+
+    /// Generates a throwaway self-signed certificate for `localhost`.
+    pub fn self_signed(mut self) -> Self {
+        self.certificate = CertificateSource::SelfSigned;
+        self
+    }
+
+    /// Stores messages in the SQLite database at `path`.
+    pub fn database(mut self, path: impl Into<PathBuf>) -> Self {
+        self.storage = StorageLocation::File(path.into());
+        self
+    }
+
+    /// Stores messages in memory only.
+    pub fn in_memory(mut self) -> Self {
+        self.storage = StorageLocation::Memory;
+        self
+    }
+
+    /// Stores messages in a custom [`MessageStore`].
+    pub fn store(mut self, store: Arc<dyn MessageStore>) -> Self {
+        self.storage = StorageLocation::Custom(store);
+        self
+    }
+
+    /// Pooled connections for the SQLite backend.
+    pub fn pool_size(mut self, size: u32) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// Maximum concurrent bidirectional streams per connection.
+    pub fn max_concurrent_streams(mut self, streams: u32) -> Self {
+        self.max_concurrent_streams = streams;
+        self
+    }
+
+    /// Largest serialized envelope accepted by SEND, in bytes. Must fit an
+    /// envelope at the [`ValidationPolicy`] sender and payload limits;
+    /// [`RelayServerBuilder::build`] refuses a smaller one.
+    pub fn max_payload_bytes(mut self, bytes: usize) -> Self {
+        self.max_payload_bytes = bytes;
+        self
+    }
+
+    /// How long shutdown waits for in-flight requests before closing
+    /// connections anyway.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Checks applied to every envelope before it is stored.
+    pub fn validation(mut self, policy: ValidationPolicy) -> Self {
+        self.validation = policy;
+        self
+    }
+
+    /// Longest envelope TTL accepted by SEND, in seconds.
+    pub fn max_ttl_secs(mut self, secs: u32) -> Self {
+        self.validation.max_ttl_secs = secs;
+        self
+    }
+
+    /// Storage limits enforced on SEND.
+    pub fn quotas(mut self, policy: QuotaPolicy) -> Self {
+        self.quotas = policy;
+        self
+    }
+
+    /// Connection and request rates accepted per IP, connection and sender.
+    /// [`RelayServerBuilder::build`] refuses limits that fail
+    /// [`RateLimitPolicy::validate`].
+    pub fn rate_limits(mut self, policy: RateLimitPolicy) -> Self {
+        self.rate_limits = policy;
+        self
+    }
+
+    /// Schedule and batch size of the background expiry sweep.
+    pub fn sweeper(mut self, config: SweeperConfig) -> Self {
+        self.sweeper = config;
+        self
+    }
+
+    /// Serves Prometheus metrics over HTTP when enabled. Port `0` picks a
+    /// free port; see [`RelayServer::metrics_addr`].
+    pub fn metrics(mut self, config: MetricsConfig) -> Self {
+        self.metrics = config;
+        self
+    }
+
+    /// Uses `secret` as the relay identity key.
+    pub fn identity_key(mut self, secret: [u8; SECRET_KEY_LENGTH]) -> Self {
+        self.identity = IdentitySource::Key(secret);
+        self
+    }
+
+    /// Loads the relay identity key from `path`, generating it if missing.
+    /// On Unix the file is created with mode 0600, and one that group or
+    /// other users may access is refused.
+    pub fn identity_key_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.identity = IdentitySource::File(path.into());
+        self
+    }
+
+    /// Enables or disables mDNS advertisement. Enabled by default.
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.mdns = enabled;
+        self
+    }
+
+    /// mDNS service instance name.
+    pub fn mdns_instance(mut self, name: impl Into<String>) -> Self {
+        self.mdns_instance = name.into();
+        self
+    }
+
+    /// Adds a TXT record property to the mDNS advertisement.
+    pub fn mdns_property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.mdns_properties.push((key.into(), value.into()));
+        self
+    }
+
+    /// Prepares storage, binds the QUIC endpoint and the metrics listener,
+    /// and registers the mDNS service. Must be called from within a Tokio runtime.
+    pub fn build(self) -> Result<RelayServer> {
+        self.rate_limits.validate()?;
+        anyhow::ensure!(
+            self.max_payload_bytes >= self.validation.max_envelope_len(),
+            "max_payload_bytes {} cannot fit an envelope at the validation limits ({} bytes)",
+            self.max_payload_bytes,
+            self.validation.max_envelope_len()
+        );
+        let (cert_der, key_der) = load_certificate(&self.certificate)?;
+        let identity_secret = load_identity(&self.identity)?;
+
+        // Build rustls server configuration
+        let mut rustls_config = RustlsServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der.clone()], PrivateKeyDer::from(key_der))
+            .context("failed to build rustls server config")?;
+
+        rustls_config.alpn_protocols = vec![b"qight".to_vec()];
+
+        // Create Quinn crypto layer
+        let crypto = QuicServerConfig::try_from(rustls_config)
+            .context("failed to create QUIC crypto config")?;
+
+        // Build Quinn server config
+        let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
+
+        // Configure transport parameters
+        Arc::get_mut(&mut server_config.transport)
+            .expect("transport config should be uniquely owned")
+            .max_concurrent_bidi_streams(self.max_concurrent_streams.into());
+
+        let store: Arc<dyn MessageStore> = match self.storage {
+            StorageLocation::File(path) => {
+                Arc::new(SqliteStore::open_with_pool_size(path, self.pool_size)?)
+            }
+            StorageLocation::Memory => Arc::new(MemoryStore::new()),
+            StorageLocation::Custom(store) => store,
+        };
+
+        let endpoint = Endpoint::server(server_config, self.bind)
+            .context("failed to create QUIC endpoint")?;
+        let local_addr = endpoint.local_addr()?;
+
+        let metrics_listener = if self.metrics.enabled {
+            let listener = std::net::TcpListener::bind(self.metrics.bind)
+                .with_context(|| format!("failed to bind metrics listener on {}", self.metrics.bind))?;
+            listener.set_nonblocking(true)?;
+            Some(tokio::net::TcpListener::from_std(listener)?)
+        } else {
+            None
+        };
+
+        let mdns = if self.mdns {
+            Some(register_mdns(local_addr, &self.mdns_instance, &self.mdns_properties)?)
+        } else {
+            None
+        };
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        Ok(RelayServer {
+            endpoint,
+            local_addr,
+            certificate: cert_der,
+            context: Arc::new(RelayContext {
+                store,
+                max_payload_bytes: self.max_payload_bytes,
+                validation: self.validation,
+                quotas: Quotas::new(self.quotas, self.sweeper.batch_size),
+                rate_limiter: RateLimiter::new(self.rate_limits),
+                features: Features::supported(),
+                identity_key: public_key_from_secret(&identity_secret),
+                identity_secret,
+                subscriptions: Subscriptions::default(),
+                receipt_watchers: Subscriptions::default(),
+                sweeper: Arc::new(Sweeper::new(self.sweeper)),
+                metrics: RelayMetrics::default(),
+                in_flight: Arc::new(InFlight::default()),
+                shutdown: shutdown_rx.clone(),
+            }),
+            drain_timeout: self.drain_timeout,
+            metrics_listener,
+            mdns,
+            shutdown_tx,
+            shutdown_rx,
+        })
+    }
+}
+
+/// An embeddable qight relay.
+///
+/// ```no_run
+/// # async fn demo() -> anyhow::Result<()> {
+/// let server = qight::relay::RelayServer::builder()
+///     .bind("127.0.0.1:0".parse()?)
+///     .self_signed()
+///     .in_memory()
+///     .mdns(false)
+///     .build()?;
+/// let shutdown = server.shutdown_handle();
+/// let task = tokio::spawn(server.run());
+/// shutdown.shutdown();
+/// task.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RelayServer {
+    endpoint: Endpoint,
+    local_addr: SocketAddr,
+    certificate: CertificateDer<'static>,
+    context: Arc<RelayContext>,
+    drain_timeout: Duration,
+    metrics_listener: Option<tokio::net::TcpListener>,
+    mdns: Option<(ServiceDaemon, String)>,
+    shutdown_tx: watch::Sender<bool>,
+    shutdown_rx: watch::Receiver<bool>,
+}
+
+impl RelayServer {
+    pub fn builder() -> RelayServerBuilder {
+        RelayServerBuilder::default()
+    }
+
+    /// The address the QUIC endpoint is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The address `/metrics` is served on, if enabled.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Public half of the relay identity key, for pinning with
+    /// `RelayClientBuilder::relay_key`.
+    pub fn identity_key(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.context.identity_key
+    }
+
+    /// DER certificate presented to clients, for trusting a self-signed relay.
+    pub fn certificate(&self) -> &CertificateDer<'static> {
+        &self.certificate
+    }
+
+    /// The storage backend messages are kept in.
+    pub fn store(&self) -> &Arc<dyn MessageStore> {
+        &self.context.store
+    }
+
+    /// Handle that stops [`RelayServer::run`] from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            tx: self.shutdown_tx.clone(),
+        }
+    }
+
+    /// Accepts connections until [`ShutdownHandle::shutdown`] is called,
+    /// then shuts down gracefully: new connections are refused, the mDNS
+    /// service is withdrawn, in-flight requests get the drain timeout to
+    /// finish, connections are closed with [`CloseCode::ShuttingDown`] and
+    /// the database is checkpointed.
+    pub async fn run(self) -> Result<()> {
+        info!(addr = %self.local_addr, "relay listening");
+
+        let sweeper = tokio::spawn(sweeper::run(self.context.clone(), self.shutdown_rx.clone()));
+        if let Some(listener) = self.metrics_listener {
+            info!(addr = %listener.local_addr()?, "serving metrics");
+            tokio::spawn(metrics::serve(
+                listener,
+                self.context.clone(),
+                self.shutdown_rx.clone(),
+            ));
+        }
+
+        let mut shutdown = self.shutdown_rx.clone();
+        loop {
+            let connecting = tokio::select! {
+                incoming = self.endpoint.accept() => match incoming {
+                    Some(incoming) => incoming,
+                    None => break,
+                },
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+
+            let context = self.context.clone();
+            let span = tracing::info_span!(
+                "connection",
+                remote = %connecting.remote_address(),
+                id = tracing::field::Empty,
+            );
+            if let Err(delay) = context.rate_limiter.accept(connecting.remote_address().ip()) {
+                context.metrics.rate_limited(RateLimitScope::Connection);
+                refuse_rate_limited(connecting, delay, &context.rate_limiter, span);
+                continue;
+            }
+            tokio::spawn(
+                async move {
+                    match connecting.await {
+                        Ok(connection) => {
+                            tracing::Span::current().record("id", connection.stable_id());
+                            info!("connection established");
+                            context.metrics.connection_opened();
+                            if let Err(e) = handle_connection(connection, context.clone()).await {
+                                warn!(error = %e, "connection failed");
+                            }
+                            context.metrics.connection_closed();
+                            info!("connection closed");
+                        }
+                        Err(e) => {
+                            context.metrics.handshake_failed();
+                            warn!(error = %e, "handshake failed");
+                        }
+                    }
+                }
+                .instrument(span),
+            );
+        }
+
+        info!("shutting down");
+        self.endpoint.set_server_config(None);
+        if let Some((mdns, fullname)) = &self.mdns {
+            if let Err(e) = mdns.unregister(fullname) {
+                warn!(error = %e, "failed to unregister mDNS service");
+            }
+            let _ = mdns.shutdown();
+        }
+
+        let in_flight = &self.context.in_flight;
+        if tokio::time::timeout(self.drain_timeout, in_flight.idle())
+            .await
+            .is_err()
+        {
+            warn!(abandoned = in_flight.count(), "drain timeout passed");
+        }
+        self.endpoint.close(CloseCode::ShuttingDown.as_u32().into(), b"relay shutting down");
+        // Let peers see the close so the socket is released promptly.
+        self.endpoint.wait_idle().await;
+
+        // A sweep in progress finishes before the final checkpoint.
+        let _ = sweeper.await;
+        let store = self.context.store.clone();
+        match tokio::task::spawn_blocking(move || store.checkpoint()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!(error = %e, "final checkpoint failed"),
+            Err(e) => error!(error = %e, "final checkpoint panicked"),
+        }
+        info!("relay stopped");
+        Ok(())
+    }
+}
+
+/// Turns away a connection over its rate limit without a handshake where
+/// possible. An unvalidated address is first sent a stateless retry, so a
+/// spoofed one costs nothing further. A validated one is refused, unless
+/// [`RateLimiter::explain_refusal`] allows completing the handshake to tell
+/// the client when to come back, so it can tell a rate limit from an outage.
+fn refuse_rate_limited(
+    incoming: quinn::Incoming,
+    delay: std::time::Duration,
+    limiter: &RateLimiter,
+    span: tracing::Span,
+) {
+    let entered = span.enter();
+    if !incoming.remote_address_validated() {
+        debug!("rate limited connection asked to validate its address");
+        if let Err(e) = incoming.retry() {
+            e.into_incoming().refuse();
+        }
+        return;
+    }
+    info!(retry_after = ?delay, "connection rate limited");
+    if !limiter.explain_refusal(incoming.remote_address().ip()) {
+        incoming.refuse();
+        return;
+    }
+    drop(entered);
+    tokio::spawn(
+        async move {
+            if let Ok(connection) = incoming.await {
+                let reason = retry_after_ms(delay).to_string();
+                connection.close(CloseCode::RateLimited.as_u32().into(), reason.as_bytes());
+            }
+        }
+        .instrument(span),
+    );
+}
+
+/// Stops a running [`RelayServer`]. Cheap to clone.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    tx: watch::Sender<bool>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+}
+
+fn load_certificate(
+    source: &CertificateSource,
+) -> Result<(CertificateDer<'static>, PrivatePkcs8KeyDer<'static>)> {
+    match source {
+        CertificateSource::Files { cert, key } if Path::new(cert).exists() => Ok((
+            CertificateDer::from(read(cert)?),
+            PrivatePkcs8KeyDer::from(read(key)?),
+        )),
+        CertificateSource::Files { cert, key } => {
+            let (cert_der, key_der) = self_signed_certificate()?;
+            write(cert, cert_der.as_ref())?;
+            write_private_key(key, key_der.secret_pkcs8_der())?;
+            Ok((cert_der, key_der))
+        }
+        CertificateSource::Der { cert, key } => Ok((
+            CertificateDer::from(cert.clone()),
+            PrivatePkcs8KeyDer::from(key.clone()),
+        )),
+        CertificateSource::SelfSigned => self_signed_certificate(),
+    }
+}
+
+fn load_identity(source: &IdentitySource) -> Result<[u8; SECRET_KEY_LENGTH]> {
+    match source {
+        IdentitySource::Ephemeral => Ok(gen_keypair().1),
+        IdentitySource::Key(secret) => Ok(*secret),
+        IdentitySource::File(path) if path.exists() => read_identity(path),
+        IdentitySource::File(path) => create_identity(path),
+    }
+}
+
+/// Reads an identity key file, refusing one other users may access.
+fn read_identity(path: &Path) -> Result<[u8; SECRET_KEY_LENGTH]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            anyhow::bail!(
+                "identity key {} has mode {:o}; restrict it to 0600",
+                path.display(),
+                mode
+            );
+        }
+    }
+    read(path)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("identity key {} must be 32 bytes", path.display()))
+}
+
+/// Generates an identity key into a new file only the owner may access.
+fn create_identity(path: &Path) -> Result<[u8; SECRET_KEY_LENGTH]> {
+    let (_, secret) = gen_keypair();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("cannot create identity key {}", path.display()))?;
+    file.write_all(&secret)?;
+    Ok(secret)
+}
+
+/// Writes a generated TLS private key so only the owner may access it.
+fn write_private_key(path: &Path, key: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("cannot write TLS key {}", path.display()))?;
+    // The mode only applies to new files; tighten one left from before.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(key)?;
+    Ok(())
+}
+
+fn self_signed_certificate() -> Result<(CertificateDer<'static>, PrivatePkcs8KeyDer<'static>)> {
+    let cert_key = generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert = CertificateDer::from(cert_key.cert.der().to_vec());
+    let key = PrivatePkcs8KeyDer::from(cert_key.signing_key.serialize_der());
+    Ok((cert, key))
+}
+
+/// Publishes the relay over mDNS. Returns the daemon and the registered
+/// service's full name so it can be unregistered on shutdown.
+fn register_mdns(
+    addr: SocketAddr,
+    instance_name: &str,
+    properties: &[(String, String)],
+) -> Result<(ServiceDaemon, String)> {
+    let mdns = ServiceDaemon::new().context("failed to create mDNS daemon")?;
+
+    let receiver = mdns.monitor().context("failed to monitor mDNS daemon")?;
+    std::thread::spawn(move || {
+        while let Ok(event) = receiver.recv() {
+            if let mdns_sd::DaemonEvent::Error(error) = event {
+                warn!(%error, "mDNS daemon error");
+            }
+        }
+    });
+
+    let host_name = format!("{}.local.", instance_name);
+
+    let my_service = ServiceInfo::new(
+        SERVICE_TYPE,
+        instance_name,
+        &host_name,
+        addr.ip().to_string(),
+        addr.port(),
+        properties,
+    )
+    .context("invalid mDNS service info")?;
+    let fullname = my_service.get_fullname().to_string();
+
+    // Register with the daemon, which publishes the service.
+    mdns.register(my_service)
+        .context("failed to register mDNS service")?;
+
+    Ok((mdns, fullname))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::rate_limit::RateLimit;
+
+    #[tokio::test]
+    async fn test_server_runs_until_shutdown() {
+        let server = RelayServer::builder()
+            .bind("127.0.0.1:0".parse().unwrap())
+            .self_signed()
+            .in_memory()
+            .mdns(false)
+            .build()
+            .unwrap();
+        assert_ne!(server.local_addr().port(), 0);
+
+        let shutdown = server.shutdown_handle();
+        let task = tokio::spawn(server.run());
+        shutdown.shutdown();
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), task)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_build_refuses_zero_rate() {
+        let policy = RateLimitPolicy {
+            sends_per_sender: Some(RateLimit::new(0.0, 5)),
+            ..RateLimitPolicy::default()
+        };
+        let result = RelayServer::builder()
+            .bind("127.0.0.1:0".parse().unwrap())
+            .self_signed()
+            .in_memory()
+            .mdns(false)
+            .rate_limits(policy)
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_identity_key_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("qight-identity-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let source = IdentitySource::File(path.clone());
+        let secret = load_identity(&source).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(load_identity(&source).unwrap(), secret);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(load_identity(&source).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_generated_tls_key_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir();
+        let cert = dir.join(format!("qight-cert-{}", std::process::id()));
+        let key = dir.join(format!("qight-key-{}", std::process::id()));
+        let _ = std::fs::remove_file(&cert);
+        std::fs::write(&key, b"stale").unwrap();
+        std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let source = CertificateSource::Files {
+            cert: cert.clone(),
+            key: key.clone(),
+        };
+        let (_, key_der) = load_certificate(&source).unwrap();
+        let mode = std::fs::metadata(&key).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(read(&key).unwrap(), key_der.secret_pkcs8_der());
+        std::fs::remove_file(&cert).unwrap();
+        std::fs::remove_file(&key).unwrap();
+    }
+}
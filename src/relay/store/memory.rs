@@ -0,0 +1,289 @@
+use anyhow::Result;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use super::{
+    ExpiredBatch, MessageStore, ReceiptEvent, ReceiptLog, StoreStats, Usage, RECEIPT_RETENTION_SECS,
+};
+use crate::protocol::{Receipt, ReceiptStatus};
+use crate::{MessageEnvelope, MessageId};
+
+struct Entry {
+    envelope: MessageEnvelope,
+    lease_until: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_seq: u64,
+    /// Messages keyed by insertion order.
+    messages: BTreeMap<u64, Entry>,
+    /// `msg_id` to insertion sequence.
+    ids: HashMap<MessageId, u64>,
+    /// `msg_id` to the accepted envelope's digest and expiry time.
+    accepted: HashMap<MessageId, ([u8; 32], u64)>,
+    /// `msg_id` to its receipt log and the time it is forgotten after.
+    receipts: HashMap<MessageId, (ReceiptLog, u64)>,
+    /// Payload bytes of `messages`.
+    payload_bytes: u64,
+}
+
+/// Volatile [`MessageStore`] for tests and ephemeral relays.
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn usage(&self, matches: impl Fn(&MessageEnvelope) -> bool) -> Usage {
+        let inner = self.inner.lock().unwrap();
+        let mut usage = Usage::default();
+        for entry in inner.messages.values().filter(|entry| matches(&entry.envelope)) {
+            usage.messages += 1;
+            usage.payload_bytes += entry.envelope.payload.len() as u64;
+        }
+        usage
+    }
+}
+
+fn expires_at(envelope: &MessageEnvelope) -> u64 {
+    envelope.timestamp + envelope.ttl as u64
+}
+
+impl Inner {
+    /// Stores `envelope` and records its acceptance. Returns `false` if
+    /// `msg_id` was already accepted.
+    fn insert(&mut self, envelope: &MessageEnvelope) -> bool {
+        if self.accepted.contains_key(&envelope.msg_id) {
+            return false;
+        }
+        self.accepted
+            .insert(envelope.msg_id, (envelope.digest(), expires_at(envelope)));
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.ids.insert(envelope.msg_id, seq);
+        self.payload_bytes += envelope.payload.len() as u64;
+        self.messages.insert(
+            seq,
+            Entry {
+                envelope: envelope.clone(),
+                lease_until: 0,
+            },
+        );
+        true
+    }
+
+    /// Deletes the message stored under `seq`.
+    fn remove(&mut self, seq: u64) -> Option<Entry> {
+        let entry = self.messages.remove(&seq)?;
+        self.ids.remove(&entry.envelope.msg_id);
+        self.payload_bytes -= entry.envelope.payload.len() as u64;
+        Some(entry)
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn insert(&self, envelope: &MessageEnvelope) -> Result<bool> {
+        Ok(self.inner.lock().unwrap().insert(envelope))
+    }
+
+    fn insert_with_receipts(&self, envelope: &MessageEnvelope, now: u64) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.insert(envelope) {
+            return Ok(false);
+        }
+        let log = ReceiptLog {
+            sender_key: envelope.sender_key,
+            recipient: envelope.recipient,
+            receipts: vec![Receipt::new(envelope.msg_id, ReceiptStatus::Stored, now)],
+        };
+        let forget_at = expires_at(envelope) + RECEIPT_RETENTION_SECS;
+        inner.receipts.insert(envelope.msg_id, (log, forget_at));
+        Ok(true)
+    }
+
+    fn contains(&self, msg_id: &MessageId) -> Result<bool> {
+        Ok(self.inner.lock().unwrap().ids.contains_key(msg_id))
+    }
+
+    fn accepted(&self, msg_id: &MessageId) -> Result<Option<[u8; 32]>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.accepted.get(msg_id).map(|(digest, _)| *digest))
+    }
+
+    fn fetch_for_recipient(
+        &self,
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        now: u64,
+        lease: Option<u64>,
+    ) -> Result<Vec<MessageEnvelope>> {
+        let mut inner = self.inner.lock().unwrap();
+        let selected: Vec<u64> = inner
+            .messages
+            .iter()
+            .filter(|(_, entry)| {
+                &entry.envelope.recipient == recipient
+                    && entry.lease_until <= now
+                    && expires_at(&entry.envelope) >= now
+            })
+            .map(|(seq, _)| *seq)
+            .collect();
+
+        let mut msgs = Vec::with_capacity(selected.len());
+        for seq in selected {
+            match lease {
+                Some(secs) => {
+                    let entry = inner.messages.get_mut(&seq).expect("selected entry");
+                    entry.lease_until = now + secs;
+                    msgs.push(entry.envelope.clone());
+                }
+                None => {
+                    let entry = inner.remove(seq).expect("selected entry");
+                    msgs.push(entry.envelope);
+                }
+            }
+        }
+        Ok(msgs)
+    }
+
+    fn ack(
+        &self,
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        msg_ids: &[MessageId],
+    ) -> Result<Vec<MessageId>> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut deleted = Vec::new();
+        for msg_id in msg_ids {
+            let seq = match inner.ids.get(msg_id) {
+                Some(seq) => *seq,
+                None => continue,
+            };
+            if &inner.messages[&seq].envelope.recipient == recipient {
+                inner.remove(seq);
+                deleted.push(*msg_id);
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn expire_batch(&self, now: u64, max: usize) -> Result<ExpiredBatch> {
+        let mut inner = self.inner.lock().unwrap();
+        let expired: Vec<u64> = inner
+            .messages
+            .iter()
+            .filter(|(_, entry)| expires_at(&entry.envelope) < now)
+            .map(|(seq, _)| *seq)
+            .take(max)
+            .collect();
+        let mut events = Vec::with_capacity(expired.len());
+        for seq in &expired {
+            if let Some(entry) = inner.remove(*seq) {
+                let receipt = Receipt::new(entry.envelope.msg_id, ReceiptStatus::Expired, now);
+                if let Some((log, _)) = inner.receipts.get_mut(&receipt.msg_id) {
+                    log.receipts.push(receipt.clone());
+                }
+                events.push(ReceiptEvent {
+                    sender_key: entry.envelope.sender_key,
+                    receipt,
+                });
+            }
+        }
+        let forgotten: Vec<_> = inner
+            .accepted
+            .iter()
+            .filter(|(_, (_, expires_at))| *expires_at < now)
+            .map(|(msg_id, _)| *msg_id)
+            .take(max)
+            .collect();
+        for msg_id in &forgotten {
+            inner.accepted.remove(msg_id);
+        }
+        let acceptances = forgotten.len();
+        let forgotten: Vec<_> = inner
+            .receipts
+            .iter()
+            .filter(|(_, (_, forget_at))| *forget_at < now)
+            .map(|(msg_id, _)| *msg_id)
+            .take(max)
+            .collect();
+        for msg_id in &forgotten {
+            inner.receipts.remove(msg_id);
+        }
+        Ok(ExpiredBatch {
+            expired: events,
+            acceptances,
+            receipts: forgotten.len(),
+        })
+    }
+
+    fn record_receipt(&self, receipt: &Receipt) -> Result<Option<ReceiptEvent>> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some((log, _)) = inner.receipts.get_mut(&receipt.msg_id) else {
+            return Ok(None);
+        };
+        if log.receipts.iter().any(|r| r.status == receipt.status) {
+            return Ok(None);
+        }
+        log.receipts.push(receipt.clone());
+        Ok(Some(ReceiptEvent {
+            sender_key: log.sender_key,
+            receipt: receipt.clone(),
+        }))
+    }
+
+    fn receipts(&self, msg_id: &MessageId) -> Result<Option<ReceiptLog>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.receipts.get(msg_id).map(|(log, _)| log.clone()))
+    }
+
+    fn count(&self) -> Result<u64> {
+        Ok(self.inner.lock().unwrap().messages.len() as u64)
+    }
+
+    fn stats(&self, now: u64) -> Result<StoreStats> {
+        let inner = self.inner.lock().unwrap();
+        let mut stats = StoreStats::default();
+        let mut recipients = HashSet::new();
+        for entry in inner.messages.values() {
+            stats.messages += 1;
+            if entry.lease_until > now {
+                stats.leased += 1;
+            }
+            stats.payload_bytes += entry.envelope.payload.len() as u64;
+            recipients.insert(entry.envelope.recipient);
+        }
+        stats.recipients = recipients.len() as u64;
+        Ok(stats)
+    }
+
+    fn payload_bytes(&self) -> Result<u64> {
+        Ok(self.inner.lock().unwrap().payload_bytes)
+    }
+
+    fn recipient_usage(&self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Usage> {
+        Ok(self.usage(|envelope| &envelope.recipient == recipient))
+    }
+
+    fn sender_usage(&self, sender_key: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Usage> {
+        Ok(self.usage(|envelope| &envelope.sender_key == sender_key))
+    }
+
+    fn evict_oldest(&self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let oldest = inner
+            .messages
+            .iter()
+            .find(|(_, entry)| &entry.envelope.recipient == recipient)
+            .map(|(seq, _)| *seq);
+        let Some(seq) = oldest else {
+            return Ok(false);
+        };
+        inner.remove(seq);
+        Ok(true)
+    }
+}
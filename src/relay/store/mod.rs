@@ -0,0 +1,398 @@
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use anyhow::Result;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+
+use crate::protocol::Receipt;
+use crate::{MessageEnvelope, MessageId};
+
+/// How long a receipt log outlives its message's TTL, in seconds, so the
+/// sender can still learn how the message ended.
+pub const RECEIPT_RETENTION_SECS: u64 = 24 * 60 * 60;
+
+/// What one [`MessageStore::expire_batch`] call removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExpiredBatch {
+    /// Expired receipts of the deleted messages, one per message.
+    pub expired: Vec<ReceiptEvent>,
+    /// Acceptances forgotten after their envelope's TTL.
+    pub acceptances: usize,
+    /// Receipts forgotten after their retention.
+    pub receipts: usize,
+}
+
+impl ExpiredBatch {
+    /// Whether every kind of row came in under `max`, so nothing expired
+    /// was left behind.
+    pub fn is_last(&self, max: usize) -> bool {
+        self.expired.len() < max && self.acceptances < max && self.receipts < max
+    }
+}
+
+/// Aggregate figures reported by [`MessageStore::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Stored messages, including leased ones.
+    pub messages: u64,
+    /// Messages currently hidden by an unexpired lease.
+    pub leased: u64,
+    /// Distinct recipients with at least one stored message.
+    pub recipients: u64,
+    /// Total payload bytes stored.
+    pub payload_bytes: u64,
+}
+
+/// Messages and payload bytes held for one recipient or sender key, as
+/// reported by [`MessageStore::recipient_usage`] and
+/// [`MessageStore::sender_usage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub messages: u64,
+    pub payload_bytes: u64,
+}
+
+/// Receipts recorded for one message, as returned by
+/// [`MessageStore::receipts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptLog {
+    pub sender_key: [u8; PUBLIC_KEY_LENGTH],
+    pub recipient: [u8; PUBLIC_KEY_LENGTH],
+    /// At most one receipt per status, in the order they were recorded.
+    pub receipts: Vec<Receipt>,
+}
+
+/// A receipt that was just recorded, and the sender key it is reported to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptEvent {
+    pub sender_key: [u8; PUBLIC_KEY_LENGTH],
+    pub receipt: Receipt,
+}
+
+/// Storage backend for relayed messages.
+///
+/// Methods are blocking; the relay calls them from
+/// [`tokio::task::spawn_blocking`]. Times are Unix seconds.
+pub trait MessageStore: Send + Sync + 'static {
+    /// Stores a verified envelope and records its acceptance. Returns
+    /// `false`, leaving the store untouched, if `msg_id` was already
+    /// accepted.
+    fn insert(&self, envelope: &MessageEnvelope) -> Result<bool>;
+
+    /// Like [`MessageStore::insert`], and atomically starts the envelope's
+    /// receipt log with a stored receipt at `now`, so no later receipt can
+    /// miss it. The log is kept until [`RECEIPT_RETENTION_SECS`] after the
+    /// envelope expires.
+    fn insert_with_receipts(&self, envelope: &MessageEnvelope, now: u64) -> Result<bool>;
+
+    /// Whether a message with `msg_id` is stored.
+    fn contains(&self, msg_id: &MessageId) -> Result<bool>;
+
+    /// [`MessageEnvelope::digest`] of the envelope accepted under `msg_id`.
+    ///
+    /// Acceptances outlive delivery, ACK and eviction and are only forgotten
+    /// by [`MessageStore::expire_batch`] once the envelope's TTL elapses, so
+    /// a SEND retried after its message was fetched is still recognised.
+    fn accepted(&self, msg_id: &MessageId) -> Result<Option<[u8; 32]>>;
+
+    /// Returns the unexpired, unleased messages for `recipient`, oldest first.
+    ///
+    /// With a `lease` (seconds) the messages stay stored but are hidden from
+    /// other fetches until `now + lease`, unless acknowledged with
+    /// [`MessageStore::ack`]. Without one they are deleted immediately.
+    fn fetch_for_recipient(
+        &self,
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        now: u64,
+        lease: Option<u64>,
+    ) -> Result<Vec<MessageEnvelope>>;
+
+    /// Deletes acknowledged messages addressed to `recipient`. Returns the
+    /// ids of those removed.
+    fn ack(
+        &self,
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        msg_ids: &[MessageId],
+    ) -> Result<Vec<MessageId>>;
+
+    /// Deletes up to `max` messages whose TTL elapsed before `now`, recording
+    /// an expired receipt for each, and forgets up to `max` acceptances and
+    /// up to `max` receipts that are past their retention. Once
+    /// [`ExpiredBatch::is_last`] holds for `max`, nothing is left.
+    fn expire_batch(&self, now: u64, max: usize) -> Result<ExpiredBatch>;
+
+    /// Deletes every message whose TTL elapsed before `now`. Returns how many
+    /// were removed.
+    fn expire(&self, now: u64) -> Result<usize> {
+        Ok(self.expire_batch(now, usize::MAX)?.expired.len())
+    }
+
+    /// Appends `receipt` to the log of its message. Returns the recorded
+    /// event, or `None` if the message has no log or a receipt with that
+    /// status was recorded before.
+    fn record_receipt(&self, receipt: &Receipt) -> Result<Option<ReceiptEvent>>;
+
+    /// The receipt log of `msg_id`, while it is kept.
+    fn receipts(&self, msg_id: &MessageId) -> Result<Option<ReceiptLog>>;
+
+    /// Returns free pages to the file system, a few at a time. The default
+    /// does nothing.
+    fn incremental_vacuum(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Folds the write-ahead log back into the database. The default does
+    /// nothing.
+    fn checkpoint(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Number of stored messages.
+    fn count(&self) -> Result<u64>;
+
+    /// Aggregate storage figures as of `now`.
+    fn stats(&self, now: u64) -> Result<StoreStats>;
+
+    /// Total payload bytes stored, as in [`StoreStats::payload_bytes`].
+    /// Checked on every SEND under a global quota, so the built-in stores
+    /// keep a running total; the default computes [`MessageStore::stats`].
+    fn payload_bytes(&self) -> Result<u64> {
+        Ok(self.stats(0)?.payload_bytes)
+    }
+
+    /// Stored messages addressed to `recipient`, leased ones included.
+    fn recipient_usage(&self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Usage>;
+
+    /// Stored messages signed by `sender_key`, leased ones included.
+    fn sender_usage(&self, sender_key: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Usage>;
+
+    /// Deletes the oldest message addressed to `recipient`. Returns `false`
+    /// if there was none.
+    fn evict_oldest(&self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<bool>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(recipient: [u8; 32], ttl: u32) -> MessageEnvelope {
+        MessageEnvelope::new("s".to_string(), recipient, [1u8; 32], b"payload".to_vec(), ttl)
+    }
+
+    fn stores() -> Vec<Box<dyn MessageStore>> {
+        vec![
+            Box::new(MemoryStore::new()),
+            Box::new(SqliteStore::in_memory().unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_insert_ignores_duplicate_msg_id() {
+        for store in stores() {
+            let msg = envelope([7u8; 32], 3600);
+            assert!(store.insert(&msg).unwrap());
+            let mut copy = msg.clone();
+            copy.payload = b"other".to_vec();
+            assert!(!store.insert(&copy).unwrap());
+            assert_eq!(store.count().unwrap(), 1);
+            let stored = store.fetch_for_recipient(&[7u8; 32], msg.timestamp, None).unwrap();
+            assert_eq!(stored[0].payload, msg.payload);
+        }
+    }
+
+    #[test]
+    fn test_payload_bytes_follow_every_change() {
+        for store in stores() {
+            let matches = |store: &dyn MessageStore| {
+                assert_eq!(store.payload_bytes().unwrap(), store.stats(0).unwrap().payload_bytes);
+            };
+            let msgs: Vec<_> = (0..5).map(|_| envelope([7u8; 32], 3600)).collect();
+            let short = envelope([8u8; 32], 10);
+            for msg in msgs.iter().chain([&short]) {
+                store.insert(msg).unwrap();
+            }
+            assert!(!store.insert(&msgs[0]).unwrap());
+            assert_eq!(store.payload_bytes().unwrap(), 6 * 7);
+            matches(store.as_ref());
+
+            let now = msgs[0].timestamp;
+            store.ack(&[7u8; 32], &[msgs[0].msg_id]).unwrap();
+            matches(store.as_ref());
+            store.evict_oldest(&[7u8; 32]).unwrap();
+            matches(store.as_ref());
+            store.fetch_for_recipient(&[7u8; 32], now, Some(30)).unwrap();
+            assert_eq!(store.payload_bytes().unwrap(), 4 * 7);
+            store.expire(now + 11).unwrap();
+            matches(store.as_ref());
+            store.fetch_for_recipient(&[7u8; 32], now + 60, None).unwrap();
+            assert_eq!(store.payload_bytes().unwrap(), 0);
+            matches(store.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_acceptance_outlives_delivery_until_expiry() {
+        for store in stores() {
+            let msg = envelope([7u8; 32], 60);
+            let now = msg.timestamp;
+            assert_eq!(store.accepted(&msg.msg_id).unwrap(), None);
+            store.insert(&msg).unwrap();
+            assert_eq!(store.fetch_for_recipient(&[7u8; 32], now, None).unwrap().len(), 1);
+
+            assert!(!store.contains(&msg.msg_id).unwrap());
+            assert_eq!(store.accepted(&msg.msg_id).unwrap(), Some(msg.digest()));
+            assert!(!store.insert(&msg).unwrap());
+            assert_eq!(store.count().unwrap(), 0);
+
+            assert_eq!(store.expire(now + 61).unwrap(), 0);
+            assert_eq!(store.accepted(&msg.msg_id).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_leased_fetch_hides_until_expiry_and_ack_deletes() {
+        for store in stores() {
+            let recipient = [7u8; 32];
+            let msg = envelope(recipient, 3600);
+            store.insert(&msg).unwrap();
+            let now = msg.timestamp;
+
+            let leased = store.fetch_for_recipient(&recipient, now, Some(30)).unwrap();
+            assert_eq!(leased.len(), 1);
+            assert_eq!(leased[0].signature, msg.signature);
+            assert!(store.fetch_for_recipient(&recipient, now + 10, Some(30)).unwrap().is_empty());
+            assert_eq!(store.stats(now + 10).unwrap().leased, 1);
+
+            // Un-acked messages reappear once the lease lapses.
+            let redelivered = store.fetch_for_recipient(&recipient, now + 31, Some(30)).unwrap();
+            assert_eq!(redelivered.len(), 1);
+
+            assert!(store.ack(&[9u8; 32], &[msg.msg_id]).unwrap().is_empty());
+            assert_eq!(store.ack(&recipient, &[msg.msg_id]).unwrap(), vec![msg.msg_id]);
+            assert_eq!(store.count().unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_unleased_fetch_deletes_in_order() {
+        for store in stores() {
+            let recipient = [7u8; 32];
+            let first = envelope(recipient, 3600);
+            let second = envelope(recipient, 3600);
+            store.insert(&first).unwrap();
+            store.insert(&second).unwrap();
+            store.insert(&envelope([8u8; 32], 3600)).unwrap();
+
+            let fetched = store.fetch_for_recipient(&recipient, first.timestamp, None).unwrap();
+            let ids: Vec<_> = fetched.iter().map(|m| m.msg_id).collect();
+            assert_eq!(ids, vec![first.msg_id, second.msg_id]);
+            assert!(store.fetch_for_recipient(&recipient, first.timestamp, None).unwrap().is_empty());
+            assert_eq!(store.count().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_usage_and_evict_oldest() {
+        for store in stores() {
+            let recipient = [7u8; 32];
+            let first = envelope(recipient, 3600);
+            let second = envelope(recipient, 3600);
+            store.insert(&first).unwrap();
+            store.insert(&second).unwrap();
+            store.insert(&envelope([8u8; 32], 3600)).unwrap();
+            assert!(store.contains(&first.msg_id).unwrap());
+
+            let payload = b"payload".len() as u64;
+            assert_eq!(
+                store.recipient_usage(&recipient).unwrap(),
+                Usage { messages: 2, payload_bytes: 2 * payload }
+            );
+            assert_eq!(store.sender_usage(&[1u8; 32]).unwrap().messages, 3);
+
+            assert!(store.evict_oldest(&recipient).unwrap());
+            assert!(!store.contains(&first.msg_id).unwrap());
+            assert!(store.contains(&second.msg_id).unwrap());
+            assert!(store.evict_oldest(&recipient).unwrap());
+            assert!(!store.evict_oldest(&recipient).unwrap());
+            assert_eq!(store.recipient_usage(&recipient).unwrap(), Usage::default());
+        }
+    }
+
+    #[test]
+    fn test_expire_and_stats() {
+        for store in stores() {
+            let short = envelope([7u8; 32], 10);
+            let long = envelope([8u8; 32], 3600);
+            store.insert(&short).unwrap();
+            store.insert(&long).unwrap();
+
+            let stats = store.stats(short.timestamp).unwrap();
+            assert_eq!(stats.messages, 2);
+            assert_eq!(stats.recipients, 2);
+            assert_eq!(stats.payload_bytes, 2 * b"payload".len() as u64);
+
+            assert!(store.fetch_for_recipient(&[7u8; 32], short.timestamp + 11, Some(30)).unwrap().is_empty());
+            assert_eq!(store.expire(short.timestamp + 11).unwrap(), 1);
+            assert_eq!(store.count().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_expire_batch_stops_at_max() {
+        for store in stores() {
+            let expiring: Vec<_> = (0..3).map(|_| envelope([7u8; 32], 10)).collect();
+            for msg in &expiring {
+                store.insert(msg).unwrap();
+            }
+            store.insert(&envelope([8u8; 32], 3600)).unwrap();
+            let now = expiring[0].timestamp + 11;
+
+            assert_eq!(store.expire_batch(now, 2).unwrap().expired.len(), 2);
+            assert_eq!(store.expire_batch(now, 2).unwrap().expired.len(), 1);
+            assert!(store.expire_batch(now, 2).unwrap().expired.is_empty());
+            assert_eq!(store.count().unwrap(), 1);
+            store.incremental_vacuum().unwrap();
+            store.checkpoint().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_receipt_log_records_once_and_outlives_expiry() {
+        use crate::protocol::ReceiptStatus;
+
+        for store in stores() {
+            let msg = envelope([7u8; 32], 10);
+            let now = msg.timestamp;
+            let delivered = Receipt::new(msg.msg_id, ReceiptStatus::Delivered, now + 1);
+            assert_eq!(store.receipts(&msg.msg_id).unwrap(), None);
+            assert_eq!(store.record_receipt(&delivered).unwrap(), None);
+
+            assert!(store.insert_with_receipts(&msg, now).unwrap());
+            assert!(!store.insert_with_receipts(&msg, now).unwrap());
+            let event = store.record_receipt(&delivered).unwrap().unwrap();
+            assert_eq!(event.sender_key, msg.sender_key);
+            assert_eq!(store.record_receipt(&delivered).unwrap(), None);
+
+            let expired = store.expire_batch(now + 11, 10).unwrap().expired;
+            assert_eq!(expired.len(), 1);
+            assert_eq!(expired[0].receipt.status, ReceiptStatus::Expired);
+
+            let log = store.receipts(&msg.msg_id).unwrap().unwrap();
+            assert_eq!((log.sender_key, log.recipient), (msg.sender_key, msg.recipient));
+            let statuses: Vec<_> = log.receipts.iter().map(|r| r.status).collect();
+            assert_eq!(
+                statuses,
+                [ReceiptStatus::Stored, ReceiptStatus::Delivered, ReceiptStatus::Expired]
+            );
+
+            let forget_at = now + 10 + RECEIPT_RETENTION_SECS;
+            assert_eq!(store.expire_batch(forget_at, 10).unwrap().receipts, 0);
+            assert!(store.receipts(&msg.msg_id).unwrap().is_some());
+            assert!(store.expire_batch(forget_at + 1, 10).unwrap().receipts > 0);
+            assert_eq!(store.receipts(&msg.msg_id).unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,732 @@
+use anyhow::Result;
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OptionalExtension;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+use super::{
+    ExpiredBatch, MessageStore, ReceiptEvent, ReceiptLog, StoreStats, Usage, RECEIPT_RETENTION_SECS,
+};
+use crate::protocol::{Receipt, ReceiptStatus};
+use crate::{MessageEnvelope, MessageId};
+
+/// Default number of pooled SQLite connections.
+pub const DEFAULT_POOL_SIZE: u32 = 15;
+
+/// Free pages released per [`MessageStore::incremental_vacuum`] call.
+const VACUUM_PAGES: u32 = 1024;
+
+/// [`MessageStore`] backed by a SQLite database.
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: Pool<SqliteConnectionManager>,
+    /// Running total of stored payload bytes, loaded at open and adjusted
+    /// after each committed change to `messages`.
+    payload_bytes: Arc<AtomicU64>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and migrates its schema.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_pool_size(path, DEFAULT_POOL_SIZE)
+    }
+
+    /// Like [`SqliteStore::open`] with `pool_size` connections. The database
+    /// is switched to WAL mode, so [`MessageStore::checkpoint`] has a log to
+    /// fold back.
+    pub fn open_with_pool_size(path: impl AsRef<Path>, pool_size: u32) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path)
+            .with_init(|conn| conn.pragma_update(None, "journal_mode", "WAL"));
+        Self::from_pool(Pool::builder().max_size(pool_size).build(manager)?)
+    }
+
+    /// Private in-memory database. Every in-memory connection is its own
+    /// database, so the pool holds exactly one.
+    pub fn in_memory() -> Result<Self> {
+        let manager = SqliteConnectionManager::memory();
+        Self::from_pool(Pool::builder().max_size(1).build(manager)?)
+    }
+
+    fn from_pool(pool: Pool<SqliteConnectionManager>) -> Result<Self> {
+        let conn = pool.get()?;
+        init_schema(&conn)?;
+        let payload_bytes: u64 =
+            conn.query_row("SELECT COALESCE(SUM(LENGTH(payload)), 0) FROM messages", [], |row| row.get(0))?;
+        drop(conn);
+        Ok(Self {
+            pool,
+            payload_bytes: Arc::new(AtomicU64::new(payload_bytes)),
+        })
+    }
+
+    fn stored(&self, bytes: u64) {
+        self.payload_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    fn deleted(&self, bytes: u64) {
+        self.payload_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    }
+}
+
+/// Creates the `messages`, `accepted` and `receipts` tables and migrates
+/// databases created before signatures, delivery leases, expiry times and
+/// acceptances were persisted. Messages stored before receipts were have no
+/// receipt log. Messages stored before message ids became 16 bytes no longer
+/// decode; they are moved to `messages_v1` for manual inspection.
+fn init_schema(conn: &rusqlite::Connection) -> Result<()> {
+    // Only takes effect on a new database; older ones keep full-vacuum mode
+    // and incremental vacuums do nothing.
+    conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (
+        msg_id      BLOB PRIMARY KEY,
+        sender      TEXT NOT NULL,
+        sender_key  BLOB NOT NULL,
+        recipient   BLOB NOT NULL,
+        timestamp   INTEGER NOT NULL,
+        ttl         INTEGER NOT NULL,
+        payload     BLOB NOT NULL,
+        signature   BLOB,
+        lease_until INTEGER NOT NULL DEFAULT 0,
+        expires_at  INTEGER NOT NULL DEFAULT 0
+    )",
+        (),
+    )?;
+
+    add_column_if_missing(conn, "signature", "BLOB")?;
+    add_column_if_missing(conn, "lease_until", "INTEGER NOT NULL DEFAULT 0")?;
+    if add_column_if_missing(conn, "expires_at", "INTEGER NOT NULL DEFAULT 0")? {
+        conn.execute("UPDATE messages SET expires_at = timestamp + ttl", ())?;
+    }
+    move_v1_messages(conn)?;
+
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_recipient ON messages(recipient);
+         CREATE INDEX IF NOT EXISTS idx_sender_key ON messages(sender_key);
+         CREATE INDEX IF NOT EXISTS idx_expires_at ON messages(expires_at);",
+    )?;
+
+    let has_accepted = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'accepted'")?
+        .exists([])?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS accepted (
+            msg_id     BLOB PRIMARY KEY,
+            digest     BLOB NOT NULL,
+            expires_at INTEGER NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS idx_accepted_expires_at ON accepted(expires_at);",
+    )?;
+    if !has_accepted {
+        backfill_accepted(conn)?;
+    }
+
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS receipts (
+            msg_id     BLOB NOT NULL,
+            status     INTEGER NOT NULL,
+            at         INTEGER NOT NULL,
+            proof      BLOB NOT NULL,
+            sender_key BLOB NOT NULL,
+            recipient  BLOB NOT NULL,
+            forget_at  INTEGER NOT NULL,
+            PRIMARY KEY (msg_id, status)
+         );
+         CREATE INDEX IF NOT EXISTS idx_receipts_forget_at ON receipts(forget_at);",
+    )?;
+    Ok(())
+}
+
+/// Moves messages whose ids are not 16 bytes long into `messages_v1`.
+fn move_v1_messages(conn: &rusqlite::Connection) -> Result<()> {
+    const COLUMNS: &str =
+        "msg_id, sender, sender_key, recipient, timestamp, ttl, payload, signature, lease_until, expires_at";
+    let v1: bool = conn.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM messages WHERE LENGTH(msg_id) != {})", MessageId::LEN),
+        [],
+        |row| row.get(0),
+    )?;
+    if !v1 {
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS messages_v1 (
+        msg_id      BLOB PRIMARY KEY,
+        sender      TEXT NOT NULL,
+        sender_key  BLOB NOT NULL,
+        recipient   BLOB NOT NULL,
+        timestamp   INTEGER NOT NULL,
+        ttl         INTEGER NOT NULL,
+        payload     BLOB NOT NULL,
+        signature   BLOB,
+        lease_until INTEGER NOT NULL DEFAULT 0,
+        expires_at  INTEGER NOT NULL DEFAULT 0
+    )",
+        (),
+    )?;
+    let moved = tx.execute(
+        &format!(
+            "INSERT OR REPLACE INTO messages_v1 ({0})
+             SELECT {0} FROM messages WHERE LENGTH(msg_id) != {1}",
+            COLUMNS,
+            MessageId::LEN
+        ),
+        (),
+    )?;
+    tx.execute(&format!("DELETE FROM messages WHERE LENGTH(msg_id) != {}", MessageId::LEN), ())?;
+    tx.commit()?;
+    warn!(moved, "messages with v1 ids moved to messages_v1");
+    Ok(())
+}
+
+/// Records an acceptance for every message stored before acceptances were.
+fn backfill_accepted(conn: &rusqlite::Connection) -> Result<()> {
+    let mut select = conn.prepare(&format!("SELECT {} FROM messages", ENVELOPE_COLUMNS))?;
+    let mut insert = conn.prepare(
+        "INSERT OR IGNORE INTO accepted (msg_id, digest, expires_at) VALUES (?1, ?2, ?3)",
+    )?;
+    for envelope in select.query_map([], envelope_from_row)? {
+        let envelope = envelope?;
+        insert.execute((
+            &envelope.msg_id,
+            &envelope.digest(),
+            envelope.timestamp + envelope.ttl as u64,
+        ))?;
+    }
+    Ok(())
+}
+
+/// Stores `envelope` and records its acceptance within `tx`. Returns
+/// `false` if `msg_id` was already accepted.
+fn insert_message(tx: &rusqlite::Transaction<'_>, envelope: &MessageEnvelope) -> Result<bool> {
+    let accepted = tx.execute(
+        "INSERT OR IGNORE INTO accepted (msg_id, digest, expires_at) VALUES (?1, ?2, ?3 + ?4)",
+        (
+            &envelope.msg_id,
+            &envelope.digest(),
+            &envelope.timestamp,
+            &envelope.ttl,
+        ),
+    )?;
+    if accepted == 0 {
+        return Ok(false);
+    }
+    let inserted = tx.execute(
+        "INSERT OR IGNORE INTO messages (msg_id,sender,sender_key,recipient,timestamp,ttl,payload,signature,expires_at)
+         VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?5 + ?6)",
+        (
+            &envelope.msg_id,
+            &envelope.sender,
+            &envelope.sender_key,
+            &envelope.recipient,
+            &envelope.timestamp,
+            &envelope.ttl,
+            &envelope.payload,
+            &envelope.signature[..],
+        ),
+    )?;
+    Ok(inserted == 1)
+}
+
+/// Adds `receipt` to an existing receipt log, copying the log's keys and
+/// retention. Returns `false` if there is no log or the status is recorded.
+fn append_receipt(conn: &rusqlite::Connection, receipt: &Receipt) -> Result<bool> {
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO receipts
+            (msg_id, status, at, proof, sender_key, recipient, forget_at)
+         SELECT msg_id, ?2, ?3, ?4, sender_key, recipient, forget_at
+         FROM receipts WHERE msg_id = ?1 LIMIT 1",
+        (
+            &receipt.msg_id,
+            receipt.status.as_u8(),
+            receipt.at,
+            &receipt.proof,
+        ),
+    )?;
+    Ok(inserted == 1)
+}
+
+/// Columns read by [`envelope_from_row`], in order.
+const ENVELOPE_COLUMNS: &str =
+    "msg_id, sender, sender_key, recipient, timestamp, ttl, payload, signature";
+
+fn envelope_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<MessageEnvelope> {
+    Ok(MessageEnvelope {
+        msg_id: row.get(0)?,
+        sender: row.get(1)?,
+        sender_key: row.get(2)?,
+        recipient: row.get(3)?,
+        timestamp: row.get(4)?,
+        ttl: row.get(5)?,
+        payload: row.get(6)?,
+        // Rows stored before signatures were persisted have none.
+        signature: row
+            .get::<_, Option<Vec<u8>>>(7)?
+            .and_then(|sig| sig.try_into().ok())
+            .unwrap_or([0u8; SIGNATURE_LENGTH]),
+    })
+}
+
+/// Returns whether the column had to be added.
+fn add_column_if_missing(conn: &rusqlite::Connection, column: &str, definition: &str) -> Result<bool> {
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = ?1")?
+        .exists([column])?;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE messages ADD COLUMN {} {}", column, definition), ())?;
+    }
+    Ok(!exists)
+}
+
+impl SqliteStore {
+    /// Count and payload size of the rows where `column` equals `key`.
+    fn usage(&self, column: &str, key: &[u8]) -> Result<Usage> {
+        let conn = self.pool.get()?;
+        Ok(conn.query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(payload)), 0) FROM messages WHERE {} = ?1",
+                column
+            ),
+            [key],
+            |row| {
+                Ok(Usage {
+                    messages: row.get(0)?,
+                    payload_bytes: row.get(1)?,
+                })
+            },
+        )?)
+    }
+}
+
+impl MessageStore for SqliteStore {
+    fn insert(&self, envelope: &MessageEnvelope) -> Result<bool> {
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction()?;
+        let inserted = insert_message(&tx, envelope)?;
+        tx.commit()?;
+        if inserted {
+            self.stored(envelope.payload.len() as u64);
+        }
+        Ok(inserted)
+    }
+
+    fn insert_with_receipts(&self, envelope: &MessageEnvelope, now: u64) -> Result<bool> {
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction()?;
+        if !insert_message(&tx, envelope)? {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO receipts
+                (msg_id, status, at, proof, sender_key, recipient, forget_at)
+             VALUES (?1, ?2, ?3, x'', ?4, ?5, ?6)",
+            (
+                &envelope.msg_id,
+                ReceiptStatus::Stored.as_u8(),
+                now,
+                &envelope.sender_key,
+                &envelope.recipient,
+                envelope.timestamp + envelope.ttl as u64 + RECEIPT_RETENTION_SECS,
+            ),
+        )?;
+        tx.commit()?;
+        self.stored(envelope.payload.len() as u64);
+        Ok(true)
+    }
+
+    fn contains(&self, msg_id: &MessageId) -> Result<bool> {
+        let conn = self.pool.get()?;
+        let exists = conn
+            .prepare_cached("SELECT 1 FROM messages WHERE msg_id = ?1")?
+            .exists([msg_id])?;
+        Ok(exists)
+    }
+
+    fn accepted(&self, msg_id: &MessageId) -> Result<Option<[u8; 32]>> {
+        let conn = self.pool.get()?;
+        let digest = conn
+            .prepare_cached("SELECT digest FROM accepted WHERE msg_id = ?1")?
+            .query_row([msg_id], |row| row.get(0))
+            .optional()?;
+        Ok(digest)
+    }
+
+    fn fetch_for_recipient(
+        &self,
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        now: u64,
+        lease: Option<u64>,
+    ) -> Result<Vec<MessageEnvelope>> {
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction()?;
+
+        let msgs: Vec<MessageEnvelope> = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT {} FROM messages
+                 WHERE recipient = ?1 AND lease_until <= ?2 AND expires_at >= ?2
+                 ORDER BY rowid",
+                ENVELOPE_COLUMNS
+            ))?;
+
+            let rows = stmt.query_map((&recipient[..], now), envelope_from_row)?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        for msg in &msgs {
+            match lease {
+                Some(secs) => tx.execute(
+                    "UPDATE messages SET lease_until = ?1 WHERE msg_id = ?2",
+                    (now + secs, &msg.msg_id),
+                )?,
+                None => tx.execute("DELETE FROM messages WHERE msg_id = ?1", [&msg.msg_id])?,
+            };
+        }
+        tx.commit()?;
+        if lease.is_none() {
+            self.deleted(msgs.iter().map(|msg| msg.payload.len() as u64).sum());
+        }
+
+        Ok(msgs)
+    }
+
+    fn ack(
+        &self,
+        recipient: &[u8; PUBLIC_KEY_LENGTH],
+        msg_ids: &[MessageId],
+    ) -> Result<Vec<MessageId>> {
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction()?;
+        let mut deleted = Vec::new();
+        let mut bytes = 0;
+        for msg_id in msg_ids {
+            let removed: Option<u64> = tx
+                .query_row(
+                    "DELETE FROM messages WHERE msg_id = ?1 AND recipient = ?2
+                     RETURNING LENGTH(payload)",
+                    (msg_id, &recipient[..]),
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(size) = removed {
+                deleted.push(*msg_id);
+                bytes += size;
+            }
+        }
+        tx.commit()?;
+        self.deleted(bytes);
+        Ok(deleted)
+    }
+
+    fn expire_batch(&self, now: u64, max: usize) -> Result<ExpiredBatch> {
+        let mut conn = self.pool.get()?;
+        let max = i64::try_from(max).unwrap_or(i64::MAX);
+        let tx = conn.transaction()?;
+        let expired: Vec<(MessageId, [u8; PUBLIC_KEY_LENGTH], u64)> = tx
+            .prepare(
+                "DELETE FROM messages WHERE rowid IN
+                    (SELECT rowid FROM messages WHERE expires_at < ?1 LIMIT ?2)
+                 RETURNING msg_id, sender_key, LENGTH(payload)",
+            )?
+            .query_map((now, max), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        let bytes: u64 = expired.iter().map(|(_, _, size)| size).sum();
+        let mut events = Vec::with_capacity(expired.len());
+        for (msg_id, sender_key, _) in expired {
+            let receipt = Receipt::new(msg_id, ReceiptStatus::Expired, now);
+            append_receipt(&tx, &receipt)?;
+            events.push(ReceiptEvent { sender_key, receipt });
+        }
+        let acceptances = tx.execute(
+            "DELETE FROM accepted WHERE rowid IN
+                (SELECT rowid FROM accepted WHERE expires_at < ?1 LIMIT ?2)",
+            (now, max),
+        )?;
+        let receipts = tx.execute(
+            "DELETE FROM receipts WHERE rowid IN
+                (SELECT rowid FROM receipts WHERE forget_at < ?1 LIMIT ?2)",
+            (now, max),
+        )?;
+        tx.commit()?;
+        self.deleted(bytes);
+        Ok(ExpiredBatch {
+            expired: events,
+            acceptances,
+            receipts,
+        })
+    }
+
+    fn record_receipt(&self, receipt: &Receipt) -> Result<Option<ReceiptEvent>> {
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction()?;
+        let sender_key: Option<[u8; PUBLIC_KEY_LENGTH]> = tx
+            .query_row(
+                "SELECT sender_key FROM receipts WHERE msg_id = ?1 LIMIT 1",
+                [&receipt.msg_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let recorded = append_receipt(&tx, receipt)?;
+        tx.commit()?;
+        Ok(sender_key.filter(|_| recorded).map(|sender_key| ReceiptEvent {
+            sender_key,
+            receipt: receipt.clone(),
+        }))
+    }
+
+    fn receipts(&self, msg_id: &MessageId) -> Result<Option<ReceiptLog>> {
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare_cached(
+            "SELECT status, at, proof, sender_key, recipient FROM receipts
+             WHERE msg_id = ?1 ORDER BY rowid",
+        )?;
+        let mut log: Option<ReceiptLog> = None;
+        let mut rows = stmt.query([msg_id])?;
+        while let Some(row) = rows.next()? {
+            let log = log.get_or_insert(ReceiptLog {
+                sender_key: row.get(3)?,
+                recipient: row.get(4)?,
+                receipts: Vec::new(),
+            });
+            let status = row.get::<_, u8>(0)?;
+            // Statuses from a newer release are left out.
+            let Some(status) = ReceiptStatus::from_u8(status) else {
+                continue;
+            };
+            log.receipts.push(Receipt {
+                msg_id: *msg_id,
+                status,
+                at: row.get(1)?,
+                proof: row.get(2)?,
+            });
+        }
+        Ok(log)
+    }
+
+    fn incremental_vacuum(&self) -> Result<()> {
+        let conn = self.pool.get()?;
+        conn.execute_batch(&format!("PRAGMA incremental_vacuum({})", VACUUM_PAGES))?;
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> Result<()> {
+        let conn = self.pool.get()?;
+        // Reports (busy, log pages, checkpointed pages); a no-op for the
+        // in-memory database, which has no log.
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn count(&self) -> Result<u64> {
+        let conn = self.pool.get()?;
+        Ok(conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?)
+    }
+
+    fn stats(&self, now: u64) -> Result<StoreStats> {
+        let conn = self.pool.get()?;
+        Ok(conn.query_row(
+            "SELECT COUNT(*),
+                    COALESCE(SUM(lease_until > ?1), 0),
+                    COUNT(DISTINCT recipient),
+                    COALESCE(SUM(LENGTH(payload)), 0)
+             FROM messages",
+            [now],
+            |row| {
+                Ok(StoreStats {
+                    messages: row.get(0)?,
+                    leased: row.get(1)?,
+                    recipients: row.get(2)?,
+                    payload_bytes: row.get(3)?,
+                })
+            },
+        )?)
+    }
+
+    fn payload_bytes(&self) -> Result<u64> {
+        Ok(self.payload_bytes.load(Ordering::SeqCst))
+    }
+
+    fn recipient_usage(&self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Usage> {
+        self.usage("recipient", recipient)
+    }
+
+    fn sender_usage(&self, sender_key: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Usage> {
+        self.usage("sender_key", sender_key)
+    }
+
+    fn evict_oldest(&self, recipient: &[u8; PUBLIC_KEY_LENGTH]) -> Result<bool> {
+        let conn = self.pool.get()?;
+        let deleted: Option<u64> = conn
+            .query_row(
+                "DELETE FROM messages WHERE rowid =
+                    (SELECT rowid FROM messages WHERE recipient = ?1 ORDER BY rowid LIMIT 1)
+                 RETURNING LENGTH(payload)",
+                [&recipient[..]],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(size) = deleted {
+            self.deleted(size);
+        }
+        Ok(deleted.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_test_db() -> Pool<SqliteConnectionManager> {
+        SqliteStore::in_memory().unwrap().pool
+    }
+
+    #[test]
+    fn test_db_insert_and_query() {
+        let pool = setup_test_db();
+        let conn = pool.get().unwrap();
+
+        let envelope = MessageEnvelope::new(
+            "test_sender".to_string(),
+            [0u8; 32],
+            [1u8; 32],
+            b"payload".to_vec(),
+            3600,
+        );
+
+        conn.execute(
+            "INSERT INTO messages (msg_id, sender, sender_key, recipient, timestamp, ttl, payload)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                &envelope.msg_id,
+                &envelope.sender,
+                &envelope.sender_key,
+                &envelope.recipient,
+                &envelope.timestamp,
+                &envelope.ttl,
+                &envelope.payload,
+            ),
+        ).unwrap();
+
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_db_signature_round_trip() {
+        let store = SqliteStore::in_memory().unwrap();
+
+        let (recipient, _) = crate::gen_keypair();
+        let (sender_pub, sender_priv) = crate::gen_keypair();
+        let mut envelope = MessageEnvelope::new(
+            "test_sender".to_string(),
+            recipient,
+            sender_pub,
+            b"payload".to_vec(),
+            3600,
+        );
+        envelope.sign(&sender_priv);
+
+        store.insert(&envelope).unwrap();
+
+        let conn = store.pool.get().unwrap();
+        let stored: Vec<u8> = conn
+            .query_row("SELECT signature FROM messages WHERE msg_id = ?1", [&envelope.msg_id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, envelope.signature.to_vec());
+    }
+
+    #[test]
+    fn test_file_database_uses_wal() {
+        let path = std::env::temp_dir().join(format!("qight-wal-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open_with_pool_size(&path, 2).unwrap();
+        let envelope = MessageEnvelope::new("s".to_string(), [0u8; 32], [1u8; 32], b"p".to_vec(), 3600);
+        store.insert(&envelope).unwrap();
+
+        let conn = store.pool.get().unwrap();
+        let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(mode, "wal");
+        drop(conn);
+
+        let wal = path.with_extension("db-wal");
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+        store.checkpoint().unwrap();
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+        drop(store);
+        let reopened = SqliteStore::open_with_pool_size(&path, 2).unwrap();
+        assert_eq!(reopened.payload_bytes().unwrap(), 1);
+        drop(reopened);
+        for file in [path.clone(), wal, path.with_extension("db-shm")] {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn test_schema_migrates_legacy_columns() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE messages (
+                msg_id BLOB PRIMARY KEY,
+                sender TEXT NOT NULL,
+                sender_key BLOB NOT NULL,
+                recipient BLOB NOT NULL,
+                timestamp INTEGER NOT NULL,
+                ttl INTEGER NOT NULL,
+                payload BLOB NOT NULL
+            )",
+            (),
+        ).unwrap();
+
+        conn.execute(
+            "INSERT INTO messages VALUES (x'01', 's', x'02', x'03', 100, 20, x'')",
+            (),
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO messages VALUES (zeroblob(16), 's', zeroblob(32), zeroblob(32), 100, 30, x'')",
+            (),
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO messages VALUES (zeroblob(32), 's', zeroblob(32), zeroblob(32), 100, 40, x'')",
+            (),
+        ).unwrap();
+
+        init_schema(&conn).unwrap();
+        init_schema(&conn).unwrap();
+
+        // Rows with ids other than 16 bytes are moved aside, migrated.
+        let expires_at: u64 = conn
+            .query_row("SELECT expires_at FROM messages_v1 WHERE msg_id = x'01'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(expires_at, 120);
+        let (stored, v1): (u64, u64) = conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM messages), (SELECT COUNT(*) FROM messages_v1)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((stored, v1), (1, 2));
+
+        let accepted: Vec<(Vec<u8>, u64)> = conn
+            .prepare("SELECT digest, expires_at FROM accepted")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(accepted.len(), 1);
+        assert_eq!((accepted[0].0.len(), accepted[0].1), (32, 130));
+
+        let has_signature = conn
+            .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'signature'")
+            .unwrap()
+            .exists([])
+            .unwrap();
+        assert!(has_signature);
+    }
+}
@@ -0,0 +1,86 @@
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{warn, Instrument};
+
+use crate::protocol::{write_frame, Frame};
+use crate::relay::handlers::RelayContext;
+use crate::relay::store::ReceiptEvent;
+
+/// Live connections that asked for pushes, keyed by the public key they
+/// proved: recipients for messages, senders for receipts.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    by_key: Mutex<HashMap<[u8; PUBLIC_KEY_LENGTH], Vec<quinn::Connection>>>,
+}
+
+impl Subscriptions {
+    /// Registers `connection` for `key`. Subscribing twice on the same
+    /// connection is a no-op.
+    pub fn subscribe(&self, key: [u8; PUBLIC_KEY_LENGTH], connection: quinn::Connection) {
+        let mut by_key = self.by_key.lock().unwrap();
+        let connections = by_key.entry(key).or_default();
+        if !connections
+            .iter()
+            .any(|c| c.stable_id() == connection.stable_id())
+        {
+            connections.push(connection);
+        }
+    }
+
+    /// Drops every subscription held by the connection with `stable_id`.
+    pub fn remove_connection(&self, stable_id: usize) {
+        let mut by_key = self.by_key.lock().unwrap();
+        by_key.retain(|_, connections| {
+            connections.retain(|c| c.stable_id() != stable_id);
+            !connections.is_empty()
+        });
+    }
+
+    /// Open connections subscribed to `key`.
+    pub fn connections_for(&self, key: &[u8; PUBLIC_KEY_LENGTH]) -> Vec<quinn::Connection> {
+        let mut by_key = self.by_key.lock().unwrap();
+        let Some(connections) = by_key.get_mut(key) else {
+            return Vec::new();
+        };
+        connections.retain(|c| c.close_reason().is_none());
+        let live = connections.clone();
+        if connections.is_empty() {
+            by_key.remove(key);
+        }
+        live
+    }
+}
+
+/// Pushes one frame to a subscriber on a fresh server-opened
+/// unidirectional stream.
+pub(crate) async fn push(connection: &quinn::Connection, frame: Frame) -> anyhow::Result<()> {
+    let mut stream = connection.open_uni().await?;
+    write_frame(&mut stream, &frame).await?;
+    stream.finish()?;
+    Ok(())
+}
+
+/// Pushes each receipt to the connections watching its sender key. Pushes
+/// run in the background and count as in flight; a failed one is only
+/// logged, the receipt stays queryable.
+pub(crate) fn publish_receipts(context: &RelayContext, events: Vec<ReceiptEvent>) {
+    for event in events {
+        for watcher in context.receipt_watchers.connections_for(&event.sender_key) {
+            let frame = Frame::Receipt {
+                sender_key: event.sender_key,
+                receipt: event.receipt.clone(),
+            };
+            let in_flight = context.in_flight.enter();
+            tokio::spawn(
+                async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = push(&watcher, frame).await {
+                        warn!(watcher = %watcher.remote_address(), error = %e, "receipt push failed");
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+    }
+}
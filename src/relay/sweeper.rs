@@ -0,0 +1,227 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::relay::handlers::RelayContext;
+use crate::relay::store::{MessageStore, ReceiptEvent};
+use crate::relay::subscriptions;
+
+/// Background purge of expired messages.
+///
+/// Loadable as the `[sweeper]` section of [`crate::relay::RelayConfig`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweeperConfig {
+    pub enabled: bool,
+    /// Seconds between sweeps.
+    pub interval_secs: u64,
+    /// Messages deleted per transaction, so a large purge never holds the
+    /// database for long.
+    pub batch_size: usize,
+    /// Return freed pages to the file system after each sweep.
+    pub incremental_vacuum: bool,
+    /// Fold the write-ahead log back into the database after each sweep.
+    pub wal_checkpoint: bool,
+}
+
+impl Default for SweeperConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 60,
+            batch_size: 1000,
+            incremental_vacuum: false,
+            wal_checkpoint: false,
+        }
+    }
+}
+
+/// Sweeper counters since the relay started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SweepMetrics {
+    /// Completed sweeps.
+    pub sweeps: u64,
+    /// Sweeps that hit a storage error.
+    pub failures: u64,
+    /// Messages deleted in total.
+    pub expired: u64,
+    /// Messages deleted by the latest sweep.
+    pub last_expired: u64,
+    /// How long the latest sweep took, compaction included.
+    pub last_duration: Duration,
+}
+
+/// Sweeper settings and counters shared with the relay.
+#[derive(Default)]
+pub(crate) struct Sweeper {
+    config: SweeperConfig,
+    sweeps: AtomicU64,
+    failures: AtomicU64,
+    expired: AtomicU64,
+    last_expired: AtomicU64,
+    last_duration_us: AtomicU64,
+}
+
+impl Sweeper {
+    pub fn new(config: SweeperConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn metrics(&self) -> SweepMetrics {
+        SweepMetrics {
+            sweeps: self.sweeps.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            last_expired: self.last_expired.load(Ordering::Relaxed),
+            last_duration: Duration::from_micros(self.last_duration_us.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Deletes everything expired before `now` batch by batch, then compacts
+    /// as configured. Returns the expired receipts of the deleted messages.
+    pub fn sweep(&self, store: &dyn MessageStore, now: u64) -> Result<Vec<ReceiptEvent>> {
+        let started = Instant::now();
+        let result = self.purge(store, now);
+        self.last_duration_us
+            .store(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        match &result {
+            Ok(expired) => {
+                self.sweeps.fetch_add(1, Ordering::Relaxed);
+                self.expired.fetch_add(expired.len() as u64, Ordering::Relaxed);
+                self.last_expired.store(expired.len() as u64, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    fn purge(&self, store: &dyn MessageStore, now: u64) -> Result<Vec<ReceiptEvent>> {
+        let batch_size = self.config.batch_size.max(1);
+        let mut expired = Vec::new();
+        loop {
+            let batch = store.expire_batch(now, batch_size)?;
+            let done = batch.is_last(batch_size);
+            expired.extend(batch.expired);
+            if done {
+                break;
+            }
+        }
+        if self.config.incremental_vacuum {
+            store.incremental_vacuum()?;
+        }
+        if self.config.wal_checkpoint {
+            store.checkpoint()?;
+        }
+        Ok(expired)
+    }
+}
+
+/// Sweeps the relay's store every configured interval until `shutdown`
+/// flips, pushing expired receipts to their senders' watchers.
+pub(crate) async fn run(context: Arc<RelayContext>, mut shutdown: watch::Receiver<bool>) {
+    let sweeper = context.sweeper.clone();
+    if !sweeper.config.enabled {
+        return;
+    }
+    let period = Duration::from_secs(sweeper.config.interval_secs.max(1));
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => return,
+        }
+
+        let (sweeper, store) = (sweeper.clone(), context.store.clone());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        match tokio::task::spawn_blocking(move || sweeper.sweep(store.as_ref(), now)).await {
+            Ok(Ok(expired)) if expired.is_empty() => {}
+            Ok(Ok(expired)) => {
+                info!(expired = expired.len(), "swept expired messages");
+                subscriptions::publish_receipts(&context, expired);
+            }
+            Ok(Err(e)) => error!(error = %e, "expiry sweep failed"),
+            Err(e) => error!(error = %e, "expiry sweep panicked"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::store::{MemoryStore, SqliteStore, RECEIPT_RETENTION_SECS};
+    use crate::MessageEnvelope;
+
+    #[test]
+    fn test_sweep_purges_in_batches_and_counts() {
+        let stores: Vec<Box<dyn MessageStore>> = vec![
+            Box::new(MemoryStore::new()),
+            Box::new(SqliteStore::in_memory().unwrap()),
+        ];
+        for store in stores {
+            let sweeper = Sweeper::new(SweeperConfig {
+                batch_size: 2,
+                incremental_vacuum: true,
+                wal_checkpoint: true,
+                ..SweeperConfig::default()
+            });
+            let mut now = 0;
+            for ttl in [10, 10, 10, 10, 10, 3600] {
+                let msg = MessageEnvelope::new("s".into(), [7; 32], [1; 32], b"x".to_vec(), ttl);
+                now = msg.timestamp + 11;
+                store.insert(&msg).unwrap();
+            }
+
+            assert_eq!(sweeper.sweep(store.as_ref(), now).unwrap().len(), 5);
+            assert!(sweeper.sweep(store.as_ref(), now).unwrap().is_empty());
+            assert_eq!(store.count().unwrap(), 1);
+
+            let metrics = sweeper.metrics();
+            assert_eq!((metrics.sweeps, metrics.failures), (2, 0));
+            assert_eq!((metrics.expired, metrics.last_expired), (5, 0));
+        }
+    }
+
+    #[test]
+    fn test_sweep_forgets_acceptances_of_delivered_messages_past_the_batch() {
+        let stores: Vec<Box<dyn MessageStore>> = vec![
+            Box::new(MemoryStore::new()),
+            Box::new(SqliteStore::in_memory().unwrap()),
+        ];
+        for store in stores {
+            let sweeper = Sweeper::new(SweeperConfig {
+                batch_size: 2,
+                ..SweeperConfig::default()
+            });
+            let delivered: Vec<_> = (0..5)
+                .map(|_| MessageEnvelope::new("s".into(), [7; 32], [1; 32], b"x".to_vec(), 10))
+                .collect();
+            for msg in &delivered {
+                store.insert_with_receipts(msg, msg.timestamp).unwrap();
+            }
+            let now = delivered[0].timestamp;
+            assert_eq!(store.fetch_for_recipient(&[7; 32], now, None).unwrap().len(), 5);
+
+            // Nothing left to expire, but every acceptance and receipt log
+            // is past its retention.
+            let later = now + 11 + RECEIPT_RETENTION_SECS;
+            assert!(sweeper.sweep(store.as_ref(), later).unwrap().is_empty());
+            for msg in &delivered {
+                assert_eq!(store.accepted(&msg.msg_id).unwrap(), None);
+                assert_eq!(store.receipts(&msg.msg_id).unwrap(), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use serde::Deserialize;
+
+use crate::errors::QightError;
+use crate::{MessageEnvelope, ENVELOPE_OVERHEAD};
+
+/// What the relay does with a SEND repeating an envelope it already
+/// accepted, until that envelope expires. A different envelope reusing the
+/// `msg_id` is always answered `MESSAGE_CONFLICT`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    /// Answer OK without storing it again, so client retries are harmless.
+    #[default]
+    Accept,
+    /// Answer `DUPLICATE_MESSAGE`. Clients can no longer tell a retry whose
+    /// first attempt succeeded from a failure.
+    Reject,
+}
+
+/// Checks a relay applies to every envelope before storing it.
+///
+/// Loadable as the `[validation]` section of [`crate::relay::RelayConfig`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationPolicy {
+    /// Longest TTL accepted, in seconds. Advertised in WELCOME.
+    pub max_ttl_secs: u32,
+    /// Shortest TTL accepted, in seconds.
+    pub min_ttl_secs: u32,
+    /// How far an envelope timestamp may lie in the future, in seconds.
+    /// Bounds how far a sender can stretch retention past its TTL.
+    pub max_clock_skew_secs: u64,
+    /// Longest `sender` string, in bytes.
+    pub max_sender_len: usize,
+    /// Largest `payload`, in bytes.
+    pub max_payload_len: usize,
+    pub duplicates: DuplicatePolicy,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        Self {
+            max_ttl_secs: 7 * 24 * 60 * 60,
+            min_ttl_secs: 1,
+            max_clock_skew_secs: 5 * 60,
+            max_sender_len: 256,
+            max_payload_len: 10_000_000,
+            duplicates: DuplicatePolicy::Accept,
+        }
+    }
+}
+
+impl ValidationPolicy {
+    /// Size of the largest envelope the policy admits once encoded, which
+    /// the relay's frame limit must fit.
+    pub fn max_envelope_len(&self) -> usize {
+        ENVELOPE_OVERHEAD
+            .saturating_add(self.max_sender_len)
+            .saturating_add(self.max_payload_len)
+    }
+
+    /// Checks `envelope` at `now` (Unix seconds), reporting the first
+    /// violation. Cheap field checks run before the signature check.
+    pub fn check(&self, envelope: &MessageEnvelope, now: u64) -> Result<(), QightError> {
+        if envelope.sender.len() > self.max_sender_len {
+            return Err(QightError::FieldTooLong);
+        }
+        if envelope.sender.chars().any(char::is_control) {
+            return Err(QightError::InvalidEnvelope);
+        }
+        if envelope.payload.len() > self.max_payload_len {
+            return Err(QightError::PayloadTooLarge);
+        }
+        if envelope.ttl > self.max_ttl_secs || envelope.ttl < self.min_ttl_secs {
+            return Err(QightError::TtlOutOfRange);
+        }
+        if envelope.timestamp > now.saturating_add(self.max_clock_skew_secs) {
+            return Err(QightError::InvalidTimestamp);
+        }
+        if envelope.is_expired(now) {
+            return Err(QightError::Expired);
+        }
+        if !envelope.verify() {
+            return Err(QightError::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_keypair;
+
+    fn signed(edit: impl FnOnce(&mut MessageEnvelope)) -> MessageEnvelope {
+        let (recipient, _) = gen_keypair();
+        let (sender_key, sender_secret) = gen_keypair();
+        let mut envelope =
+            MessageEnvelope::new("alice".to_string(), recipient, sender_key, b"hi".to_vec(), 60);
+        edit(&mut envelope);
+        envelope.sign(&sender_secret);
+        envelope
+    }
+
+    #[test]
+    fn test_policy_rejects_each_field() {
+        let policy = ValidationPolicy {
+            max_ttl_secs: 3600,
+            max_sender_len: 8,
+            max_payload_len: 4,
+            ..ValidationPolicy::default()
+        };
+        let now = signed(|_| {}).timestamp;
+        let check = |envelope: MessageEnvelope| policy.check(&envelope, now);
+
+        assert_eq!(check(signed(|_| {})), Ok(()));
+        assert_eq!(
+            check(signed(|e| e.sender = "a".repeat(9))),
+            Err(QightError::FieldTooLong)
+        );
+        assert_eq!(
+            check(signed(|e| e.sender = "al\nice".to_string())),
+            Err(QightError::InvalidEnvelope)
+        );
+        assert_eq!(
+            check(signed(|e| e.payload = vec![0; 5])),
+            Err(QightError::PayloadTooLarge)
+        );
+        assert_eq!(check(signed(|e| e.ttl = 3601)), Err(QightError::TtlOutOfRange));
+        assert_eq!(check(signed(|e| e.ttl = 0)), Err(QightError::TtlOutOfRange));
+        assert_eq!(
+            check(signed(|e| e.timestamp = now + 301)),
+            Err(QightError::InvalidTimestamp)
+        );
+        assert_eq!(check(signed(|e| e.timestamp = now + 300)), Ok(()));
+        assert_eq!(check(signed(|e| e.timestamp = now - 61)), Err(QightError::Expired));
+
+        let mut tampered = signed(|_| {});
+        tampered.payload = b"yo".to_vec();
+        assert_eq!(check(tampered), Err(QightError::InvalidSignature));
+    }
+}
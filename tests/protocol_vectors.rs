@@ -0,0 +1,159 @@
+//! Golden vectors for the wire protocol described in `docs/PROTOCOL.md`.
+//!
+//! `tests/vectors/frames.txt` holds one `name = hex` pair per line. Set
+//! `QIGHT_BLESS_VECTORS=1` to rewrite it after an intentional format change.
+
+use qight::protocol::{
+    Capabilities, ErrorCode, Features, Frame, Receipt, ReceiptStatus, RelayStats,
+};
+use qight::{public_key_from_secret, MessageEnvelope, MessageId};
+
+const VECTORS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/frames.txt");
+
+fn sample_envelope() -> MessageEnvelope {
+    let sender_secret = [0x01u8; 32];
+    let recipient_secret = [0x02u8; 32];
+    let mut envelope = MessageEnvelope {
+        msg_id: MessageId::from_bytes([0x11; 16]),
+        sender: "alice".to_string(),
+        sender_key: public_key_from_secret(&sender_secret),
+        recipient: public_key_from_secret(&recipient_secret),
+        timestamp: 1_700_000_000,
+        ttl: 3600,
+        payload: b"hello".to_vec(),
+        signature: [0; 64],
+    };
+    envelope.sign(&sender_secret);
+    envelope
+}
+
+fn vectors() -> Vec<(&'static str, Vec<u8>, Option<Frame>)> {
+    let envelope = sample_envelope();
+    let envelope_bytes = envelope.to_bytes().unwrap();
+    let frames = [
+        (
+            "hello",
+            Frame::Hello {
+                client_id: "alice".to_string(),
+                min_version: 2,
+                max_version: 2,
+                features: Features::supported(),
+            },
+        ),
+        ("send", Frame::Send { envelope: envelope_bytes.clone() }),
+        ("fetch", Frame::Fetch { recipient: [0x22; 32], lease_secs: 30 }),
+        ("fetch_unleased", Frame::Fetch { recipient: [0x22; 32], lease_secs: 0 }),
+        (
+            "ack",
+            Frame::Ack {
+                recipient: [0x22; 32],
+                msg_ids: vec![MessageId::from_bytes([0x11; 16]), MessageId::from_bytes([0x33; 16])],
+            },
+        ),
+        ("subscribe", Frame::Subscribe { recipient: [0x22; 32] }),
+        ("stats", Frame::Stats),
+        ("challenge_response", Frame::ChallengeResponse { signature: [0x44; 64] }),
+        ("ok", Frame::Ok),
+        ("error_invalid_signature", Frame::error(ErrorCode::InvalidSignature, "Invalid signature")),
+        ("challenge", Frame::Challenge { nonce: [0x55; 32] }),
+        ("message", Frame::Message { envelope: envelope_bytes.clone() }),
+        ("end", Frame::End),
+        (
+            "welcome",
+            Frame::Welcome {
+                capabilities: Capabilities {
+                    version: 2,
+                    features: Features::PUSH | Features::ACK,
+                    max_payload: 10_000_000,
+                    max_ttl: 604_800,
+                    relay_key: [0x44; 32],
+                },
+                signature: [0x55; 64],
+                message: "Welcome, \"alice\"".to_string(),
+            },
+        ),
+        ("acked", Frame::Acked { count: 2 }),
+        (
+            "stats_reply",
+            Frame::StatsReply {
+                stats: RelayStats {
+                    messages: 3,
+                    leased: 1,
+                    recipients: 2,
+                    payload_bytes: 15,
+                    quota_rejections: 4,
+                    quota_evictions: 5,
+                    expired: 6,
+                    max_total_bytes: Some(1 << 30),
+                    inbox_max_messages: Some(100),
+                    inbox_max_bytes: None,
+                    sender_max_messages: None,
+                    sender_max_bytes: Some(1 << 20),
+                    evict_oldest: true,
+                },
+            },
+        ),
+        ("rate_limited", Frame::RateLimited { retry_after_ms: 1500 }),
+        (
+            "receipts",
+            Frame::Receipts { sender_key: envelope.sender_key, msg_id: envelope.msg_id },
+        ),
+        ("watch", Frame::Watch { sender_key: envelope.sender_key }),
+        (
+            "receipt_stored",
+            Frame::Receipt {
+                sender_key: envelope.sender_key,
+                receipt: Receipt::new(envelope.msg_id, ReceiptStatus::Stored, 1_700_000_001),
+            },
+        ),
+    ];
+
+    let mut vectors = vec![
+        ("envelope_signing_bytes", envelope.signing_bytes(), None),
+        ("envelope", envelope_bytes, None),
+    ];
+    for (name, frame) in frames {
+        vectors.push((name, frame.encode(), Some(frame)));
+    }
+    vectors
+}
+
+fn parse(file: &str) -> Vec<(String, Vec<u8>)> {
+    file.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, hex_bytes) = line.split_once('=').expect("expected `name = hex`");
+            (name.trim().to_string(), hex::decode(hex_bytes.trim()).unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn test_golden_vectors() {
+    let computed = vectors();
+
+    if std::env::var_os("QIGHT_BLESS_VECTORS").is_some() {
+        let mut file = String::from(
+            "# qight wire protocol v2 golden vectors; see docs/PROTOCOL.md and\n\
+             # tests/protocol_vectors.rs for the inputs.\n",
+        );
+        for (name, bytes, _) in &computed {
+            file.push_str(&format!("{} = {}\n", name, hex::encode(bytes)));
+        }
+        std::fs::write(VECTORS_PATH, file).unwrap();
+    }
+
+    let golden = parse(&std::fs::read_to_string(VECTORS_PATH).unwrap());
+    assert_eq!(golden.len(), computed.len());
+    for ((name, bytes), (expected_name, expected, frame)) in golden.iter().zip(&computed) {
+        assert_eq!(name, expected_name);
+        assert_eq!(hex::encode(bytes), hex::encode(expected), "vector `{}` changed", name);
+        if let Some(frame) = frame {
+            assert_eq!(&Frame::decode(bytes).unwrap(), frame, "vector `{}`", name);
+        }
+    }
+
+    let envelope = MessageEnvelope::from_bytes(&golden[1].1).unwrap();
+    assert!(envelope.verify());
+}
@@ -0,0 +1,868 @@
+use qight::relay::{
+    MessageStore, MetricsConfig, OverflowPolicy, QuotaPolicy, RateLimit, RateLimitPolicy,
+    RelayServer, ShutdownHandle, SqliteStore, SweeperConfig,
+};
+use qight::errors::{ErrorKind, QightError};
+use qight::protocol::{Features, ReceiptStatus, PROTOCOL_VERSION};
+use qight::{
+    gen_keypair, ConnectionState, MessageEnvelope, Outbox, OutboxState, RelayClient, SendStatus,
+    TrustRoots,
+};
+use std::net::SocketAddr;
+use futures::StreamExt;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn start_relay() -> (SocketAddr, Vec<u8>, ShutdownHandle) {
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .build()
+        .unwrap();
+    let addr = server.local_addr();
+    let cert = server.certificate().to_vec();
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+    (addr, cert, shutdown)
+}
+
+fn signed_for(recipient: [u8; 32], payload: &[u8]) -> MessageEnvelope {
+    let (sender_pub, sender_priv) = gen_keypair();
+    let mut envelope = MessageEnvelope::new(
+        "alice".to_string(),
+        recipient,
+        sender_pub,
+        payload.to_vec(),
+        3600,
+    );
+    envelope.sign(&sender_priv);
+    envelope
+}
+
+#[tokio::test]
+async fn test_send_and_fetch_through_in_process_relay() {
+    let (addr, cert, shutdown) = start_relay();
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let (sender_pub, sender_priv) = gen_keypair();
+    let mut envelope = MessageEnvelope::new(
+        "alice".to_string(),
+        recipient_key,
+        sender_pub,
+        b"hello".to_vec(),
+        3600,
+    );
+    envelope.sign(&sender_priv);
+    client.send(&envelope).await.unwrap();
+
+    let messages = client.fetch_verified(&recipient_priv).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].payload, b"hello".to_vec());
+    assert!(client.fetch(&recipient_priv).await.unwrap().is_empty());
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_resent_envelope_is_accepted_once_and_conflicts_are_rejected() {
+    let (addr, cert, shutdown) = start_relay();
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .in_memory_outbox()
+        .connect()
+        .await
+        .unwrap();
+    assert!(client.capabilities().unwrap().supports(Features::IDEMPOTENT_SEND));
+
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let envelope = signed_for(recipient_key, b"once");
+    assert_eq!(client.send(&envelope).await.unwrap(), SendStatus::Delivered);
+    assert_eq!(client.send(&envelope).await.unwrap(), SendStatus::Delivered);
+    assert_eq!(client.fetch_verified(&recipient_priv).await.unwrap().len(), 1);
+
+    // Still recognised after delivery, so a late retry is not redelivered.
+    assert_eq!(client.send(&envelope).await.unwrap(), SendStatus::Delivered);
+    assert!(client.fetch(&recipient_priv).await.unwrap().is_empty());
+
+    let mut reused = signed_for(recipient_key, b"twice");
+    reused.msg_id = envelope.msg_id;
+    let (sender_pub, sender_priv) = gen_keypair();
+    reused.sender_key = sender_pub;
+    reused.sign(&sender_priv);
+    let err = client.send(&reused).await.unwrap_err();
+    assert_eq!(err, QightError::MessageConflict);
+    assert_eq!(client.outbox().unwrap().dead_letters().unwrap().len(), 1);
+    assert!(client.fetch(&recipient_priv).await.unwrap().is_empty());
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_content_addressed_envelopes_are_delivered_once() {
+    let (addr, cert, shutdown) = start_relay();
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let (sender_pub, sender_priv) = gen_keypair();
+    let content_addressed = |ttl| {
+        let mut envelope = MessageEnvelope::new_content_addressed(
+            "alice".to_string(),
+            recipient_key,
+            sender_pub,
+            b"ping".to_vec(),
+            ttl,
+        );
+        envelope.sign(&sender_priv);
+        envelope
+    };
+    let first = content_addressed(3600);
+    let again = content_addressed(7200);
+    assert!(first.has_content_id());
+    assert_eq!(first.msg_id, again.msg_id);
+    assert_ne!(first.digest(), again.digest());
+
+    assert_eq!(client.send(&first).await.unwrap(), SendStatus::Delivered);
+    assert_eq!(client.send(&again).await.unwrap(), SendStatus::Delivered);
+    let fetched = client.fetch_verified(&recipient_priv).await.unwrap();
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0].msg_id, first.msg_id);
+    assert_eq!(fetched[0].ttl, 3600);
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_senders_query_and_watch_receipts() {
+    let (addr, cert, shutdown) = start_relay();
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    assert!(client.capabilities().unwrap().supports(Features::RECEIPTS));
+
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let (sender_pub, sender_priv) = gen_keypair();
+    let mut envelope =
+        MessageEnvelope::new("alice".to_string(), recipient_key, sender_pub, b"hi".to_vec(), 3600);
+    envelope.sign(&sender_priv);
+    let mut watch = client.watch_receipts(&sender_priv).await.unwrap();
+    assert_eq!(watch.sender_key(), &sender_pub);
+    let mut next_status = async || {
+        tokio::time::timeout(Duration::from_secs(5), watch.next())
+            .await
+            .unwrap()
+            .unwrap()
+    };
+
+    client.send(&envelope).await.unwrap();
+    assert_eq!(next_status().await.status, ReceiptStatus::Stored);
+    let stored = client.receipts(&sender_priv, &envelope.msg_id).await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].status, ReceiptStatus::Stored);
+
+    // Only the sender may see how its message fared.
+    let (_, stranger_priv) = gen_keypair();
+    assert!(client.receipts(&stranger_priv, &envelope.msg_id).await.unwrap().is_empty());
+
+    let fetched = client.fetch_verified(&recipient_priv).await.unwrap();
+    // Pushes travel on separate streams and may overtake each other.
+    let mut delivery = vec![next_status().await.status, next_status().await.status];
+    delivery.sort();
+    assert_eq!(delivery, [ReceiptStatus::Delivered, ReceiptStatus::Acked]);
+
+    client.send_read_receipt(&recipient_priv, &fetched[0]).await.unwrap();
+    let read = next_status().await;
+    assert_eq!(read.status, ReceiptStatus::Read);
+    assert_eq!(read.read_receipt().unwrap().sender_key, recipient_key);
+
+    let statuses: Vec<_> = client
+        .receipts(&sender_priv, &envelope.msg_id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|receipt| receipt.status)
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ReceiptStatus::Stored,
+            ReceiptStatus::Delivered,
+            ReceiptStatus::Acked,
+            ReceiptStatus::Read
+        ]
+    );
+
+    // The read receipt also reaches the sender as an ordinary message.
+    let inbox = client.fetch_verified(&sender_priv).await.unwrap();
+    assert_eq!(inbox[0].read_receipt().unwrap().msg_id, envelope.msg_id);
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_untrusted_relay_leaves_client_offline() {
+    let (addr, cert, shutdown) = start_relay();
+
+    let wrong_name = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .server_name("relay.example.com")
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    let (_, recipient_priv) = gen_keypair();
+    let err = wrong_name.fetch(&recipient_priv).await.unwrap_err();
+    assert_eq!(err, QightError::NotConnected);
+    assert_eq!(err.kind(), ErrorKind::Transport);
+
+    let system_only = RelayClient::builder(addr)
+        .trust(TrustRoots::System)
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    assert!(system_only.fetch(&recipient_priv).await.is_err());
+
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_outbox_replays_signed_envelopes_on_connect() {
+    let (addr, cert, shutdown) = start_relay();
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let envelope = signed_for(recipient_key, b"queued while offline");
+
+    let outbox = Outbox::in_memory().unwrap();
+    outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .outbox(outbox.clone())
+        .connect()
+        .await
+        .unwrap();
+    assert!(outbox.pending().unwrap().is_empty());
+
+    let messages = client.fetch_verified(&recipient_priv).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].msg_id, envelope.msg_id);
+    assert_eq!(messages[0].signature, envelope.signature);
+
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_outbox_dead_letters_rejected_envelopes() {
+    let (addr, cert, shutdown) = start_relay();
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let mut tampered = signed_for(recipient_key, b"original");
+    tampered.payload = b"tampered".to_vec();
+
+    let outbox = Outbox::in_memory().unwrap();
+    outbox.enqueue(&tampered, tampered.timestamp).unwrap();
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .outbox(outbox.clone())
+        .connect()
+        .await
+        .unwrap();
+
+    let dead = outbox.dead_letters().unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].state, OutboxState::DeadLetter);
+    assert_eq!(dead[0].attempts, 1);
+    assert_eq!(dead[0].last_error.as_deref(), Some("Invalid signature"));
+    assert!(outbox.pending().unwrap().is_empty());
+
+    // A direct send of a rejected envelope reports the typed rejection as well.
+    let err = client.send(&tampered).await.unwrap_err();
+    assert_eq!(err, QightError::InvalidSignature);
+    assert!(client.fetch(&recipient_priv).await.unwrap().is_empty());
+
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_offline_send_is_queued_and_replayed() {
+    let (addr, cert, shutdown) = start_relay();
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let envelope = signed_for(recipient_key, b"queued while offline");
+    let outbox = Outbox::in_memory().unwrap();
+
+    // The certificate does not match this name, so the client stays offline.
+    let offline = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert.clone()))
+        .server_name("relay.example.com")
+        .outbox(outbox.clone())
+        .reconnect(false)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(offline.state(), ConnectionState::Disconnected);
+    assert_eq!(offline.send(&envelope).await.unwrap(), SendStatus::Queued);
+    assert_eq!(outbox.pending().unwrap().len(), 1);
+
+    let online = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .outbox(outbox.clone())
+        .connect()
+        .await
+        .unwrap();
+    assert!(online.is_connected());
+    assert!(outbox.pending().unwrap().is_empty());
+
+    let messages = online.fetch_verified(&recipient_priv).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].payload, b"queued while offline".to_vec());
+
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_client_reconnects_and_drains_outbox() {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let cert = certified.cert.der().to_vec();
+    let key = certified.signing_key.serialize_der();
+    let relay = |addr: SocketAddr| {
+        let server = RelayServer::builder()
+            .bind(addr)
+            .certificate(cert.clone(), key.clone())
+            .in_memory()
+            .mdns(false)
+            .build()
+            .unwrap();
+        let addr = server.local_addr();
+        let shutdown = server.shutdown_handle();
+        let task = tokio::spawn(server.run());
+        (addr, shutdown, task)
+    };
+
+    let (addr, shutdown, task) = relay("127.0.0.1:0".parse().unwrap());
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert.clone()))
+        .in_memory_outbox()
+        .reconnect_delay(Duration::from_millis(50), Duration::from_millis(200))
+        .connect()
+        .await
+        .unwrap();
+    let mut states = client.state_changes();
+    assert!(client.is_connected());
+
+    shutdown.shutdown();
+    task.await.unwrap().unwrap();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        states.wait_for(|state| *state != ConnectionState::Connected),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let envelope = signed_for(recipient_key, b"sent during outage");
+    assert_eq!(client.send(&envelope).await.unwrap(), SendStatus::Queued);
+
+    let (_, shutdown, _task) = relay(addr);
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        states.wait_for(|state| *state == ConnectionState::Connected),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    // The queue is drained right after reconnecting.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !client.outbox().unwrap().pending().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let messages = client.fetch_verified(&recipient_priv).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].msg_id, envelope.msg_id);
+
+    client.close(None).await;
+    assert_eq!(client.state(), ConnectionState::Closed);
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_subscribe_receives_pushed_messages() {
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .build()
+        .unwrap();
+    let addr = server.local_addr();
+    let cert = server.certificate().to_vec();
+    let store = server.store().clone();
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+
+    let connect = || {
+        RelayClient::builder(addr)
+            .trust(TrustRoots::Der(cert.clone()))
+            .no_outbox()
+            .connect()
+    };
+    let sender = connect().await.unwrap();
+    let receiver = connect().await.unwrap();
+
+    let (recipient_key, recipient_priv) = gen_keypair();
+    let mut subscription = receiver.subscribe(&recipient_priv).await.unwrap();
+    assert_eq!(subscription.recipient(), &recipient_key);
+
+    let (other_key, _) = gen_keypair();
+    sender.send(&signed_for(other_key, b"not for us")).await.unwrap();
+    let envelope = signed_for(recipient_key, b"pushed");
+    sender.send(&envelope).await.unwrap();
+
+    let pushed = tokio::time::timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pushed.msg_id, envelope.msg_id);
+    assert_eq!(pushed.payload, b"pushed".to_vec());
+
+    // Delivered pushes are acknowledged, leaving only the other recipient's message.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while store.count().unwrap() != 1 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    receiver.close(None).await;
+    assert!(subscription.next().await.is_none());
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_hello_negotiates_capabilities_and_identity() {
+    let (_, identity_secret) = gen_keypair();
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .max_ttl_secs(60)
+        .identity_key(identity_secret)
+        .build()
+        .unwrap();
+    let (addr, identity) = (server.local_addr(), server.identity_key());
+    let cert = server.certificate().to_vec();
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert.clone()))
+        .relay_key(identity)
+        .require_features(Features::PUSH | Features::ACK)
+        .in_memory_outbox()
+        .connect()
+        .await
+        .unwrap();
+    let capabilities = client.capabilities().unwrap();
+    assert_eq!(capabilities.version, PROTOCOL_VERSION);
+    assert_eq!(capabilities.relay_key, identity);
+    assert_eq!(capabilities.max_ttl, 60);
+    assert!(capabilities.supports(Features::PUSH | Features::ACK | Features::ENCRYPTION));
+
+    // Over-long TTLs are refused before they reach the outbox.
+    let (recipient, _) = gen_keypair();
+    let err = client.send(&signed_for(recipient, b"too long")).await.unwrap_err();
+    assert_eq!(err, QightError::TtlOutOfRange);
+    assert!(client.outbox().unwrap().pending().unwrap().is_empty());
+    client.close(None).await;
+
+    let (other_identity, _) = gen_keypair();
+    let pinned = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert.clone()))
+        .relay_key(other_identity)
+        .no_outbox()
+        .connect()
+        .await;
+    let err = pinned.err().expect("relay with a different identity must be refused");
+    assert_eq!(err, QightError::RelayIdentityMismatch);
+
+    let demanding = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .require_features(Features::COMPRESSION)
+        .no_outbox()
+        .connect()
+        .await;
+    let err = demanding.err().expect("relay without a required feature must be refused");
+    assert_eq!(err, QightError::UnsupportedFeature);
+
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_relay_dead_letters_ttl_above_limit() {
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .max_ttl_secs(60)
+        .build()
+        .unwrap();
+    let store = server.store().clone();
+    let (addr, cert) = (server.local_addr(), server.certificate().to_vec());
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+
+    // Queued before the limits were known, so only the relay can refuse it.
+    let outbox = Outbox::in_memory().unwrap();
+    let (recipient, _) = gen_keypair();
+    let envelope = signed_for(recipient, b"queued");
+    outbox.enqueue(&envelope, envelope.timestamp).unwrap();
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .outbox(outbox.clone())
+        .connect()
+        .await
+        .unwrap();
+
+    let dead = outbox.dead_letters().unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].last_error.as_deref(), Some("ttl outside 1..=60 seconds"));
+    assert_eq!(store.count().unwrap(), 0);
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_relay_validation_rejections_are_typed() {
+    let (addr, cert, shutdown) = start_relay();
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    let (recipient, recipient_priv) = gen_keypair();
+    let (sender_pub, sender_priv) = gen_keypair();
+    let signed = |edit: &dyn Fn(&mut MessageEnvelope)| {
+        let mut envelope =
+            MessageEnvelope::new("alice".to_string(), recipient, sender_pub, b"hi".to_vec(), 60);
+        edit(&mut envelope);
+        envelope.sign(&sender_priv);
+        envelope
+    };
+
+    let future = signed(&|e| e.timestamp += 3600);
+    assert_eq!(client.send(&future).await.unwrap_err(), QightError::InvalidTimestamp);
+
+    let long_sender = signed(&|e| e.sender = "x".repeat(1024));
+    assert_eq!(client.send(&long_sender).await.unwrap_err(), QightError::FieldTooLong);
+
+    // Re-sending an accepted envelope is acknowledged without a second copy.
+    let envelope = signed(&|_| {});
+    assert_eq!(client.send(&envelope).await.unwrap(), SendStatus::Delivered);
+    assert_eq!(client.fetch(&recipient_priv).await.unwrap().len(), 1);
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_full_inbox_rejects_or_evicts_and_reports_stats() {
+    let start = |on_full| {
+        let server = RelayServer::builder()
+            .bind("127.0.0.1:0".parse().unwrap())
+            .self_signed()
+            .in_memory()
+            .mdns(false)
+            .quotas(QuotaPolicy {
+                inbox_max_messages: Some(2),
+                on_full,
+                ..QuotaPolicy::default()
+            })
+            .build()
+            .unwrap();
+        let (addr, cert) = (server.local_addr(), server.certificate().to_vec());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        (addr, cert, shutdown)
+    };
+    let (recipient, recipient_priv) = gen_keypair();
+
+    let (addr, cert, shutdown) = start(OverflowPolicy::Reject);
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    client.send(&signed_for(recipient, b"one")).await.unwrap();
+    client.send(&signed_for(recipient, b"two")).await.unwrap();
+    let err = client.send(&signed_for(recipient, b"three")).await.unwrap_err();
+    assert_eq!(err, QightError::QuotaExceeded);
+    assert!(err.is_transient());
+
+    let stats = client.stats().await.unwrap();
+    assert_eq!((stats.messages, stats.recipients, stats.quota_rejections), (2, 1, 1));
+    assert_eq!(stats.payload_bytes, 6);
+    assert_eq!(stats.inbox_max_messages, Some(2));
+    assert_eq!(stats.max_total_bytes, None);
+    assert!(!stats.evict_oldest);
+    client.close(None).await;
+    shutdown.shutdown();
+
+    let (addr, cert, shutdown) = start(OverflowPolicy::EvictOldest);
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    for payload in [&b"one"[..], b"two", b"three"] {
+        client.send(&signed_for(recipient, payload)).await.unwrap();
+    }
+    let stats = client.stats().await.unwrap();
+    assert_eq!((stats.messages, stats.quota_evictions), (2, 1));
+    let payloads: Vec<_> = client
+        .fetch(&recipient_priv)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|m| m.payload)
+        .collect();
+    assert_eq!(payloads, vec![b"two".to_vec(), b"three".to_vec()]);
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_rate_limited_requests_report_retry_after() {
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .rate_limits(RateLimitPolicy {
+            connections_per_ip: Some(RateLimit::new(0.01, 1)),
+            sends_per_connection: Some(RateLimit::new(0.5, 2)),
+            ..RateLimitPolicy::default()
+        })
+        .build()
+        .unwrap();
+    let (addr, cert) = (server.local_addr(), server.certificate().to_vec());
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert.clone()))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    let (recipient, _) = gen_keypair();
+    client.send(&signed_for(recipient, b"one")).await.unwrap();
+    client.send(&signed_for(recipient, b"two")).await.unwrap();
+    let err = client.send(&signed_for(recipient, b"three")).await.unwrap_err();
+    assert!(matches!(err, QightError::RateLimited { .. }));
+    assert!(err.is_transient());
+    let retry_after = err.retry_after().unwrap();
+    assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(2));
+
+    // The second connection from this address is refused until the bucket
+    // refills, leaving the client offline.
+    let refused = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .reconnect(false)
+        .connect()
+        .await
+        .unwrap();
+    assert!(!refused.is_connected());
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_sweeper_purges_unfetched_expired_messages() {
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .sweeper(SweeperConfig {
+            interval_secs: 1,
+            batch_size: 2,
+            ..SweeperConfig::default()
+        })
+        .build()
+        .unwrap();
+    let store = server.store().clone();
+    let (addr, cert) = (server.local_addr(), server.certificate().to_vec());
+    let (recipient, _) = gen_keypair();
+    for _ in 0..3 {
+        let mut stale = signed_for(recipient, b"stale");
+        stale.timestamp -= 7200;
+        store.insert(&stale).unwrap();
+    }
+    store.insert(&signed_for(recipient, b"fresh")).unwrap();
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // Nobody fetched the inbox, yet only the unexpired message is left.
+    let stats = client.stats().await.unwrap();
+    assert_eq!((stats.messages, stats.expired), (1, 3));
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+async fn http_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint_reports_relay_activity() {
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .in_memory()
+        .mdns(false)
+        .metrics(MetricsConfig {
+            enabled: true,
+            bind: "127.0.0.1:0".parse().unwrap(),
+        })
+        .build()
+        .unwrap();
+    let (addr, cert) = (server.local_addr(), server.certificate().to_vec());
+    let metrics_addr = server.metrics_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(server.run());
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    let (recipient, recipient_priv) = gen_keypair();
+    client.send(&signed_for(recipient, b"hello")).await.unwrap();
+    let mut forged = signed_for(recipient, b"original");
+    forged.payload = b"forged".to_vec();
+    assert!(client.send(&forged).await.is_err());
+    assert_eq!(client.fetch(&recipient_priv).await.unwrap().len(), 1);
+    client.send(&signed_for(recipient, b"pending")).await.unwrap();
+
+    let response = http_get(metrics_addr, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    for line in [
+        "qight_connections_active 1",
+        "qight_connections_total 1",
+        "qight_requests_total{command=\"hello\"} 1",
+        "qight_requests_total{command=\"send\"} 3",
+        "qight_requests_total{command=\"fetch\"} 1",
+        "qight_request_duration_seconds_count{command=\"send\"} 3",
+        "qight_signature_failures_total{check=\"envelope\"} 1",
+        "qight_signature_failures_total{check=\"challenge\"} 0",
+        "qight_stored_messages 1",
+        "qight_stored_bytes 7",
+        "# TYPE qight_outbox_drains_total counter",
+    ] {
+        assert!(response.contains(&format!("\n{}", line)), "missing {:?}", line);
+    }
+
+    assert!(http_get(metrics_addr, "/").await.starts_with("HTTP/1.1 404 Not Found"));
+
+    client.close(None).await;
+    shutdown.shutdown();
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_and_disconnects_clients() {
+    let db = std::env::temp_dir().join(format!("qight-shutdown-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&db);
+    let server = RelayServer::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .self_signed()
+        .database(&db)
+        .pool_size(2)
+        .drain_timeout(Duration::from_secs(2))
+        .mdns(false)
+        .build()
+        .unwrap();
+    let (addr, cert) = (server.local_addr(), server.certificate().to_vec());
+    let shutdown = server.shutdown_handle();
+    let relay = tokio::spawn(server.run());
+
+    let client = RelayClient::builder(addr)
+        .trust(TrustRoots::Der(cert))
+        .no_outbox()
+        .connect()
+        .await
+        .unwrap();
+    let (recipient, _) = gen_keypair();
+    for payload in [&b"one"[..], b"two"] {
+        client.send(&signed_for(recipient, payload)).await.unwrap();
+    }
+    let mut states = client.state_changes();
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), relay)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        states.wait_for(|state| *state != ConnectionState::Connected),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    // Everything accepted before the shutdown was persisted.
+    assert_eq!(SqliteStore::open(&db).unwrap().count().unwrap(), 2);
+
+    client.close(None).await;
+    let _ = std::fs::remove_file(&db);
+}